@@ -0,0 +1,255 @@
+//! Read-only mode, for terminals that anyone can walk up to
+//!
+//! Started with `--read-only`, or with `read_only: true` in the `cli` section of the config file,
+//! the CLI can watch every device but can't change any. Commands that would are turned away with
+//! a message saying how to get write access: `unlock` with the `write_pin` from the config file,
+//! or relaunching as root (which ignores the config setting, but not the flag).
+//!
+//! Who can change which devices is up to the permissions file (see [`crate::permissions`]).
+//!
+//! The daemon works this out for each client, from who's on the other end of the socket. A client
+//! sends the PIN it was unlocked with along with every command, and the daemon checks it each time.
+//!
+//! Wrong PINs are slowed down, twice as much each time, and after [`MAX_GUESSES`] in a row the user
+//! is turned away for [`LOCKOUT`], whatever PIN they give.
+use std::error::Error;
+use std::io::{stdin, stdout, Write};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::collections::BTreeMap;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use brewdrivers::model::Device;
+use log::{error, info, warn};
+use termion::input::TermRead;
+
+use crate::output::Failed;
+use crate::registry::Category;
+use crate::session::Session;
+use crate::{daemon, permissions};
+
+static READ_ONLY: AtomicBool = AtomicBool::new(false);
+
+/// The PIN this CLI was unlocked with, when it's the daemon's client
+static PIN: Mutex<Option<String>> = Mutex::new(None);
+
+/// How many wrong PINs in a row a user can give before they're locked out
+pub const MAX_GUESSES: u32 = 5;
+
+/// How long a user is locked out for after too many wrong PINs
+pub const LOCKOUT: Duration = Duration::from_secs(300);
+
+/// Wrong PINs in a row by user ID, and when the last one was
+static GUESSES: Mutex<BTreeMap<u32, (u32, Instant)>> = Mutex::new(BTreeMap::new());
+
+/// Whether the CLI is running as root. In the daemon, whether the client is.
+pub(crate) fn is_root() -> bool {
+    if let Some(client) = daemon::client() {
+        return client.root;
+    }
+    uids().is_some_and(|(_, effective)| effective == 0)
+}
+
+/// This process's real and effective user IDs, from `/proc/self/status`
+pub(crate) fn uids() -> Option<(u32, u32)> {
+    let status = std::fs::read_to_string("/proc/self/status").ok()?;
+    // `Uid:` is followed by the real, effective, saved and filesystem user IDs
+    let mut ids = status.lines().find(|line| line.starts_with("Uid:"))?.split_whitespace().skip(1).map(|id| id.parse().ok());
+    Some((ids.next()??, ids.next()??))
+}
+
+/// The name of a user ID, from `/etc/passwd`
+pub(crate) fn username(uid: u32) -> String {
+    std::fs::read_to_string("/etc/passwd")
+        .unwrap_or_default()
+        .lines()
+        .map(|line| line.split(':').collect::<Vec<_>>())
+        .find(|fields| fields.get(2) == Some(&uid.to_string().as_str()))
+        .map(|fields| fields[0].to_string())
+        .unwrap_or_else(|| uid.to_string())
+}
+
+/// Starts in read-only mode if the flag was given, or the config file asks for it and the CLI isn't running as root
+pub fn init(flag: bool, config: bool) {
+    READ_ONLY.store(flag || (config && !is_root()), Ordering::Relaxed);
+}
+
+/// Whether the CLI is read-only. In the daemon, whether the client is.
+pub fn read_only() -> bool {
+    match daemon::client() {
+        Some(client) => client.read_only,
+        None => READ_ONLY.load(Ordering::Relaxed),
+    }
+}
+
+/// The PIN to send the daemon with each command
+pub fn pin() -> Option<String> {
+    PIN.lock().unwrap().clone()
+}
+
+/// An error for `what` if the CLI is read-only. `what` is the thing being turned away, like `` `wsrelay0 On` ``.
+pub fn check_write(what: &str) -> Result<(), String> {
+    if read_only() {
+        return Err(format!(
+            "{} would change a device, and this terminal is read-only. Use `unlock` to switch to write mode",
+            what
+        ));
+    }
+    Ok(())
+}
+
+/// Checks that `what`, a `category` command on `device`, is allowed: that it isn't a change on a
+/// read-only terminal, and that the permissions file allows it
+pub fn check(device: &Device, category: Category, what: &str) -> Result<(), String> {
+    if category != Category::Read {
+        check_write(what)?;
+    }
+    permissions::check(device, category, what)
+}
+
+/// Whether two PINs are the same, taking as long to say no whichever character is wrong
+fn same_pin(given: &[u8], expected: &[u8]) -> bool {
+    let mut differ = given.len() ^ expected.len();
+    for i in 0..given.len().max(expected.len()) {
+        differ |= (given.get(i).copied().unwrap_or(0) ^ expected.get(i).copied().unwrap_or(0)) as usize;
+    }
+    differ == 0
+}
+
+/// Checks a PIN for write mode, given by the user with the ID `uid`. A wrong one is only said to be
+/// wrong after a wait that doubles with each one in a row. `Err` if the user is locked out.
+pub async fn check_pin(uid: u32, pin: &str, expected: &str) -> Result<bool, String> {
+    let wrong = {
+        let mut guesses = GUESSES.lock().unwrap_or_else(|e| e.into_inner());
+        let (wrong, last) = match guesses.get(&uid) {
+            Some((wrong, last)) if last.elapsed() < LOCKOUT => (*wrong, *last),
+            _ => (0, Instant::now()),
+        };
+        if wrong >= MAX_GUESSES {
+            let left = LOCKOUT.saturating_sub(last.elapsed());
+            return Err(format!("Too many wrong PINs. Try again in {} seconds", left.as_secs() + 1));
+        }
+        if same_pin(pin.as_bytes(), expected.as_bytes()) {
+            guesses.remove(&uid);
+            return Ok(true);
+        }
+        guesses.insert(uid, (wrong + 1, Instant::now()));
+        wrong + 1
+    };
+    warn!("Wrong PIN for write mode (user {}, {} in a row)", username(uid), wrong);
+    tokio::time::sleep(Duration::from_secs(1 << (wrong - 1))).await;
+    Ok(false)
+}
+
+/// Reads the PIN without showing it, on a thread of its own since it waits for the brewer
+async fn read_pin() -> Option<String> {
+    tokio::task::spawn_blocking(|| {
+        print!("PIN: ");
+        stdout().flush().ok();
+        let pin = stdin().read_passwd(&mut stdout()).ok().flatten();
+        println!();
+        pin
+    })
+    .await
+    .ok()
+    .flatten()
+}
+
+/// The `unlock` shell command. Switches to write mode with the PIN from the config file.
+///
+/// A client of the daemon reads the PIN and sends it to the daemon, which checks it when the
+/// request comes in (see [`check_pin`]) and then runs this.
+pub async fn unlock(session: &mut Session, _: Vec<String>) -> Result<(), Box<dyn Error>> {
+    let client = daemon::client();
+    let locked = match &client {
+        Some(client) => client.locked,
+        None => read_only(),
+    };
+    if !locked {
+        info!("Already in write mode");
+        return Ok(());
+    }
+
+    if client.is_none() && daemon::socket().is_some() {
+        let pin = read_pin().await;
+        *PIN.lock().unwrap() = pin;
+        if daemon::send(vec!["unlock".to_string()]).await != 0 {
+            *PIN.lock().unwrap() = None;
+            return Err(Failed.into());
+        }
+        READ_ONLY.store(false, Ordering::Relaxed);
+        return Ok(());
+    }
+
+    let expected = match &session.config.write_pin {
+        Some(pin) => pin,
+        None => {
+            error!("No `write_pin` is set in the config file, so write mode can't be unlocked here. Relaunch the CLI without `--read-only`, or as root");
+            return Err(Failed.into());
+        }
+    };
+
+    let right = match &client {
+        Some(client) => match &client.refused {
+            Some(refused) => Err(refused.clone()),
+            None => Ok(!client.read_only),
+        },
+        None => match read_pin().await {
+            Some(pin) => check_pin(uids().map_or(0, |(real, _)| real), &pin, expected).await,
+            None => Ok(false),
+        },
+    };
+    match right {
+        Ok(true) => {
+            // A client's PIN only unlocks the client, and it sends it with every command
+            if client.is_none() {
+                READ_ONLY.store(false, Ordering::Relaxed);
+            }
+            info!("Write mode. Use `lock` to go back to read-only");
+            Ok(())
+        }
+        Ok(false) => {
+            error!("Wrong PIN");
+            Err(Failed.into())
+        }
+        Err(e) => {
+            error!("{}", e);
+            Err(Failed.into())
+        }
+    }
+}
+
+/// The `lock` shell command. Goes back to read-only.
+pub fn lock(_: &mut Session, _: Vec<String>) -> Result<(), Box<dyn Error>> {
+    READ_ONLY.store(true, Ordering::Relaxed);
+    *PIN.lock().unwrap() = None;
+    info!("Read-only. Use `unlock` to switch back to write mode");
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn pins_are_compared_whole() {
+        assert!(same_pin(b"1234", b"1234"));
+        assert!(!same_pin(b"1235", b"1234"));
+        assert!(!same_pin(b"123", b"1234"));
+        assert!(!same_pin(b"12340", b"1234"));
+        assert!(!same_pin(b"", b"1234"));
+    }
+
+    #[tokio::test]
+    async fn too_many_wrong_pins_lock_the_user_out() {
+        // User IDs of their own, so the tests don't share guesses
+        assert_eq!(check_pin(4_000_001, "1234", "1234").await, Ok(true));
+
+        GUESSES.lock().unwrap().insert(4_000_002, (MAX_GUESSES, Instant::now()));
+        assert!(check_pin(4_000_002, "1234", "1234").await.is_err(), "the right PIN shouldn't get past a lockout");
+
+        // Once the lockout's over, the user starts again
+        GUESSES.lock().unwrap().insert(4_000_003, (MAX_GUESSES, Instant::now() - LOCKOUT));
+        assert_eq!(check_pin(4_000_003, "1234", "1234").await, Ok(true));
+    }
+}
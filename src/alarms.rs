@@ -0,0 +1,537 @@
+//! Threshold alarms
+//!
+//! Alarms are checked by a background poller that runs for as long as the shell is open. When an
+//! alarm fires the terminal bell rings, the event is logged, the device is highlighted in the
+//! dashboard, and the configured `alarm_hook` is run. The bell keeps ringing on every poll until
+//! the alarm is acknowledged or its condition clears.
+//!
+//! ```text
+//! alarm add mash_cn pv < 148 for 2m
+//! alarm add wsrelay3 unreachable for 30s
+//! ```
+use std::error::Error;
+use std::fmt;
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::time::{Duration, Instant};
+
+use brewdrivers::controllers::*;
+use brewdrivers::model::{Device, RTU};
+use brewdrivers::state::DeviceState;
+use chrono::{DateTime, Local};
+use log::{error, info};
+
+use crate::config::CliConfig;
+use crate::output::Failed;
+use crate::session::Session;
+use crate::{boards, duration, hooks, resolve, tables};
+
+/// The value of a device that an alarm watches
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Metric {
+    /// Process value (CN7500)
+    Pv,
+    /// Setpoint value (CN7500)
+    Sv,
+    /// Relay state. On is 1, Off is 0
+    State,
+}
+
+impl Metric {
+    /// Whether a device with this controller has the value. Only CN7500s have a PV and SV, and only
+    /// relay boards have a relay state.
+    fn read_from(&self, controller: &Controller) -> bool {
+        match self {
+            Metric::Pv | Metric::Sv => *controller == Controller::CN7500,
+            Metric::State => *controller != Controller::CN7500,
+        }
+    }
+}
+
+impl fmt::Display for Metric {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Metric::Pv => write!(f, "pv"),
+            Metric::Sv => write!(f, "sv"),
+            Metric::State => write!(f, "state"),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Comparison {
+    Lt,
+    Le,
+    Gt,
+    Ge,
+    Eq,
+    Ne,
+}
+
+impl Comparison {
+    fn parse(input: &str) -> Option<Self> {
+        match input {
+            "<" => Some(Self::Lt),
+            "<=" => Some(Self::Le),
+            ">" => Some(Self::Gt),
+            ">=" => Some(Self::Ge),
+            "==" | "=" => Some(Self::Eq),
+            "!=" => Some(Self::Ne),
+            _ => None,
+        }
+    }
+
+    fn holds(&self, lhs: f64, rhs: f64) -> bool {
+        match self {
+            Self::Lt => lhs < rhs,
+            Self::Le => lhs <= rhs,
+            Self::Gt => lhs > rhs,
+            Self::Ge => lhs >= rhs,
+            Self::Eq => (lhs - rhs).abs() < f64::EPSILON,
+            Self::Ne => (lhs - rhs).abs() >= f64::EPSILON,
+        }
+    }
+}
+
+impl fmt::Display for Comparison {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let op = match self {
+            Self::Lt => "<",
+            Self::Le => "<=",
+            Self::Gt => ">",
+            Self::Ge => ">=",
+            Self::Eq => "==",
+            Self::Ne => "!=",
+        };
+        write!(f, "{}", op)
+    }
+}
+
+/// What has to be true for an alarm to fire
+#[derive(Debug, Clone, PartialEq)]
+pub enum Condition {
+    /// A device value crosses a threshold, like `pv < 148`
+    Threshold { metric: Metric, cmp: Comparison, value: f64 },
+    /// The device doesn't answer
+    Unreachable,
+}
+
+impl Condition {
+    /// Parses a condition from shell arguments, like `["pv", "<", "148"]` or `["unreachable"]`
+    pub fn parse(args: &[String]) -> Result<Self, String> {
+        let metric = match args.first().map(|s| s.as_str()) {
+            Some("unreachable") if args.len() == 1 => return Ok(Condition::Unreachable),
+            Some("pv") => Metric::Pv,
+            Some("sv") => Metric::Sv,
+            Some("state") => Metric::State,
+            Some(other) => return Err(format!("unknown alarm condition `{}`, expected pv, sv, state, or unreachable", other)),
+            None => return Err("missing alarm condition".to_string()),
+        };
+
+        let (cmp, value) = match args {
+            [_, cmp, value] => (cmp, value),
+            _ => return Err(format!("expected `{} [<|<=|>|>=|==|!=] [value]`", metric)),
+        };
+
+        let cmp = Comparison::parse(cmp).ok_or_else(|| format!("unknown comparison `{}`", cmp))?;
+
+        let value = match metric {
+            Metric::State => match value.parse::<BinaryState>() {
+                Ok(BinaryState::On) => 1.0,
+                Ok(BinaryState::Off) => 0.0,
+                Err(e) => return Err(format!("{}", e)),
+            },
+            _ => value.parse::<f64>().map_err(|e| format!("couldn't parse `{}` as a number: {}", value, e))?,
+        };
+
+        Ok(Condition::Threshold { metric, cmp, value })
+    }
+
+    /// An error if `device` doesn't have the value this condition watches, so the alarm could never go off
+    pub fn check_device(&self, device: &Device) -> Result<(), String> {
+        match self {
+            Condition::Threshold { metric, .. } if !metric.read_from(device.conn.controller()) => Err(format!(
+                "`{}` is a {}, which has no {} to watch. Use {}",
+                device.id,
+                device.conn.controller(),
+                metric,
+                if *device.conn.controller() == Controller::CN7500 { "pv or sv" } else { "state" }
+            )),
+            _ => Ok(()),
+        }
+    }
+
+    /// Checks a reading against this condition. `None` means the device couldn't be reached.
+    ///
+    /// Returns the value that tripped the condition, for logging
+    fn check(&self, reading: Option<&DeviceState>) -> Option<String> {
+        match (self, reading) {
+            (Condition::Unreachable, None) => Some("unreachable".to_string()),
+            (Condition::Unreachable, Some(_)) => None,
+            // Threshold alarms can't be evaluated without a reading. That's what `unreachable` is for.
+            (Condition::Threshold { .. }, None) => None,
+            (Condition::Threshold { metric, cmp, value }, Some(state)) => {
+                let actual = match metric {
+                    Metric::Pv => state.pv?,
+                    Metric::Sv => state.sv?,
+                    Metric::State => match state.relay_state? {
+                        BinaryState::On => 1.0,
+                        BinaryState::Off => 0.0,
+                    },
+                };
+
+                if cmp.holds(actual, *value) {
+                    Some(match metric {
+                        Metric::State => format!("{}", state.relay_state?),
+                        _ => format!("{}", actual),
+                    })
+                } else {
+                    None
+                }
+            }
+        }
+    }
+}
+
+impl fmt::Display for Condition {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Condition::Unreachable => write!(f, "unreachable"),
+            Condition::Threshold { metric: Metric::State, cmp, value } => {
+                write!(f, "state {} {}", cmp, BinaryState::from(*value > 0.5))
+            }
+            Condition::Threshold { metric, cmp, value } => write!(f, "{} {} {}", metric, cmp, value),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Status {
+    /// The condition is false
+    Normal,
+    /// The condition is true, but hasn't been true for long enough
+    Pending(Instant),
+    /// The alarm has gone off
+    Firing { since: DateTime<Local>, acked: bool },
+}
+
+impl fmt::Display for Status {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Status::Normal => write!(f, "ok"),
+            Status::Pending(since) => write!(f, "pending ({})", duration::format(since.elapsed())),
+            Status::Firing { since, acked: false } => write!(f, "FIRING since {}", since.format("%H:%M:%S")),
+            Status::Firing { since, acked: true } => write!(f, "firing since {} (acked)", since.format("%H:%M:%S")),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Alarm {
+    pub id: usize,
+    pub device_id: String,
+    pub condition: Condition,
+    /// How long the condition has to hold before the alarm fires
+    pub hold: Duration,
+    pub status: Status,
+}
+
+impl Alarm {
+    pub fn is_firing(&self) -> bool {
+        matches!(self.status, Status::Firing { .. })
+    }
+}
+
+/// Something the poller needs to act on after evaluating an alarm
+enum Event {
+    Fired(Alarm, String),
+    StillFiring,
+    Cleared(Alarm),
+}
+
+#[derive(Debug, Default)]
+struct AlarmBook {
+    next_id: usize,
+    alarms: Vec<Alarm>,
+}
+
+/// The set of configured alarms. Cheap to clone, every clone shares the same alarms.
+#[derive(Debug, Clone, Default)]
+pub struct Alarms(Arc<Mutex<AlarmBook>>);
+
+impl Alarms {
+    fn book(&self) -> MutexGuard<'_, AlarmBook> {
+        // A poisoned lock only means another thread panicked while holding it, the alarms are still usable
+        self.0.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    /// Adds an alarm, returning its ID
+    pub fn add(&self, device_id: &str, condition: Condition, hold: Duration) -> usize {
+        let mut book = self.book();
+        book.next_id += 1;
+        let id = book.next_id;
+        book.alarms.push(Alarm {
+            id,
+            device_id: device_id.to_string(),
+            condition,
+            hold,
+            status: Status::Normal,
+        });
+        id
+    }
+
+    /// A copy of every alarm, in the order they were added
+    pub fn list(&self) -> Vec<Alarm> {
+        self.book().alarms.clone()
+    }
+
+    /// Acknowledges a firing alarm, which silences the bell. `None` acknowledges every alarm.
+    ///
+    /// Returns the number of alarms acknowledged
+    pub fn ack(&self, id: Option<usize>) -> usize {
+        let mut count = 0;
+        for alarm in self.book().alarms.iter_mut().filter(|a| id.is_none() || id == Some(a.id)) {
+            if let Status::Firing { acked, .. } = &mut alarm.status {
+                *acked = true;
+                count += 1;
+            }
+        }
+        count
+    }
+
+    /// Removes an alarm. `None` removes every alarm.
+    ///
+    /// Returns the number of alarms removed
+    pub fn clear(&self, id: Option<usize>) -> usize {
+        let mut book = self.book();
+        let before = book.alarms.len();
+        book.alarms.retain(|a| id.is_some() && id != Some(a.id));
+        before - book.alarms.len()
+    }
+
+    /// IDs of devices that have an alarm going off
+    pub fn firing_devices(&self) -> Vec<String> {
+        self.book()
+            .alarms
+            .iter()
+            .filter(|a| a.is_firing())
+            .map(|a| a.device_id.clone())
+            .collect()
+    }
+
+    /// IDs of every device that has an alarm, without duplicates
+    fn watched_devices(&self) -> Vec<String> {
+        let mut ids: Vec<String> = self.book().alarms.iter().map(|a| a.device_id.clone()).collect();
+        ids.sort();
+        ids.dedup();
+        ids
+    }
+
+    /// Evaluates every alarm on a device against a new reading and updates their status
+    fn evaluate(&self, device_id: &str, reading: Option<&DeviceState>) -> Vec<Event> {
+        let mut events = vec![];
+        for alarm in self.book().alarms.iter_mut().filter(|a| a.device_id == device_id) {
+            match (alarm.condition.check(reading), &alarm.status) {
+                (Some(value), Status::Normal) | (Some(value), Status::Pending(_)) => {
+                    let since = match alarm.status {
+                        Status::Pending(since) => since,
+                        _ => Instant::now(),
+                    };
+
+                    if since.elapsed() >= alarm.hold {
+                        alarm.status = Status::Firing { since: Local::now(), acked: false };
+                        events.push(Event::Fired(alarm.clone(), value));
+                    } else {
+                        alarm.status = Status::Pending(since);
+                    }
+                }
+                (Some(_), Status::Firing { acked: false, .. }) => events.push(Event::StillFiring),
+                (Some(_), Status::Firing { acked: true, .. }) => {}
+                (None, Status::Firing { .. }) => {
+                    alarm.status = Status::Normal;
+                    events.push(Event::Cleared(alarm.clone()));
+                }
+                (None, _) => alarm.status = Status::Normal,
+            }
+        }
+        events
+    }
+}
+
+/// Starts the background task that checks alarms every `alarm_interval` seconds
+pub fn spawn_poller(alarms: Alarms, rtu: RTU, config: CliConfig) {
+    tokio::spawn(async move {
+        let interval = Duration::from_secs(config.alarm_interval.max(1));
+        loop {
+            tokio::time::sleep(interval).await;
+
+            let mut ring = false;
+            for device_id in alarms.watched_devices() {
+                let device = match rtu.devices.iter().find(|dev| dev.id == device_id) {
+                    Some(dev) => dev,
+                    None => continue,
+                };
+
+                let reading = boards::read(device).await.ok();
+                for event in alarms.evaluate(&device_id, reading.as_ref()) {
+                    match event {
+                        Event::Fired(alarm, value) => {
+                            ring = true;
+                            error!("ALARM #{}: {} {} (value: {})", alarm.id, alarm.device_id, alarm.condition, value);
+                            if let Some(hook) = &config.alarm_hook {
+                                hooks::run(hook, &[
+                                    ("NBC_ALARM_ID", alarm.id.to_string()),
+                                    ("NBC_ALARM_DEVICE", alarm.device_id.clone()),
+                                    ("NBC_ALARM_CONDITION", alarm.condition.to_string()),
+                                    ("NBC_ALARM_VALUE", value),
+                                ]);
+                            }
+                        }
+                        Event::StillFiring => ring = true,
+                        Event::Cleared(alarm) => info!("Alarm #{} cleared: {} {}", alarm.id, alarm.device_id, alarm.condition),
+                    }
+                }
+            }
+
+            if ring {
+                hooks::bell();
+            }
+        }
+    });
+}
+
+/// Parses `[alarmID|all]`
+fn parse_target(arg: Option<&String>) -> Result<Option<usize>, String> {
+    match arg.map(|s| s.as_str()) {
+        Some("all") => Ok(None),
+        Some(id) => id.parse::<usize>().map(Some).map_err(|_| format!("`{}` isn't an alarm ID", id)),
+        None => Err("provide an alarm ID or `all`".to_string()),
+    }
+}
+
+/// The `alarm` shell command
+pub fn command(session: &mut Session, args: Vec<String>) -> Result<(), Box<dyn Error>> {
+    let alarms = &session.alarms;
+
+    match args.get(1).map(|s| s.as_str()) {
+        None | Some("list") => outln!("{}", tables::alarms::render(&alarms.list())),
+        Some("add") => {
+            let device = match args.get(2).map(|token| resolve::device(&session.rtu, &session.config, token)) {
+                Some(Ok(device)) => device,
+                Some(Err(e)) => {
+                    error!("{}", e);
+                    return Err(Failed.into());
+                }
+                None => {
+                    error!("Usage: alarm add [device] [condition] (for [duration])");
+                    return Err(Failed.into());
+                }
+            };
+
+            // Split off the optional `for [duration]` at the end
+            let mut cond_args = &args[3..];
+            let mut hold = Duration::ZERO;
+            if let Some(pos) = cond_args.iter().position(|a| a == "for") {
+                hold = match cond_args.get(pos + 1).and_then(|d| duration::parse(d)) {
+                    Some(d) if pos + 2 == cond_args.len() => d,
+                    _ => {
+                        error!("`for` needs a single duration after it, like `for 2m` or `for 30s`");
+                        return Err(Failed.into());
+                    }
+                };
+                cond_args = &cond_args[..pos];
+            }
+
+            match Condition::parse(cond_args).and_then(|condition| condition.check_device(device).map(|_| condition)) {
+                Ok(condition) => {
+                    let id = alarms.add(&device.id, condition.clone(), hold);
+                    info!("Added alarm #{}: {} {} for {}", id, device.id, condition, duration::format(hold));
+                }
+                Err(e) => {
+                    error!("Couldn't add alarm: {}", e);
+                    return Err(Failed.into());
+                }
+            }
+        }
+        Some("ack") => match parse_target(args.get(2)) {
+            Ok(target) => info!("Acknowledged {} alarm(s)", alarms.ack(target)),
+            Err(e) => {
+                error!("{}", e);
+                return Err(Failed.into());
+            }
+        },
+        Some("clear") => match parse_target(args.get(2)) {
+            Ok(target) => info!("Removed {} alarm(s)", alarms.clear(target)),
+            Err(e) => {
+                error!("{}", e);
+                return Err(Failed.into());
+            }
+        },
+        Some(other) => {
+            error!("Unknown alarm command `{}`. Use list, add, ack, or clear", other);
+            return Err(Failed.into());
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn args(line: &str) -> Vec<String> {
+        line.split_whitespace().map(String::from).collect()
+    }
+
+    #[test]
+    fn parses_thresholds() {
+        assert_eq!(
+            Condition::parse(&args("pv < 148")),
+            Ok(Condition::Threshold { metric: Metric::Pv, cmp: Comparison::Lt, value: 148.0 })
+        );
+        assert_eq!(
+            Condition::parse(&args("sv >= 152.5")),
+            Ok(Condition::Threshold { metric: Metric::Sv, cmp: Comparison::Ge, value: 152.5 })
+        );
+        assert_eq!(
+            Condition::parse(&args("state == On")),
+            Ok(Condition::Threshold { metric: Metric::State, cmp: Comparison::Eq, value: 1.0 })
+        );
+        assert_eq!(
+            Condition::parse(&args("state != Off")),
+            Ok(Condition::Threshold { metric: Metric::State, cmp: Comparison::Ne, value: 0.0 })
+        );
+    }
+
+    #[test]
+    fn parses_unreachable() {
+        assert_eq!(Condition::parse(&args("unreachable")), Ok(Condition::Unreachable));
+        assert!(Condition::parse(&args("unreachable < 1")).is_err());
+    }
+
+    #[test]
+    fn rejects_bad_conditions() {
+        assert!(Condition::parse(&[]).is_err());
+        assert!(Condition::parse(&args("temp < 148")).is_err());
+        assert!(Condition::parse(&args("pv <")).is_err());
+        assert!(Condition::parse(&args("pv ~ 148")).is_err());
+        assert!(Condition::parse(&args("pv < hot")).is_err());
+        assert!(Condition::parse(&args("state == Maybe")).is_err());
+    }
+
+    #[test]
+    fn rejects_values_the_device_doesnt_have() {
+        let rtu: RTU = serde_yaml::from_str(include_str!("../tests/fixtures/rtu_conf.yaml")).unwrap();
+        let device = |controller: Controller| rtu.devices.iter().find(|device| *device.conn.controller() == controller).unwrap();
+        let (relay, cn7500) = (device(Controller::WaveshareV2), device(Controller::CN7500));
+
+        assert!(Condition::parse(&args("pv < 148")).unwrap().check_device(relay).is_err());
+        assert!(Condition::parse(&args("sv > 150")).unwrap().check_device(relay).is_err());
+        assert!(Condition::parse(&args("state == On")).unwrap().check_device(cn7500).is_err());
+
+        assert!(Condition::parse(&args("pv < 148")).unwrap().check_device(cn7500).is_ok());
+        assert!(Condition::parse(&args("state == On")).unwrap().check_device(relay).is_ok());
+        assert!(Condition::Unreachable.check_device(relay).is_ok());
+        assert!(Condition::Unreachable.check_device(cn7500).is_ok());
+    }
+}
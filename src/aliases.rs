@@ -0,0 +1,185 @@
+//! Aliases: one word for a few commands
+//!
+//! ```text
+//! alias mashon = mash_pump On; mash run
+//! ```
+//!
+//! Running `mashon` then runs `mash_pump On` and `mash run`, in order. Commands in an alias can
+//! be chained with `&&` and `||` too (see [`crate::repl::chain`]). Words after an alias are added
+//! to its last command. Aliases are per user, and saved to
+//! `~/.local/share/nbc_cli/aliases.json`, so they're there in the next shell and in `exec` mode.
+//! Commands and devices always come before an alias with the same name.
+use std::collections::BTreeMap;
+use std::error::Error;
+use std::fs;
+use std::path::PathBuf;
+use std::sync::{Mutex, MutexGuard};
+
+use log::{error, info, warn};
+
+use crate::output::Failed;
+use crate::session::Session;
+use crate::repl::{self, Chain};
+use crate::{registry, resolve};
+
+static ALIASES: Mutex<BTreeMap<String, String>> = Mutex::new(BTreeMap::new());
+
+fn aliases() -> MutexGuard<'static, BTreeMap<String, String>> {
+    // A poisoned lock only means another thread panicked while holding it, the aliases are still good
+    ALIASES.lock().unwrap_or_else(|e| e.into_inner())
+}
+
+/// Where the aliases are saved
+fn file() -> Option<PathBuf> {
+    std::env::var_os("HOME").map(|home| PathBuf::from(home).join(".local/share/nbc_cli/aliases.json"))
+}
+
+/// Reads the saved aliases
+pub fn load() {
+    let path = match file() {
+        Some(path) if path.exists() => path,
+        _ => return,
+    };
+    match fs::read_to_string(&path).map_err(|e| e.to_string()).and_then(|contents| serde_json::from_str(&contents).map_err(|e| e.to_string())) {
+        Ok(saved) => *aliases() = saved,
+        Err(e) => warn!("Couldn't read the aliases in `{}`: {}", path.display(), e),
+    }
+}
+
+fn save(aliases: &BTreeMap<String, String>) -> Result<(), String> {
+    let path = file().ok_or("there's no home directory to save them in")?;
+    if let Some(dir) = path.parent() {
+        fs::create_dir_all(dir).map_err(|e| format!("Couldn't create `{}`: {}", dir.display(), e))?;
+    }
+    let contents = serde_json::to_string_pretty(aliases).map_err(|e| e.to_string())?;
+    fs::write(&path, contents).map_err(|e| format!("Couldn't save the aliases to `{}`: {}", path.display(), e))
+}
+
+/// Every alias's name, for tab completion
+pub fn names() -> Vec<String> {
+    aliases().keys().cloned().collect()
+}
+
+/// The chain `line` stands for, if it starts with an alias. Words after the alias are added to the
+/// chain's last command. Aliases in the chain are left for the caller, who expands them as they
+/// run, but an alias that ends up running itself is an error. `taken` says whether a word is a
+/// command or device, which an alias can't override.
+pub fn expand(line: &[String], taken: &dyn Fn(&str) -> bool) -> Result<Option<Chain>, String> {
+    let aliases = aliases();
+    let (first, rest) = match line.split_first() {
+        Some((first, rest)) if !taken(first) && aliases.contains_key(first) => (first, rest),
+        _ => return Ok(None),
+    };
+
+    check_loops(first, &aliases, taken, &mut vec![])?;
+    let mut chain = repl::chain(&aliases[first]).map_err(|e| format!("The `{}` alias: {}", first, e))?;
+    if let Some((_, last)) = chain.last_mut() {
+        last.extend(rest.iter().cloned());
+    }
+    Ok(Some(chain))
+}
+
+fn check_loops(name: &str, aliases: &BTreeMap<String, String>, taken: &dyn Fn(&str) -> bool, expanding: &mut Vec<String>) -> Result<(), String> {
+    if expanding.iter().any(|outer| outer == name) {
+        return Err(format!("The `{}` alias runs itself: {} -> {}", name, expanding.join(" -> "), name));
+    }
+    expanding.push(name.to_string());
+
+    let chain = repl::chain(&aliases[name]).map_err(|e| format!("The `{}` alias: {}", name, e))?;
+    for (_, line) in chain {
+        match line.first() {
+            Some(first) if !taken(first) && aliases.contains_key(first) => check_loops(first, aliases, taken, expanding)?,
+            _ => {}
+        }
+    }
+
+    expanding.pop();
+    Ok(())
+}
+
+/// The `alias` shell command
+///
+/// ```text
+/// alias                          # every alias
+/// alias [name] = [commands]      # add an alias, or change one. Join commands with `;`, `&&` or `||`
+/// ```
+pub fn command(session: &mut Session, args: Vec<String>) -> Result<(), Box<dyn Error>> {
+    if args.len() == 1 {
+        let aliases = aliases();
+        if aliases.is_empty() {
+            info!("No aliases. Use `alias [name] = [commands]` to add one");
+        }
+        for (name, commands) in aliases.iter() {
+            outln!("{} = {}", name, commands);
+        }
+        return Ok(());
+    }
+
+    let (name, commands) = match args.get(2).map(String::as_str) {
+        // One word is the commands as they were typed, quoted to keep them together
+        Some("=") if args.len() == 4 => (&args[1], args[3].clone()),
+        Some("=") if args.len() > 4 => (&args[1], args[3..].iter().map(|word| repl::quote(word)).collect::<Vec<_>>().join(" ")),
+        _ => {
+            error!("Usage: alias [name] = [command](; [command]...)");
+            return Err(Failed.into());
+        }
+    };
+
+    let is_command = registry::is_general(name);
+    if is_command || resolve::device(&session.rtu, &session.config, name).is_ok() {
+        error!("`{}` is already a {}, so it can't be an alias", name, if is_command { "command" } else { "device" });
+        return Err(Failed.into());
+    }
+    if name.contains(|c: char| c.is_whitespace() || matches!(c, ';' | '&' | '|')) {
+        error!("An alias's name can't have spaces, `;`, `&` or `|` in it");
+        return Err(Failed.into());
+    }
+    if let Err(e) = repl::chain(&commands) {
+        error!("{}", e);
+        return Err(Failed.into());
+    }
+
+    // Another shell might have changed them since
+    load();
+    let mut aliases = aliases();
+    let replaced = match aliases.insert(name.clone(), commands.clone()) {
+        // Like an alias in `~/.nbc_clirc`, every time the shell starts
+        Some(old) if old == commands => return Ok(()),
+        old => old.is_some(),
+    };
+    match save(&aliases) {
+        Ok(_) if replaced => info!("Changed `{}`", name),
+        Ok(_) => info!("Added `{}`", name),
+        Err(e) => {
+            error!("{}. `{}` only lasts until the CLI exits", e, name);
+            return Err(Failed.into());
+        }
+    }
+    Ok(())
+}
+
+/// The `unalias` shell command, which removes an alias
+pub fn unalias(_: &mut Session, args: Vec<String>) -> Result<(), Box<dyn Error>> {
+    let name = match args.get(1) {
+        Some(name) if args.len() == 2 => name,
+        _ => {
+            error!("Usage: unalias [name]");
+            return Err(Failed.into());
+        }
+    };
+
+    load();
+    let mut aliases = aliases();
+    if aliases.remove(name).is_none() {
+        error!("There's no `{}` alias", name);
+        return Err(Failed.into());
+    }
+    match save(&aliases) {
+        Ok(_) => info!("Removed `{}`", name),
+        Err(e) => {
+            error!("{}", e);
+            return Err(Failed.into());
+        }
+    }
+    Ok(())
+}
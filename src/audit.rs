@@ -0,0 +1,290 @@
+//! The audit journal
+//!
+//! Every command that changes a device is recorded in an append-only journal, one line of JSON per
+//! change, so an incident can be pieced together afterwards:
+//!
+//! ```json
+//! {"time":"2026-10-19T14:02:11.518204-05:00","user":"brewer","mode":"shell","device":"mash_cn","command":"set_sv","previous":"152","new":"148.5","result":"ok"}
+//! ```
+//!
+//! `previous` is the state before the change, if it could be read. `result` is `ok`, or the error.
+//! The journal is [`DEFAULT_FILE`] unless `cli.audit_file` says otherwise. It's one file for every
+//! user, so an incident can be pieced together from one place. The `audit` command shows it,
+//! filtered by device and time.
+//!
+//! Lines are only ever appended, but that's up to the CLI. Anyone who can write to the file can
+//! change it.
+use std::error::Error;
+use std::fmt;
+use std::fs::{self, OpenOptions};
+use std::future::Future;
+use std::io::{self, Write};
+use std::path::PathBuf;
+use std::sync::OnceLock;
+
+use brewdrivers::model::Device;
+use chrono::{DateTime, Local, NaiveDate, NaiveDateTime, NaiveTime, SecondsFormat, TimeZone};
+use log::{error, info, warn};
+use serde::{Deserialize, Serialize};
+
+use crate::output::Failed;
+use crate::session::Session;
+use crate::{access, daemon, duration, resolve, tables};
+
+/// How many entries `audit` shows at most
+const SHOWN: usize = 50;
+
+/// How a command came to be run
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Mode {
+    /// Typed into the shell
+    Shell,
+    /// Run with `NBC_cli exec`
+    Exec,
+    /// Run by the CLI itself, like a timer's `then` command
+    Script,
+    /// Sent by another program
+    Api,
+}
+
+impl fmt::Display for Mode {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Mode::Shell => write!(f, "shell"),
+            Mode::Exec => write!(f, "exec"),
+            Mode::Script => write!(f, "script"),
+            Mode::Api => write!(f, "api"),
+        }
+    }
+}
+
+tokio::task_local! {
+    /// The mode of the commands running in this task, if it isn't the CLI's own mode
+    static MODE: Mode;
+}
+
+/// Where the journal is kept unless the config file says otherwise
+pub const DEFAULT_FILE: &str = "/var/log/nbc_cli/audit.jsonl";
+
+/// Where the journal is, and how the CLI was started
+static SETUP: OnceLock<(PathBuf, Mode)> = OnceLock::new();
+
+/// Sets where the journal is kept (the default if `None`), and the mode commands run in unless
+/// they're run with [`scoped`].
+pub fn init(file: Option<&str>, mode: Mode) {
+    let _ = SETUP.set((PathBuf::from(file.unwrap_or(DEFAULT_FILE)), mode));
+}
+
+fn path() -> PathBuf {
+    SETUP.get().map(|(path, _)| path.clone()).unwrap_or_else(|| PathBuf::from(DEFAULT_FILE))
+}
+
+/// The mode commands in the current task are running in. In the daemon, that's the client's mode.
+pub fn mode() -> Mode {
+    MODE.try_with(|mode| *mode)
+        .ok()
+        .or_else(|| daemon::client().map(|client| client.request.mode))
+        .unwrap_or_else(|| SETUP.get().map(|(_, mode)| *mode).unwrap_or(Mode::Shell))
+}
+
+/// Runs `f` with its device changes recorded as `mode`
+pub async fn scoped<F: Future>(mode: Mode, f: F) -> F::Output {
+    MODE.scope(mode, f).await
+}
+
+/// The person running the CLI, from the real user ID. Under `sudo`, that's the user who ran
+/// `sudo`, not root. In the daemon, it's the client's user.
+///
+/// The permissions file goes by this, so the environment is only trusted for `SUDO_USER`, and only
+/// when the CLI really is running as root. Anyone can set `USER`.
+pub fn user() -> String {
+    if let Some(client) = daemon::client() {
+        return client.user.clone();
+    }
+    match access::uids() {
+        Some((_, 0)) => match std::env::var("SUDO_USER") {
+            Ok(user) if !user.is_empty() => user,
+            _ => access::username(0),
+        },
+        Some((real, _)) => access::username(real),
+        None => "unknown".to_string(),
+    }
+}
+
+/// One change to one device
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Entry {
+    /// When, as RFC 3339
+    pub time: String,
+    pub user: String,
+    pub mode: Mode,
+    pub device: String,
+    /// The operation, like `set_relay` or `set_sv`
+    pub command: String,
+    /// The state before the change, if it could be read
+    pub previous: Option<String>,
+    /// The state that was asked for
+    pub new: String,
+    /// `ok`, or the error
+    pub result: String,
+}
+
+impl Entry {
+    fn time(&self) -> Option<DateTime<Local>> {
+        DateTime::parse_from_rfc3339(&self.time).ok().map(|time| time.with_timezone(&Local))
+    }
+}
+
+fn append(entry: &Entry) -> io::Result<()> {
+    let path = path();
+    if let Some(dir) = path.parent().filter(|dir| !dir.as_os_str().is_empty()) {
+        fs::create_dir_all(dir)?;
+    }
+    let mut line = serde_json::to_string(entry).map_err(io::Error::other)?;
+    line.push('\n');
+    // Opened to append (O_APPEND), with one write per line, so lines from two CLIs at once don't
+    // interleave or overwrite each other
+    OpenOptions::new().create(true).append(true).open(&path)?.write_all(line.as_bytes())
+}
+
+/// Records a change to a device. Failing to write the journal is logged, but doesn't stop anything.
+pub fn record<T, E: fmt::Display>(device: &Device, command: &str, previous: Option<String>, new: String, result: &Result<T, E>) {
+    let entry = Entry {
+        time: Local::now().to_rfc3339_opts(SecondsFormat::Micros, false),
+        user: user(),
+        mode: mode(),
+        device: device.id.clone(),
+        command: command.to_string(),
+        previous,
+        new,
+        result: match result {
+            Ok(_) => "ok".to_string(),
+            Err(e) => e.to_string(),
+        },
+    };
+    if let Err(e) = append(&entry) {
+        warn!("Couldn't write to the audit journal `{}`: {}", path().display(), e);
+    }
+}
+
+/// Reads the whole journal. Lines that can't be read are skipped with a warning.
+pub fn load() -> Result<Vec<Entry>, String> {
+    let path = path();
+    let contents = match fs::read_to_string(&path) {
+        Ok(contents) => contents,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(e) => return Err(format!("Couldn't read the audit journal `{}`: {}", path.display(), e)),
+    };
+
+    let mut entries = Vec::new();
+    for (i, line) in contents.lines().enumerate().filter(|(_, line)| !line.trim().is_empty()) {
+        match serde_json::from_str(line) {
+            Ok(entry) => entries.push(entry),
+            Err(e) => warn!("Skipping line {} of `{}`: {}", i + 1, path.display(), e),
+        }
+    }
+    Ok(entries)
+}
+
+/// Parses a point in time: a duration ago (`2h`), a time today (`14:30`), a date (`2026-10-19`),
+/// or a date and time (`2026-10-19T14:30`)
+fn parse_when(input: &str) -> Option<DateTime<Local>> {
+    if let Some(ago) = duration::parse(input) {
+        return Some(Local::now() - chrono::Duration::from_std(ago).ok()?);
+    }
+    let naive = if let Ok(time) = NaiveTime::parse_from_str(input, "%H:%M") {
+        Local::now().date_naive().and_time(time)
+    } else if let Ok(datetime) = NaiveDateTime::parse_from_str(input, "%Y-%m-%dT%H:%M") {
+        datetime
+    } else if let Ok(date) = NaiveDate::parse_from_str(input, "%Y-%m-%d") {
+        date.and_hms_opt(0, 0, 0)?
+    } else {
+        return DateTime::parse_from_rfc3339(input).ok().map(|time| time.with_timezone(&Local));
+    };
+    Local.from_local_datetime(&naive).earliest()
+}
+
+/// What to show from the journal
+#[derive(Debug, Default)]
+struct Query {
+    device: Option<String>,
+    since: Option<DateTime<Local>>,
+    until: Option<DateTime<Local>>,
+}
+
+impl Query {
+    fn parse(session: &Session, args: &[String]) -> Result<Self, String> {
+        let mut query = Query::default();
+        let mut args = args.iter();
+        while let Some(arg) = args.next() {
+            match arg.as_str() {
+                "since" | "until" => {
+                    let input = args.next().ok_or_else(|| format!("`{}` needs a time, like `2h` or `14:30`", arg))?;
+                    let when = parse_when(input).ok_or_else(|| {
+                        format!("`{}` isn't a time. Use a duration ago (`2h`), a time today (`14:30`), or a date (`2026-10-19` or `2026-10-19T14:30`)", input)
+                    })?;
+                    if arg == "since" {
+                        query.since = Some(when);
+                    } else {
+                        query.until = Some(when);
+                    }
+                }
+                device if query.device.is_none() => {
+                    // A device that's since been removed from the config can still be looked up by ID
+                    query.device = Some(match resolve::device(&session.rtu, &session.config, device) {
+                        Ok(device) => device.id.clone(),
+                        Err(_) => device.to_string(),
+                    });
+                }
+                other => return Err(format!("Unexpected `{}`. Use `audit [device] (since [time]) (until [time])`", other)),
+            }
+        }
+        Ok(query)
+    }
+
+    fn matches(&self, entry: &Entry) -> bool {
+        if self.device.as_ref().is_some_and(|device| *device != entry.device) {
+            return false;
+        }
+        match entry.time() {
+            Some(time) => self.since.is_none_or(|since| time >= since) && self.until.is_none_or(|until| time <= until),
+            None => self.since.is_none() && self.until.is_none(),
+        }
+    }
+}
+
+/// The `audit` shell command
+///
+/// ```text
+/// audit                          # the latest changes
+/// audit mash_cn since 2h         # changes to one device
+/// audit since 14:00 until 15:30  # changes in a time range
+/// ```
+pub fn command(session: &mut Session, args: Vec<String>) -> Result<(), Box<dyn Error>> {
+    let query = match Query::parse(session, &args[1..]) {
+        Ok(query) => query,
+        Err(e) => {
+            error!("{}", e);
+            return Err(Failed.into());
+        }
+    };
+    let entries: Vec<Entry> = match load() {
+        Ok(entries) => entries.into_iter().filter(|entry| query.matches(entry)).collect(),
+        Err(e) => {
+            error!("{}", e);
+            return Err(Failed.into());
+        }
+    };
+
+    if entries.is_empty() {
+        info!("No changes recorded in `{}` match", path().display());
+        return Ok(());
+    }
+    let shown = &entries[entries.len().saturating_sub(SHOWN)..];
+    outln!("{}", tables::audit::render(shown));
+    if shown.len() < entries.len() {
+        info!("Showing the last {} of {} changes. Use `since` and `until` to narrow it down", shown.len(), entries.len());
+    }
+    Ok(())
+}
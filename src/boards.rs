@@ -0,0 +1,453 @@
+//! Relay boards and temperature controllers, whatever the model
+//!
+//! `brewdrivers` has a separate type for every controller, with slightly different methods. The CLI
+//! works with two abstractions instead: a `RelayBoard` (STR1, Waveshare, WaveshareV2) and a
+//! `TempController` (CN7500). Supporting a new model means writing one adapter here and adding
+//! it to `connect`.
+//!
+//! The relay board drivers block while they wait on the port, so their adapters implement
+//! [`RelayDriver`], and the [`RelayBoard`] that `connect` returns runs each operation on a thread
+//! of its own.
+//!
+//! Every board that `connect` returns retries its operations according to its controller's
+//! retry policy (see [`crate::retry`]).
+use std::convert::TryFrom;
+
+use async_trait::async_trait;
+
+use brewdrivers::controllers::cn7500::Degree;
+use brewdrivers::controllers::*;
+use brewdrivers::model::Device;
+use brewdrivers::state::{BinaryState, DeviceState};
+
+use crate::retry::{self, Policy};
+use crate::locks::{self, PortLock};
+use crate::{dry_run, replay, rtus, trace, transport};
+
+pub type Result<T> = std::result::Result<T, InstrumentError>;
+
+/// A board with numbered relays
+#[async_trait]
+pub trait RelayBoard: Send {
+    async fn get_relay(&mut self, relay: u8) -> Result<BinaryState>;
+
+    async fn set_relay(&mut self, relay: u8, state: BinaryState) -> Result<()>;
+
+    /// The states of every relay on the board, in order
+    async fn get_all(&mut self) -> Result<Vec<BinaryState>>;
+
+    /// Sets every relay on the board
+    async fn set_all(&mut self, state: BinaryState) -> Result<()>;
+
+    /// Programs a new controller number into the board
+    async fn set_cn(&mut self, new_cn: u8) -> Result<()>;
+
+    /// Asks the board for its controller number, for boards that support it
+    async fn get_cn(&mut self) -> Result<u8>;
+
+    /// The board's software revision, for boards that support it
+    async fn software_revision(&mut self) -> Result<String>;
+}
+
+/// A relay board's driver, which blocks until the board answers
+pub trait RelayDriver: Send {
+    fn get_relay(&mut self, relay: u8) -> Result<BinaryState>;
+
+    fn set_relay(&mut self, relay: u8, state: BinaryState) -> Result<()>;
+
+    /// The states of every relay on the board, in order
+    fn get_all(&mut self) -> Result<Vec<BinaryState>>;
+
+    /// Sets every relay on the board
+    fn set_all(&mut self, state: BinaryState) -> Result<()> {
+        for relay in 0..self.get_all()?.len() {
+            self.set_relay(relay as u8, state)?;
+        }
+        Ok(())
+    }
+
+    /// Programs a new controller number into the board
+    fn set_cn(&mut self, new_cn: u8) -> Result<()>;
+
+    /// Asks the board for its controller number, for boards that support it
+    fn get_cn(&mut self) -> Result<u8> {
+        Err(unsupported("reading the controller number"))
+    }
+
+    /// The board's software revision, for boards that support it
+    fn software_revision(&mut self) -> Result<String> {
+        Err(unsupported("reading the software revision"))
+    }
+}
+
+/// A PID temperature controller
+#[async_trait]
+pub trait TempController: Send {
+    /// The process value (actual temperature)
+    async fn pv(&mut self) -> Result<f64>;
+
+    /// The setpoint value (target temperature)
+    async fn sv(&mut self) -> Result<f64>;
+
+    async fn set_sv(&mut self, new_sv: f64) -> Result<()>;
+
+    async fn is_running(&mut self) -> Result<bool>;
+
+    async fn run(&mut self) -> Result<()>;
+
+    async fn stop(&mut self) -> Result<()>;
+
+    async fn set_degrees(&mut self, degrees: Degree) -> Result<()>;
+}
+
+fn unsupported(what: &str) -> InstrumentError {
+    InstrumentError::serialError(format!("this board doesn't support {}", what), None)
+}
+
+impl RelayDriver for STR1 {
+    fn get_relay(&mut self, relay: u8) -> Result<BinaryState> {
+        STR1::get_relay(self, relay)
+    }
+
+    fn set_relay(&mut self, relay: u8, state: BinaryState) -> Result<()> {
+        STR1::set_relay(self, relay, state)
+    }
+
+    fn get_all(&mut self) -> Result<Vec<BinaryState>> {
+        (0..self.relay_count()?).map(|relay| STR1::get_relay(self, relay)).collect()
+    }
+
+    fn set_cn(&mut self, new_cn: u8) -> Result<()> {
+        self.set_controller_num(new_cn)
+    }
+}
+
+/// Both Waveshare versions have the same methods
+macro_rules! waveshare_adapter {
+    ($board:ty) => {
+        impl RelayDriver for $board {
+            fn get_relay(&mut self, relay: u8) -> Result<BinaryState> {
+                <$board>::get_relay(self, relay)
+            }
+
+            fn set_relay(&mut self, relay: u8, state: BinaryState) -> Result<()> {
+                <$board>::set_relay(self, relay, state)
+            }
+
+            fn get_all(&mut self) -> Result<Vec<BinaryState>> {
+                self.get_all_relays()
+            }
+
+            fn set_all(&mut self, state: BinaryState) -> Result<()> {
+                self.set_all_relays(state)
+            }
+
+            fn set_cn(&mut self, new_cn: u8) -> Result<()> {
+                self.set_address(new_cn)
+            }
+
+            fn get_cn(&mut self) -> Result<u8> {
+                self.get_address()
+            }
+
+            fn software_revision(&mut self) -> Result<String> {
+                <$board>::software_revision(self)
+            }
+        }
+    };
+}
+
+waveshare_adapter!(Waveshare);
+waveshare_adapter!(WaveshareV2);
+
+#[async_trait]
+impl TempController for CN7500 {
+    async fn pv(&mut self) -> Result<f64> {
+        self.get_pv().await
+    }
+
+    async fn sv(&mut self) -> Result<f64> {
+        self.get_sv().await
+    }
+
+    async fn set_sv(&mut self, new_sv: f64) -> Result<()> {
+        CN7500::set_sv(self, new_sv).await
+    }
+
+    async fn is_running(&mut self) -> Result<bool> {
+        CN7500::is_running(self).await
+    }
+
+    async fn run(&mut self) -> Result<()> {
+        CN7500::run(self).await
+    }
+
+    async fn stop(&mut self) -> Result<()> {
+        CN7500::stop(self).await
+    }
+
+    async fn set_degrees(&mut self, degrees: Degree) -> Result<()> {
+        CN7500::set_degrees(self, degrees).await
+    }
+}
+
+/// A relay board driver, with the lock on its port
+struct Driver {
+    driver: Box<dyn RelayDriver>,
+    _lock: Option<PortLock>,
+}
+
+/// A relay board whose driver is run on a thread of its own for each operation, so waiting on the
+/// port doesn't hold up the runtime. The lock on the port goes with it, so it's held until the
+/// operation is done even if the command is stopped.
+struct BlockingRelay {
+    device_id: String,
+    /// How operations are retried. `None` for a board from [`connect_once`], whose operations are
+    /// tried once and not counted in the stats.
+    policy: Option<Policy>,
+    /// Only missing while an operation that was stopped is still running
+    driver: Option<Driver>,
+}
+
+impl BlockingRelay {
+    /// Runs `f` on the driver, through the retry policy. `once` is for operations that can't
+    /// safely be repeated.
+    async fn run<T: Send + 'static>(
+        &mut self,
+        op: &'static str,
+        once: bool,
+        mut f: impl FnMut(&mut dyn RelayDriver) -> Result<T> + Send + 'static,
+    ) -> Result<T> {
+        let mut driver = self.driver.take().ok_or_else(|| InstrumentError::serialError(format!("`{}` is still busy with a stopped command", self.device_id), None))?;
+        let (device_id, policy) = (self.device_id.clone(), self.policy.clone());
+        let (driver, result) = blocking(&self.device_id, op, move || {
+            let result = match policy {
+                Some(policy) if once => policy.once().run(&device_id, op, || f(driver.driver.as_mut())),
+                Some(policy) => policy.run(&device_id, op, || f(driver.driver.as_mut())),
+                None => f(driver.driver.as_mut()),
+            };
+            (driver, result)
+        })
+        .await?;
+        self.driver = Some(driver);
+        result
+    }
+}
+
+#[async_trait]
+impl RelayBoard for BlockingRelay {
+    async fn get_relay(&mut self, relay: u8) -> Result<BinaryState> {
+        self.run("get_relay", false, move |board| board.get_relay(relay)).await
+    }
+
+    async fn set_relay(&mut self, relay: u8, state: BinaryState) -> Result<()> {
+        self.run("set_relay", false, move |board| board.set_relay(relay, state)).await
+    }
+
+    async fn get_all(&mut self) -> Result<Vec<BinaryState>> {
+        self.run("get_all", false, |board| board.get_all()).await
+    }
+
+    async fn set_all(&mut self, state: BinaryState) -> Result<()> {
+        self.run("set_all", false, move |board| board.set_all(state)).await
+    }
+
+    async fn set_cn(&mut self, new_cn: u8) -> Result<()> {
+        // A board that took the new number but whose answer was lost can't be reached at the old
+        // one, so a retry would report a change that happened as a failure
+        self.run("set_cn", true, move |board| board.set_cn(new_cn)).await
+    }
+
+    async fn get_cn(&mut self) -> Result<u8> {
+        self.run("get_cn", false, |board| board.get_cn()).await
+    }
+
+    async fn software_revision(&mut self) -> Result<String> {
+        self.run("software_revision", false, |board| board.software_revision()).await
+    }
+}
+
+/// A temperature controller whose operations are retried
+struct RetryingTemp {
+    _lock: PortLock,
+    device_id: String,
+    policy: Policy,
+    controller: Box<dyn TempController>,
+}
+
+#[async_trait]
+impl TempController for RetryingTemp {
+    async fn pv(&mut self) -> Result<f64> {
+        self.policy.run_async(&self.device_id, "pv", self.controller.as_mut(), |cn| cn.pv()).await
+    }
+
+    async fn sv(&mut self) -> Result<f64> {
+        self.policy.run_async(&self.device_id, "sv", self.controller.as_mut(), |cn| cn.sv()).await
+    }
+
+    async fn set_sv(&mut self, new_sv: f64) -> Result<()> {
+        self.policy.run_async(&self.device_id, "set_sv", self.controller.as_mut(), |cn| cn.set_sv(new_sv)).await
+    }
+
+    async fn is_running(&mut self) -> Result<bool> {
+        self.policy.run_async(&self.device_id, "is_running", self.controller.as_mut(), |cn| cn.is_running()).await
+    }
+
+    async fn run(&mut self) -> Result<()> {
+        self.policy.run_async(&self.device_id, "run", self.controller.as_mut(), |cn| cn.run()).await
+    }
+
+    async fn stop(&mut self) -> Result<()> {
+        self.policy.run_async(&self.device_id, "stop", self.controller.as_mut(), |cn| cn.stop()).await
+    }
+
+    async fn set_degrees(&mut self, degrees: Degree) -> Result<()> {
+        self.policy.run_async(&self.device_id, "set_degrees", self.controller.as_mut(), |cn| cn.set_degrees(degrees.clone())).await
+    }
+}
+
+/// A connection to a device, whatever kind it is
+pub enum Board {
+    Relay(Box<dyn RelayBoard>),
+    Temp(Box<dyn TempController>),
+}
+
+/// Connects to a device. Connecting is retried too, since most boards are polled as they connect.
+///
+/// The device's port is locked until the board is dropped.
+pub async fn connect(device: &Device) -> Result<Board> {
+    refuse_dry_run(device)?;
+    refuse_remote(device)?;
+    let lock = lock(device).await?;
+    let routed = route(device)?;
+    let policy = retry::policy(routed.conn.controller());
+    let device_id = routed.id.clone();
+
+    if *routed.conn.controller() == Controller::CN7500 {
+        // CN7500 doesn't implement TryFrom<&Device> so we have to do it manually
+        let mut port = routed.conn.port();
+        let (addr, baudrate, timeout) = (routed.conn.controller_addr(), *routed.conn.baudrate() as u64, routed.conn.timeout());
+        let cn = policy
+            .run_async(&device_id, "connect", &mut port, |port| Box::pin(CN7500::connect(addr, port, baudrate, timeout)))
+            .await?;
+        return Ok(Board::Temp(Box::new(RetryingTemp { _lock: lock, device_id, policy, controller: Box::new(cn) })));
+    }
+
+    let retries = policy.clone();
+    let (lock, driver) = blocking(&device_id, "connect", move || {
+        let driver = retries.run(&routed.id, "connect", || relay_driver(&routed));
+        (lock, driver)
+    })
+    .await?;
+    Ok(Board::Relay(Box::new(BlockingRelay { device_id, policy: Some(policy), driver: Some(Driver { driver: driver?, _lock: Some(lock) }) })))
+}
+
+/// Connects to a device once, without retrying or counting it in the stats. This is for probing
+/// connection settings, where a failure is an answer rather than a problem.
+///
+/// This doesn't lock the port. Hold a [`lock`] for as long as the board is used.
+pub async fn connect_once(device: &Device) -> Result<Board> {
+    refuse_dry_run(device)?;
+    refuse_remote(device)?;
+    let routed = route(device)?;
+    let device_id = routed.id.clone();
+
+    if *routed.conn.controller() == Controller::CN7500 {
+        let (addr, baudrate, timeout) = (routed.conn.controller_addr(), *routed.conn.baudrate() as u64, routed.conn.timeout());
+        return Ok(Board::Temp(Box::new(CN7500::connect(addr, &routed.conn.port(), baudrate, timeout).await?)));
+    }
+
+    let driver = blocking(&device_id, "connect", move || relay_driver(&routed)).await??;
+    Ok(Board::Relay(Box::new(BlockingRelay { device_id, policy: None, driver: Some(Driver { driver, _lock: None }) })))
+}
+
+/// Opens a relay board's driver, which waits for the board to answer
+fn relay_driver(device: &Device) -> Result<Box<dyn RelayDriver>> {
+    Ok(match device.conn.controller() {
+        Controller::STR1 => Box::new(STR1::try_from(device)?),
+        Controller::Waveshare => Box::new(Waveshare::try_from(device)?),
+        Controller::WaveshareV2 => Box::new(WaveshareV2::try_from(device)?),
+        Controller::CN7500 => return Err(wrong_kind(device, "relay board")),
+    })
+}
+
+/// Runs `f` on a thread of its own, so it can block without holding up the runtime
+async fn blocking<T: Send + 'static>(device_id: &str, op: &str, f: impl FnOnce() -> T + Send + 'static) -> Result<T> {
+    tokio::task::spawn_blocking(f)
+        .await
+        .map_err(|e| InstrumentError::serialError(format!("`{}` {} didn't finish: {}", device_id, op, e), None))
+}
+
+/// The device to actually open: through the replay, trace and gateway bridge, whichever apply
+fn route(device: &Device) -> Result<Device> {
+    transport::route(&trace::route(&replay::route(device))).map_err(|e| InstrumentError::serialError(e, Some(device.conn.controller_addr())))
+}
+
+/// Locks the port a device is on (see [`crate::locks`])
+pub async fn lock(device: &Device) -> Result<PortLock> {
+    // A replayed port is a fake, so that's what's locked, but a traced one is still the real port
+    locks::acquire(&replay::route(device).conn.port()).await.map_err(|e| InstrumentError::serialError(e, Some(device.conn.controller_addr())))
+}
+
+/// Nothing connects to a port in a dry run
+fn refuse_dry_run(device: &Device) -> Result<()> {
+    if dry_run::enabled() {
+        return Err(InstrumentError::serialError(
+            format!("not connecting to `{}` in a dry run", device.id),
+            Some(device.conn.controller_addr()),
+        ));
+    }
+    Ok(())
+}
+
+/// Devices on another RTU's daemon are connected to by the daemon, not here
+fn refuse_remote(device: &Device) -> Result<()> {
+    match rtus::remote(device) {
+        Some(remote) => Err(InstrumentError::serialError(
+            format!("`{}` is on the `{}` RTU, so only its device commands can be run from here", device.id, remote.rtu),
+            Some(device.conn.controller_addr()),
+        )),
+        None => Ok(()),
+    }
+}
+
+/// Connects to a device that should be a relay board
+pub async fn relay_board(device: &Device) -> Result<Box<dyn RelayBoard>> {
+    match connect(device).await? {
+        Board::Relay(board) => Ok(board),
+        Board::Temp(_) => Err(wrong_kind(device, "relay board")),
+    }
+}
+
+/// Connects to a device that should be a temperature controller
+pub async fn temp_controller(device: &Device) -> Result<Box<dyn TempController>> {
+    match connect(device).await? {
+        Board::Temp(controller) => Ok(controller),
+        Board::Relay(_) => Err(wrong_kind(device, "temperature controller")),
+    }
+}
+
+fn wrong_kind(device: &Device, kind: &str) -> InstrumentError {
+    InstrumentError::serialError(
+        format!("`{}` is a {}, not a {}", device.id, device.conn.controller(), kind),
+        Some(device.conn.controller_addr()),
+    )
+}
+
+/// Reads the current values of a device. Relay boards only fill in `relay_state`. Devices on
+/// another RTU's daemon are read through it.
+pub async fn read(device: &Device) -> Result<DeviceState> {
+    if let Some(remote) = rtus::remote(device) {
+        return rtus::read(remote).await;
+    }
+    let mut state = DeviceState::default();
+    match connect(device).await? {
+        Board::Relay(mut board) => state.relay_state = Some(board.get_relay(device.conn.addr()).await?),
+        Board::Temp(mut controller) => {
+            state.relay_state = Some(controller.is_running().await?.into());
+            state.pv = Some(controller.pv().await?);
+            state.sv = Some(controller.sv().await?);
+        }
+    }
+    Ok(state)
+}
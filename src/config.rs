@@ -0,0 +1,342 @@
+//! CLI specific configuration
+//!
+//! `brewdrivers` owns the RTU configuration file, but it ignores keys it doesn't know about.
+//! The CLI keeps its own settings in the same file, under a top level `cli` key:
+//!
+//! ```yaml
+//! cli:
+//!   alarm_hook: "notify-send 'Brewhouse alarm' \"$NBC_ALARM_DEVICE: $NBC_ALARM_CONDITION\""
+//!   alarm_interval: 5
+//!   poll_timeout: 1000
+//!   timer_hook: "notify-send 'Timer done' \"$NBC_TIMER_NAME\""
+//!   temp_unit: F
+//!   audit_file: /srv/brewery/audit.jsonl
+//!   read_only: true
+//!   write_pin: "4711"
+//!   permissions_file: /etc/NavasotaBrewing/permissions.yaml
+//!   lock_timeout: 5000
+//!   socket: /run/nbc_cli/nbc_cli.sock
+//!   rtus:
+//!     cellar:
+//!       config: /etc/NavasotaBrewing/cellar.yaml
+//!     hot:
+//!       socket: /run/nbc_cli/hot.sock
+//!   roles:
+//!     mash: mash_cn
+//!     hlt: hlt_cn
+//!     kettle: kettle_cn
+//!     hlt_heater: wsrelay3
+//!   retry:
+//!     CN7500:
+//!       attempts: 5
+//!       backoff: 200
+//!       retry_on: [timeout, crc]
+//! ```
+//!
+//! Every setting is optional, and the whole `cli` section can be left out.
+use std::collections::{BTreeMap, HashMap};
+use std::fs;
+use std::sync::OnceLock;
+
+use brewdrivers::model::{ModelError, RTU};
+use serde::Deserialize;
+
+use crate::retry::Policy;
+use crate::transport;
+
+/// Settings for the CLI itself, as opposed to the RTU and its devices
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+pub struct CliConfig {
+    /// A shell command that is run (with `sh -c`) every time an alarm fires
+    #[serde(default)]
+    pub alarm_hook: Option<String>,
+    /// How often the alarm poller checks devices, in seconds
+    #[serde(default = "default_alarm_interval")]
+    pub alarm_interval: u64,
+    /// How long the dashboard waits on each device before showing its last values as stale, in milliseconds
+    #[serde(default = "default_poll_timeout")]
+    pub poll_timeout: u64,
+    /// A shell command that is run (with `sh -c`) every time a timer ends
+    #[serde(default)]
+    pub timer_hook: Option<String>,
+    /// The units the CN7500s are set to display. Used to convert recipe temperatures.
+    #[serde(default)]
+    pub temp_unit: TempUnit,
+    /// Maps a role, like `mash` or `hlt_heater`, to a device ID. Roles can be used in place of device IDs.
+    #[serde(default)]
+    pub roles: HashMap<String, String>,
+    /// Retry policies by controller type, like `CN7500`. Controllers that aren't listed get the default policy.
+    #[serde(default)]
+    pub retry: HashMap<String, Policy>,
+    /// Where the audit journal of device changes is kept. Defaults to [`crate::audit::DEFAULT_FILE`].
+    #[serde(default)]
+    pub audit_file: Option<String>,
+    /// Start in read-only mode, unless running as root
+    #[serde(default)]
+    pub read_only: bool,
+    /// The PIN that `unlock` asks for to switch a read-only CLI to write mode
+    #[serde(default)]
+    pub write_pin: Option<String>,
+    /// Who can change which devices. Defaults to `/etc/NavasotaBrewing/permissions.yaml`, if it exists.
+    #[serde(default)]
+    pub permissions_file: Option<String>,
+    /// How long to wait for a port another CLI is using, in milliseconds
+    #[serde(default = "default_lock_timeout")]
+    pub lock_timeout: u64,
+    /// Where `NBC_cli daemon` listens, and where the CLI looks for it. Defaults to `/tmp/nbc_cli.sock`.
+    #[serde(default)]
+    pub socket: Option<String>,
+    /// Other RTUs to control alongside this one, by the name their devices are namespaced with
+    #[serde(default)]
+    pub rtus: BTreeMap<String, RtuSource>,
+}
+
+/// Where another RTU's devices come from: its config file, for an RTU on this machine, or its
+/// daemon's socket. Exactly one should be given.
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+pub struct RtuSource {
+    #[serde(default)]
+    pub config: Option<String>,
+    #[serde(default)]
+    pub socket: Option<String>,
+}
+
+/// Temperature units
+#[derive(Debug, Clone, Copy, PartialEq, Default, Deserialize)]
+pub enum TempUnit {
+    #[default]
+    F,
+    C,
+}
+
+impl TempUnit {
+    /// Converts a temperature in Celsius to these units
+    pub fn convert(&self, celsius: f64) -> f64 {
+        match self {
+            TempUnit::F => celsius * 9.0 / 5.0 + 32.0,
+            TempUnit::C => celsius,
+        }
+    }
+
+    /// Converts a temperature in Celsius to these units and formats it, like `152.0F`
+    pub fn format(&self, celsius: f64) -> String {
+        format!("{:.1}{:?}", self.convert(celsius), self)
+    }
+}
+
+fn default_alarm_interval() -> u64 {
+    5
+}
+
+fn default_poll_timeout() -> u64 {
+    1000
+}
+
+fn default_lock_timeout() -> u64 {
+    5000
+}
+
+impl Default for CliConfig {
+    fn default() -> Self {
+        Self {
+            alarm_hook: None,
+            alarm_interval: default_alarm_interval(),
+            poll_timeout: default_poll_timeout(),
+            timer_hook: None,
+            temp_unit: TempUnit::default(),
+            roles: HashMap::new(),
+            retry: HashMap::new(),
+            audit_file: None,
+            read_only: false,
+            write_pin: None,
+            permissions_file: None,
+            lock_timeout: default_lock_timeout(),
+            socket: None,
+            rtus: BTreeMap::new(),
+        }
+    }
+}
+
+/// The parts of the config file that the CLI cares about
+#[derive(Deserialize)]
+struct ConfigFile {
+    #[serde(default)]
+    cli: CliConfig,
+}
+
+impl CliConfig {
+    /// Reads the `cli` section out of the given configuration file
+    pub fn load(path: &str) -> Result<CliConfig, ModelError> {
+        let contents = fs::read_to_string(path).map_err(ModelError::IOError)?;
+        let file = serde_yaml::from_str::<ConfigFile>(&contents).map_err(ModelError::SerdeParseError)?;
+        Ok(file.cli)
+    }
+}
+
+/// Builds the RTU from a config file, like `RTU::generate`, except that ports can be on the network
+/// too (see [`crate::transport`]). brewdrivers only knows about `/dev` ports, so it checks network
+/// ports as if they were `/dev/null`.
+pub fn rtu(path: &str) -> Result<RTU, ModelError> {
+    let contents = fs::read_to_string(path).map_err(ModelError::IOError)?;
+    let rtu = serde_yaml::from_str::<RTU>(&contents).map_err(ModelError::SerdeParseError)?;
+
+    let mut checked = rtu.clone();
+    for device in &mut checked.devices {
+        if transport::is_network(&device.conn.port()) {
+            device.conn.port = "/dev/null".into();
+        }
+    }
+    checked.validate()?;
+    transport::validate(&rtu)?;
+    Ok(rtu)
+}
+
+static FILE: OnceLock<String> = OnceLock::new();
+
+/// Remembers which config file the CLI was started with
+pub fn set_file(path: &str) {
+    let _ = FILE.set(path.to_string());
+}
+
+/// The config file the CLI was started with
+pub fn file() -> &'static str {
+    FILE.get().map(|path| path.as_str()).unwrap_or(brewdrivers::CONFIG_FILE)
+}
+
+/// Where a line's `# comment` starts, if it has one. A `#` only starts a comment at the start of
+/// the line or after a space, and not inside quotes.
+fn comment_start(line: &str) -> Option<usize> {
+    let mut quote = None;
+    let mut previous = ' ';
+    for (i, c) in line.char_indices() {
+        match (quote, c) {
+            (None, '"' | '\'') => quote = Some(c),
+            (Some(open), _) if c == open => quote = None,
+            (None, '#') if previous.is_whitespace() => return Some(i),
+            _ => {}
+        }
+        previous = c;
+    }
+    None
+}
+
+/// A line without its `# comment`
+fn uncommented(line: &str) -> &str {
+    comment_start(line).map_or(line, |i| line[..i].trim_end())
+}
+
+/// The value of a `key: value` line, without quotes or a comment
+fn value(line: &str) -> &str {
+    uncommented(line).split_once(':').map(|(_, value)| value.trim().trim_matches(|c| c == '"' || c == '\'')).unwrap_or("")
+}
+
+/// The key of a `key: value` line, without indentation or a list dash
+fn key(line: &str) -> &str {
+    line.trim_start().trim_start_matches('-').trim_start().split(':').next().unwrap_or("")
+}
+
+/// How far a line is indented
+fn indent(line: &str) -> usize {
+    line.len() - line.trim_start().len()
+}
+
+/// Sets a device's `baudrate` and `timeout` in the config file. The file is edited line by line,
+/// so comments and formatting are kept.
+pub fn save_connection(path: &str, device_id: &str, baudrate: usize, timeout: u64) -> Result<(), String> {
+    let contents = fs::read_to_string(path).map_err(|e| format!("Couldn't read `{}`: {}", path, e))?;
+    let contents = with_connection(&contents, device_id, baudrate, timeout).map_err(|e| format!("`{}` {}", path, e))?;
+    fs::write(path, contents).map_err(|e| format!("Couldn't write `{}`: {}", path, e))
+}
+
+/// The config file's contents, with a device's `baudrate` and `timeout` set
+fn with_connection(contents: &str, device_id: &str, baudrate: usize, timeout: u64) -> Result<String, String> {
+    let mut lines: Vec<String> = contents.lines().map(String::from).collect();
+    let is_content = |line: &str| !uncommented(line).trim().is_empty();
+
+    // The `devices` list runs until the next top level key. Its items can be indented or not.
+    let devices = lines
+        .iter()
+        .position(|line| indent(line) == 0 && key(line) == "devices")
+        .ok_or("has no `devices`")?;
+    let block_end = lines[devices + 1..]
+        .iter()
+        .position(|line| is_content(line) && indent(line) == 0 && !line.starts_with('-'))
+        .map_or(lines.len(), |i| devices + 1 + i);
+
+    // Each device starts with a dash, at the same indent as the first one
+    let item_indent = lines[devices + 1..block_end]
+        .iter()
+        .find(|line| is_content(line))
+        .filter(|line| line.trim_start().starts_with('-'))
+        .map(|line| indent(line))
+        .ok_or("has no devices")?;
+    let starts: Vec<usize> = (devices + 1..block_end)
+        .filter(|&i| indent(&lines[i]) == item_indent && lines[i].trim_start().starts_with('-'))
+        .collect();
+    let (start, end) = starts
+        .iter()
+        .enumerate()
+        .map(|(n, &start)| (start, starts.get(n + 1).copied().unwrap_or(block_end)))
+        .find(|&(start, end)| lines[start..end].iter().any(|line| key(line) == "id" && value(line) == device_id))
+        .ok_or_else(|| format!("has no device `{}`", device_id))?;
+
+    for (setting, new_value) in [("baudrate", baudrate.to_string()), ("timeout", timeout.to_string())] {
+        let line = lines[start..end]
+            .iter_mut()
+            .find(|line| key(line) == setting)
+            .ok_or_else(|| format!("has no `{}` for `{}`", setting, device_id))?;
+        // Everything after the old value, like a comment, stays as it was
+        let value_end = uncommented(line).len();
+        let name = line[..value_end].split_once(':').map_or("", |(name, _)| name);
+        *line = format!("{}: {}{}", name, new_value, &line[value_end..]);
+    }
+
+    let mut contents = lines.join("\n");
+    contents.push('\n');
+    Ok(contents)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn saves_to_the_right_device() {
+        let saved = with_connection(include_str!("../tests/fixtures/rtu_conf.yaml"), "mash_cn", 9600, 80).unwrap();
+        assert!(saved.contains("  - id: mash_cn\n    name: Mash Tun\n    conn:\n      port: /dev/ttyUSB1\n      baudrate: 9600\n      timeout: 80\n"));
+        // The other devices are left alone
+        assert!(saved.contains("      port: /dev/ttyUSB0\n      baudrate: 38400\n      timeout: 40\n"));
+    }
+
+    #[test]
+    fn saves_unindented_devices_with_comments() {
+        let contents = "\
+id: rtu # the RTU's own id
+devices:
+- id: pump # by the kettle
+  conn:
+    baudrate: 9600 # factory setting
+    timeout: 40
+- id: \"kettle\"
+  conn:
+    baudrate: 9600
+    timeout: 40
+cli:
+  read_only: true
+";
+        let saved = with_connection(contents, "pump", 38400, 25).unwrap();
+        assert_eq!(saved, contents.replacen("baudrate: 9600 #", "baudrate: 38400 #", 1).replacen("timeout: 40", "timeout: 25", 1));
+
+        let saved = with_connection(contents, "kettle", 19200, 30).unwrap();
+        assert!(saved.ends_with("- id: \"kettle\"\n  conn:\n    baudrate: 19200\n    timeout: 30\ncli:\n  read_only: true\n"));
+
+        assert_eq!(with_connection(contents, "rtu", 9600, 40).unwrap_err(), "has no device `rtu`");
+    }
+
+    #[test]
+    fn comments_arent_values() {
+        assert_eq!(value("  - id: pump # by the kettle"), "pump");
+        assert_eq!(value("  name: \"Pump #2\" # the spare"), "Pump #2");
+        assert_eq!(value("  name: Pump#2"), "Pump#2");
+    }
+}
@@ -0,0 +1,498 @@
+//! `NBC_cli daemon`: one CLI that owns the RTU, with every other CLI as its client
+//!
+//! The daemon is the only process that opens the serial ports. It runs the alarm poller and the
+//! timer ticker for as long as it's up, and listens on a Unix domain socket (`cli.socket`, or
+//! `/tmp/nbc_cli.sock`) for commands. While it's running, `exec`, the shell and scripts don't
+//! touch the ports at all. They send each command line to the daemon and print what comes back,
+//! so `exec` doesn't have to build the RTU first either. `--local` (or `--trace-serial` or
+//! `--replay-serial`) runs commands in the CLI itself, like before.
+//!
+//! Each command is one connection. The client sends a [`Request`] as a line of JSON, and the
+//! daemon runs it as the user on the other end of the socket, sending back its output as it goes.
+//! Lines starting with [`LOG`] are log records, for stderr. A line starting with [`CONTROL`] asks
+//! the client for something (`ask`, an answer to a confirmation), or ends the command (`exit 1`).
+//!
+//! Other CLIs can also send a [`Query`] instead of a command line, for the daemon's RTU or a
+//! device's values. That's how devices on another RTU are shown alongside local ones (see
+//! [`crate::rtus`]).
+use std::collections::HashMap;
+use std::fs::{self, Permissions};
+use std::io;
+use std::os::unix::fs::{chown, MetadataExt, PermissionsExt};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, OnceLock};
+use std::time::Duration;
+
+use log::{error, info, warn};
+use brewdrivers::model::RTU;
+use brewdrivers::state::DeviceState;
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+use shellfish::Command;
+use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
+use tokio::net::unix::{OwnedReadHalf, OwnedWriteHalf};
+use tokio::net::{UnixListener, UnixStream};
+use tokio::signal::unix::{signal, SignalKind};
+use tokio::sync::{mpsc, Mutex};
+
+use crate::audit::{self, Mode};
+use crate::config::CliConfig;
+use crate::session::Session;
+use crate::{access, boards, dispatch, dry_run, output, permissions, prompt, rtus};
+
+/// Where the daemon listens, unless `cli.socket` says otherwise
+pub const DEFAULT_SOCKET: &str = "/tmp/nbc_cli.sock";
+
+/// Starts a line that's for the client rather than the brewer
+const CONTROL: char = '\u{1}';
+
+/// Starts a line that's a log record
+const LOG: char = '\u{2}';
+
+/// How long a client has to send its request
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// How long to wait for a daemon to answer a query
+const QUERY_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// A command line for the daemon to run, and how the client was started
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Request {
+    pub line: Vec<String>,
+    #[serde(default = "default_mode")]
+    pub mode: Mode,
+    /// Who the client says it is. Only believed from root, since that's who `sudo` users are.
+    #[serde(default)]
+    pub user: Option<String>,
+    /// `--yes`
+    #[serde(default)]
+    pub yes: bool,
+    /// `--dry-run`
+    #[serde(default)]
+    pub dry_run: bool,
+    /// Whether the client is read-only
+    #[serde(default)]
+    pub read_only: bool,
+    /// The PIN the client was unlocked with, if any
+    #[serde(default)]
+    pub pin: Option<String>,
+    /// Asks for information instead of running `line`
+    #[serde(default)]
+    pub query: Option<Query>,
+}
+
+/// Something a daemon is asked for rather than told to do. It answers with one line of JSON.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Query {
+    /// The daemon's own RTU
+    Rtu,
+    /// The current values of one of its devices, or why they couldn't be read
+    Read(String),
+}
+
+fn default_mode() -> Mode {
+    Mode::Api
+}
+
+fn socket_path(config: &CliConfig) -> PathBuf {
+    PathBuf::from(config.socket.as_deref().unwrap_or(DEFAULT_SOCKET))
+}
+
+/// A client the daemon is running a command for
+pub struct Client {
+    pub request: Request,
+    pub user: String,
+    pub root: bool,
+    /// Whether the client is read-only, before its PIN is taken into account
+    pub locked: bool,
+    /// Whether the client is read-only
+    pub read_only: bool,
+    /// Why the client's PIN wasn't checked, if it's locked out
+    pub refused: Option<String>,
+    /// Output waiting to be written to the socket
+    output: mpsc::UnboundedSender<String>,
+    /// The client's side of the socket, for answers to questions
+    answers: Mutex<BufReader<OwnedReadHalf>>,
+    closed: Arc<AtomicBool>,
+}
+
+tokio::task_local! {
+    /// The client the command running in this task is for
+    static CLIENT: Arc<Client>;
+}
+
+/// The client the current command is being run for, if it's being run by the daemon
+pub fn client() -> Option<Arc<Client>> {
+    CLIENT.try_with(|client| client.clone()).ok()
+}
+
+impl Client {
+    /// Sends output to the client. It's written to the socket in the background (see
+    /// [`write_output`]), so a slow client doesn't hold up the command.
+    pub fn write(&self, text: &str) {
+        if self.closed.load(Ordering::Relaxed) {
+            return;
+        }
+        if self.output.send(text.to_string()).is_err() {
+            self.closed.store(true, Ordering::Relaxed);
+        }
+    }
+
+    /// Sends a log record to the client
+    pub fn log(&self, text: &str) {
+        self.write(&text.lines().map(|line| format!("{}{}\n", LOG, line)).collect::<String>());
+    }
+
+    /// Asks the client to read a line from its terminal. `None` if there was nothing to read.
+    pub async fn ask(&self) -> Option<String> {
+        self.write(&format!("{}ask\n", CONTROL));
+        let mut answer = Vec::new();
+        match self.answers.lock().await.read_until(b'\n', &mut answer).await {
+            Ok(n) if n > 0 => serde_json::from_slice(&answer).ok().flatten(),
+            _ => None,
+        }
+    }
+}
+
+/// Writes a client's output to its socket as it comes. Finishes once the client has nothing left
+/// to send, or has gone.
+async fn write_output(mut stream: OwnedWriteHalf, mut output: mpsc::UnboundedReceiver<String>, closed: Arc<AtomicBool>) {
+    while let Some(text) = output.recv().await {
+        if stream.write_all(text.as_bytes()).await.is_err() {
+            closed.store(true, Ordering::Relaxed);
+            return;
+        }
+    }
+}
+
+/// The daemon's state, shared by every connection. Each command runs on its own copy of the
+/// session, whose alarms, timers and recipe are shared with every other copy.
+struct Daemon {
+    session: Session,
+    commands: HashMap<String, Command<Session>>,
+}
+
+/// Runs the daemon until it's stopped with Ctrl-C or `SIGTERM`. Fails if another daemon is
+/// already listening on the socket.
+pub async fn serve(session: Session, commands: HashMap<String, Command<Session>>) -> Result<(), String> {
+    let path = socket_path(&session.config);
+    if UnixStream::connect(&path).await.is_ok() {
+        return Err(format!("Another daemon is already listening on `{}`", path.display()));
+    }
+    // Left behind by a daemon that didn't stop cleanly
+    match fs::remove_file(&path) {
+        Err(e) if e.kind() != io::ErrorKind::NotFound => return Err(format!("Couldn't remove the old socket `{}`: {}", path.display(), e)),
+        _ => {}
+    }
+
+    let listener = UnixListener::bind(&path).map_err(|e| format!("Couldn't listen on `{}`: {}", path.display(), e))?;
+    // Only the daemon's user and the serial ports' group can connect, since they could open the
+    // ports themselves anyway. Who they are is checked again on every command (see `admits`).
+    let _ = fs::set_permissions(&path, Permissions::from_mode(0o660));
+    if let Some(gid) = port_group(&session.rtu) {
+        if let Err(e) = chown(&path, None, Some(gid)) {
+            warn!("Couldn't give `{}` to the serial ports' group: {}", path.display(), e);
+        }
+    }
+    info!("Listening on `{}`", path.display());
+
+    let daemon = Arc::new(Daemon { session, commands });
+    let mut terminate = signal(SignalKind::terminate()).map_err(|e| e.to_string())?;
+    loop {
+        tokio::select! {
+            accepted = listener.accept() => match accepted {
+                Ok((stream, _)) => {
+                    tokio::spawn(connection(daemon.clone(), stream));
+                }
+                Err(e) => error!("Couldn't accept a connection: {}", e),
+            },
+            _ = tokio::signal::ctrl_c() => break,
+            _ = terminate.recv() => break,
+        }
+    }
+
+    info!("Stopping");
+    let _ = fs::remove_file(&path);
+    Ok(())
+}
+
+/// The group that owns this RTU's serial ports, found from the first one that's there
+fn port_group(rtu: &RTU) -> Option<u32> {
+    rtu.devices
+        .iter()
+        .filter(|device| rtus::remote(device).is_none())
+        .find_map(|device| fs::metadata(&device.conn.port).ok())
+        .map(|port| port.gid())
+}
+
+/// Whether the user with the ID `uid` can use the daemon. Without a permissions file to say what
+/// each user can do, only root and the daemon's own user can.
+fn admits(uid: u32) -> bool {
+    uid == 0 || access::uids().is_some_and(|(_, effective)| effective == uid) || permissions::loaded()
+}
+
+/// Reads a request off a new connection and runs it
+async fn connection(daemon: Arc<Daemon>, stream: UnixStream) {
+    let uid = match stream.peer_cred() {
+        Ok(cred) => cred.uid(),
+        Err(e) => {
+            error!("Couldn't tell who connected: {}", e);
+            return;
+        }
+    };
+
+    let (reader, writer) = stream.into_split();
+    let (output, waiting) = mpsc::unbounded_channel();
+    let closed = Arc::new(AtomicBool::new(false));
+    tokio::spawn(write_output(writer, waiting, closed.clone()));
+
+    let mut reader = BufReader::new(reader);
+    let mut header = String::new();
+    match tokio::time::timeout(REQUEST_TIMEOUT, reader.read_line(&mut header)).await {
+        Ok(Ok(n)) if n > 0 => {}
+        // Connections that close straight away are clients checking that the daemon is up
+        _ => return,
+    }
+    let request: Request = match serde_json::from_str(&header) {
+        Ok(request) => request,
+        Err(e) => {
+            let _ = output.send(format!("{}Not a request: {}\n{}exit 1\n", LOG, e, CONTROL));
+            return;
+        }
+    };
+    if !admits(uid) {
+        let owner = access::uids().map(|(_, effective)| access::username(effective)).unwrap_or_default();
+        let _ = output.send(format!(
+            "{}`{}` can't use this daemon. It only takes commands from `{}` until there's a permissions file saying who can do what\n{}exit 1\n",
+            LOG,
+            access::username(uid),
+            owner,
+            CONTROL
+        ));
+        return;
+    }
+
+    let config = daemon.session.config.clone();
+    let root = uid == 0;
+    // `NBC_cli --read-only daemon` locks every client
+    let locked = request.read_only || access::read_only() || (config.read_only && !root);
+    let unlocked = match (&request.pin, &config.write_pin) {
+        (Some(pin), Some(expected)) if locked => access::check_pin(uid, pin, expected).await,
+        _ => Ok(false),
+    };
+    let client = Arc::new(Client {
+        user: match &request.user {
+            Some(user) if root && user != "unknown" => user.clone(),
+            _ => access::username(uid),
+        },
+        root,
+        locked,
+        read_only: locked && unlocked != Ok(true),
+        refused: unlocked.err(),
+        request,
+        output,
+        answers: Mutex::new(reader),
+        closed,
+    });
+
+    if let Some(query) = client.request.query.clone() {
+        let answer = CLIENT.scope(client.clone(), answer(&daemon, query)).await;
+        client.write(&format!("{}\n", answer));
+        return;
+    }
+
+    let succeeded = CLIENT
+        .scope(client.clone(), async {
+            tokio::select! {
+                (_, succeeded) = output::succeeded(run(&daemon, client.request.line.clone())) => succeeded,
+                _ = gone(&client) => false,
+            }
+        })
+        .await;
+    let status = if succeeded { 0 } else { 1 };
+    client.write(&format!("{}exit {}\n", CONTROL, status));
+}
+
+/// Answers a query, as JSON
+async fn answer(daemon: &Daemon, query: Query) -> String {
+    let rtu = daemon.session.rtu.clone();
+    match query {
+        Query::Rtu => {
+            let mut rtu = rtu;
+            // Only the daemon's own devices, not other RTUs it's controlling
+            rtu.devices.retain(|device| !rtus::is_namespaced(&device.id));
+            serde_json::to_string(&rtu)
+        }
+        Query::Read(id) => {
+            let state: Result<DeviceState, String> = match rtu.devices.iter().find(|device| device.id == id) {
+                Some(device) => boards::read(device).await.map_err(|e| e.to_string()),
+                None => Err(format!("No device with the ID `{}`", id)),
+            };
+            serde_json::to_string(&state)
+        }
+    }
+    .unwrap_or_default()
+}
+
+/// Finishes once the client has gone, so its command can be stopped
+async fn gone(client: &Client) {
+    while !client.closed.load(Ordering::Relaxed) {
+        tokio::time::sleep(Duration::from_millis(250)).await;
+    }
+}
+
+/// Runs a command line for the current client
+async fn run(daemon: &Daemon, mut line: Vec<String>) {
+    let mut session = daemon.session.clone();
+    let commands: HashMap<&str, Command<Session>> = daemon.commands.iter().map(|(name, cmd)| (name.as_str(), cmd.clone())).collect();
+    if let Err(e) = dispatch::resolve_device(&mut line, &commands, &session) {
+        error!("{}", e);
+        output::fail();
+        return;
+    }
+
+    let name = match line.first() {
+        Some(name) => name.clone(),
+        None => return,
+    };
+    match (name.as_str(), commands.get(name.as_str())) {
+        ("help", _) => {
+            let mut names: Vec<_> = commands.iter().collect();
+            names.sort_by_key(|(name, _)| **name);
+            for (name, cmd) in names {
+                outln!("{}: {}", name, cmd.help);
+            }
+        }
+        ("quit" | "exit", _) => {}
+        (_, Some(command)) => {
+            dispatch::execute(command, &mut session, line).await;
+        }
+        (_, None) => {
+            error!("Command not found: {}", name);
+            output::fail();
+        }
+    }
+}
+
+/// The daemon's socket, if this CLI is its client
+static SOCKET: OnceLock<PathBuf> = OnceLock::new();
+
+/// Looks for a daemon on the socket in `config`, and makes this CLI its client if there is one
+pub async fn connect(config: &CliConfig) -> bool {
+    let path = socket_path(config);
+    if UnixStream::connect(&path).await.is_err() {
+        return false;
+    }
+    let _ = SOCKET.set(path);
+    true
+}
+
+/// The daemon's socket, if this CLI is its client
+pub fn socket() -> Option<&'static PathBuf> {
+    SOCKET.get()
+}
+
+/// A request for `line`, from this CLI as it was started
+fn request(line: Vec<String>, query: Option<Query>) -> Request {
+    Request {
+        line,
+        mode: audit::mode(),
+        user: Some(audit::user()),
+        yes: prompt::assumed_yes(),
+        dry_run: dry_run::enabled(),
+        read_only: access::read_only(),
+        pin: access::pin(),
+        query,
+    }
+}
+
+/// Connects to the daemon at `path` and sends it a request
+async fn open(path: &Path, request: &Request) -> Result<UnixStream, String> {
+    let mut stream = UnixStream::connect(path).await.map_err(|e| format!("Couldn't reach the daemon at `{}`: {}", path.display(), e))?;
+    let header = serde_json::to_string(request).unwrap_or_default();
+    stream.write_all(format!("{}\n", header).as_bytes()).await.map_err(|e| format!("Couldn't send a request to the daemon at `{}`: {}", path.display(), e))?;
+    Ok(stream)
+}
+
+/// Has the daemon run a command line, printing what it sends back. Returns the command's exit status.
+pub async fn send(line: Vec<String>) -> i32 {
+    match socket() {
+        Some(path) => send_to(path, line).await,
+        None => 1,
+    }
+}
+
+/// Asks the daemon at `path` a query
+pub async fn query<T: DeserializeOwned>(path: &Path, query: Query) -> Result<T, String> {
+    let stream = open(path, &request(vec![], Some(query))).await?;
+    let mut lines = BufReader::new(stream).lines();
+    let answer = async {
+        while let Some(line) = lines.next_line().await.map_err(|e| format!("The daemon at `{}` didn't answer: {}", path.display(), e))? {
+            // Anything it logged while answering is left out
+            if !line.starts_with(LOG) {
+                return serde_json::from_str(&line).map_err(|e| format!("The daemon at `{}` gave an answer that can't be read: {}", path.display(), e));
+            }
+        }
+        Err(format!("The daemon at `{}` stopped without answering", path.display()))
+    };
+    tokio::time::timeout(QUERY_TIMEOUT, answer)
+        .await
+        .unwrap_or_else(|_| Err(format!("The daemon at `{}` didn't answer within {} seconds", path.display(), QUERY_TIMEOUT.as_secs())))
+}
+
+/// Has the daemon at `path` run a command line, printing what it sends back. Returns the
+/// command's exit status.
+///
+/// In the daemon itself, everything is passed through to the client, which answers the questions.
+pub async fn send_to(path: &Path, line: Vec<String>) -> i32 {
+    let relay = client();
+    let request = request(line, None);
+    let (reader, mut writer) = match open(path, &request).await {
+        Ok(stream) => stream.into_split(),
+        Err(e) => {
+            error!("{}", e);
+            return 1;
+        }
+    };
+
+    let mut reader = BufReader::new(reader);
+    let mut buf = Vec::new();
+    loop {
+        buf.clear();
+        match reader.read_until(b'\n', &mut buf).await {
+            Ok(0) | Err(_) => {
+                error!("The daemon stopped before `{}` finished", request.line.join(" "));
+                return 1;
+            }
+            Ok(_) => {}
+        }
+        let line = String::from_utf8_lossy(&buf);
+        if let Some(record) = line.strip_prefix(LOG) {
+            match &relay {
+                Some(client) => client.write(&line),
+                None => eprint!("{}", record),
+            }
+            continue;
+        }
+        // A control line can come straight after output without a newline, like a question
+        let (text, control) = match line.split_once(CONTROL) {
+            Some((text, control)) => (text, Some(control.trim())),
+            None => (line.as_ref(), None),
+        };
+        out!("{}", text);
+        match control {
+            Some("ask") => {
+                let answer = prompt::answer().await.map(|answer| answer.trim().to_string());
+                let _ = writer.write_all(format!("{}\n", serde_json::to_string(&answer).unwrap_or_default()).as_bytes()).await;
+            }
+            Some(exit) => {
+                if let Some(status) = exit.strip_prefix("exit ").and_then(|status| status.parse().ok()) {
+                    return status;
+                }
+            }
+            None => {}
+        }
+    }
+}
@@ -0,0 +1,227 @@
+//! Running command lines against the shell's commands
+use std::collections::HashMap;
+use std::error::Error;
+use std::time::Duration;
+
+use async_trait::async_trait;
+use log::error;
+use shellfish::command::CommandType;
+use shellfish::handler::{AsyncHandler, DefaultAsyncHandler};
+use shellfish::Command;
+
+use crate::repl::{Chain, Link};
+use crate::resolve::{self, ResolveError};
+use crate::output::{self, Failed};
+use crate::{aliases, daemon, duration, registry};
+use crate::session::Session;
+
+/// Shellfish's default handler, except that a device can be addressed by name or role as well as
+/// ID, and an alias runs the commands it stands for (see [`crate::aliases`]).
+///
+/// If the first word isn't a command, it's resolved to a device and replaced with that device's ID
+/// before the line is handed to shellfish. When the CLI is the daemon's client, lines are sent to
+/// the daemon instead, except for the ones about the shell itself. Aliases are the user's own, so
+/// they're expanded before anything is sent.
+///
+/// A command fails if it returns an error (see [`crate::output`]), which is what `&&` and `||` go by.
+#[derive(Default, Clone, Copy)]
+pub struct Dispatcher;
+
+#[async_trait]
+impl AsyncHandler<Session> for Dispatcher {
+    async fn handle_async(
+        &self,
+        line: Vec<String>,
+        commands: &HashMap<&str, Command<Session>>,
+        state: &mut Session,
+        description: &str,
+    ) -> bool {
+        let taken = |word: &str| is_taken(word, commands, state);
+        match aliases::expand(&line, &taken) {
+            Ok(Some(chain)) => {
+                let (quit, ok) = run_chain(self, chain, commands, state, description).await;
+                if !ok {
+                    output::fail();
+                }
+                quit
+            }
+            Ok(None) => handle_line(line, commands, state, description).await,
+            Err(e) => {
+                error!("{}", e);
+                output::fail();
+                false
+            }
+        }
+    }
+}
+
+/// Runs a chain of commands (see [`crate::repl::chain`]), skipping the ones whose `&&` or `||`
+/// doesn't hold. Returns whether one of them quit the shell, and whether the last one that ran
+/// succeeded.
+///
+/// A chain isn't atomic: each command locks its ports for itself, so other terminals and daemon
+/// clients can get at the devices between two commands.
+pub async fn run_chain<H: AsyncHandler<Session>>(
+    handler: &H,
+    chain: Chain,
+    commands: &HashMap<&str, Command<Session>>,
+    state: &mut Session,
+    description: &str,
+) -> (bool, bool) {
+    let mut ok = true;
+    for (link, line) in chain {
+        match link {
+            Link::And if !ok => continue,
+            Link::Or if ok => continue,
+            _ => {}
+        }
+        let (quit, succeeded) = output::succeeded(handler.handle_async(line, commands, state, description)).await;
+        if quit {
+            return (true, succeeded);
+        }
+        ok = succeeded;
+    }
+    (false, ok)
+}
+
+/// Whether a command line runs in this CLI even when it's the daemon's client
+pub fn is_local(line: &[String]) -> bool {
+    matches!(line.first().map(String::as_str), None | Some("help" | "quit" | "exit" | "unlock" | "lock" | "alias" | "unalias" | "sleep"))
+}
+
+/// Whether a word is a command or a device, which aliases can't override
+fn is_taken(word: &str, commands: &HashMap<&str, Command<Session>>, session: &Session) -> bool {
+    commands.contains_key(word) || matches!(word, "help" | "quit" | "exit") || resolve::device(&session.rtu, &session.config, word).is_ok()
+}
+
+/// Sends a chain of commands to the daemon, as a client `exec` does, with aliases expanded. Returns
+/// the exit status of the last command that ran. Each command is its own request, so like any
+/// chain, other clients' commands can run in between.
+pub async fn forward(chain: Chain) -> i32 {
+    let mut status = 0;
+    for (link, line) in chain {
+        match link {
+            Link::And if status != 0 => continue,
+            Link::Or if status == 0 => continue,
+            _ => {}
+        }
+        // The RTU isn't loaded in a client `exec`, so only the general commands are known to be taken
+        status = match aliases::expand(&line, &registry::is_general) {
+            Ok(Some(chain)) => Box::pin(forward(chain)).await,
+            // Sleeping in the daemon would hold up its other clients
+            Ok(None) if line[0] == "sleep" => match sleep_time(&line) {
+                Some(time) => {
+                    tokio::time::sleep(time).await;
+                    0
+                }
+                None => 1,
+            },
+            Ok(None) => daemon::send(line).await,
+            Err(e) => {
+                error!("{}", e);
+                1
+            }
+        };
+    }
+    status
+}
+
+/// How long `sleep [duration]` sleeps for. Logs the usage if it's wrong.
+fn sleep_time(line: &[String]) -> Option<Duration> {
+    match line.get(1).and_then(|duration| duration::parse(duration)) {
+        Some(time) if line.len() == 2 => Some(time),
+        _ => {
+            error!("Usage: sleep [duration], ie. `sleep 5` or `sleep 1m30s`");
+            None
+        }
+    }
+}
+
+/// The `sleep` shell command, which waits before the next command in a chain, ie.
+/// `mash_pump On && sleep 5 && mash run`
+pub async fn sleep(_: &mut Session, args: Vec<String>) -> Result<(), Box<dyn Error>> {
+    let time = sleep_time(&args).ok_or(Failed)?;
+    tokio::time::sleep(time).await;
+    Ok(())
+}
+
+/// Runs one command line, with no aliases in it
+async fn handle_line(
+    mut line: Vec<String>,
+    commands: &HashMap<&str, Command<Session>>,
+    state: &mut Session,
+    description: &str,
+) -> bool {
+    if daemon::socket().is_some() && !is_local(&line) {
+        if daemon::send(line).await != 0 {
+            output::fail();
+        }
+        return false;
+    }
+
+    if let Err(e) = resolve_device(&mut line, commands, state) {
+        error!("{}", e);
+        output::fail();
+        return false;
+    }
+
+    match line.first().and_then(|first| commands.get(first.as_str())) {
+        Some(command) => {
+            // The padding shellfish puts around a command's output
+            outln!();
+            execute(command, state, line).await;
+            outln!();
+            false
+        }
+        None => {
+            // Shellfish says so itself, but it's a failure as far as `&&` is concerned
+            if line.first().is_some_and(|first| !matches!(first.as_str(), "help" | "quit" | "exit")) {
+                output::fail();
+            }
+            DefaultAsyncHandler().handle_async(line, commands, state, description).await
+        }
+    }
+}
+
+/// Runs a shell command, logging the error it returns unless it's already said what went wrong.
+/// Returns whether it succeeded.
+pub async fn execute(command: &Command<Session>, state: &mut Session, line: Vec<String>) -> bool {
+    let result = match command.command {
+        CommandType::Sync(command) => command(state, line),
+        CommandType::Async(command) => command(state, line).await,
+    };
+    match result {
+        Ok(()) => true,
+        Err(e) => {
+            if !e.is::<Failed>() {
+                error!("{}", e);
+            }
+            output::fail();
+            false
+        }
+    }
+}
+
+/// Replaces the first word of `line` with a device ID, if it names a device rather than a command.
+/// Words that don't match anything are left for shellfish to report.
+pub fn resolve_device(
+    line: &mut [String],
+    commands: &HashMap<&str, Command<Session>>,
+    session: &Session,
+) -> Result<(), ResolveError> {
+    let first = match line.first_mut() {
+        Some(first) => first,
+        None => return Ok(()),
+    };
+
+    if commands.contains_key(first.as_str()) || matches!(first.as_str(), "help" | "quit" | "exit") {
+        return Ok(());
+    }
+
+    match resolve::device(&session.rtu, &session.config, first) {
+        Ok(device) => *first = device.id.clone(),
+        Err(ResolveError::NotFound(_)) => {}
+        Err(e) => return Err(e),
+    }
+    Ok(())
+}
@@ -0,0 +1,108 @@
+//! `--dry-run`: checking commands without touching the bus
+//!
+//! In a dry run, commands are parsed, checked and resolved to a device like always, and then the
+//! CLI says what it would have sent and to which controller, and the bytes of each frame, instead
+//! of sending it. Nothing connects to a port: [`crate::boards::connect`] refuses to, so commands
+//! that only read fail too.
+use std::sync::atomic::{AtomicBool, Ordering};
+
+use brewdrivers::controllers::Controller;
+use brewdrivers::drivers::serial::Bytestring;
+use brewdrivers::model::Device;
+use brewdrivers::state::BinaryState;
+use log::info;
+
+use crate::{daemon, modbus};
+use crate::registry::{DeviceCommand, Value};
+
+static ENABLED: AtomicBool = AtomicBool::new(false);
+
+pub fn enable() {
+    ENABLED.store(true, Ordering::Relaxed);
+}
+
+/// Whether this is a dry run. In the daemon, that's up to the client.
+pub fn enabled() -> bool {
+    ENABLED.load(Ordering::Relaxed) || daemon::client().is_some_and(|client| client.request.dry_run)
+}
+
+/// Reports what a device command would have done
+pub fn report(device: &Device, cmd: &DeviceCommand, values: &[Value]) {
+    // Commands without a name read or switch the device's own relay, or read a temperature controller
+    let mut operation = match (cmd.name, device.conn.controller()) {
+        (Some(name), _) => name.to_string(),
+        (None, Controller::CN7500) => "read".to_string(),
+        (None, _) if values.is_empty() => format!("get_relay {}", device.conn.addr()),
+        (None, _) => format!("set_relay {}", device.conn.addr()),
+    };
+    for value in values {
+        operation.push(' ');
+        operation.push_str(&match value {
+            Value::State(state) => state.to_string(),
+            Value::Byte(byte) => byte.to_string(),
+            Value::Float(float) => float.to_string(),
+            Value::Word(word) => word.to_string(),
+        });
+    }
+
+    info!(
+        "Dry run: would send `{}` to {} controller {} on `{}` ({} baud, {}ms timeout)",
+        operation,
+        device.conn.controller(),
+        device.conn.controller_addr(),
+        device.conn.port(),
+        device.conn.baudrate(),
+        device.conn.timeout
+    );
+    match frames(device, cmd, values).as_slice() {
+        [] => {}
+        [frame] => info!("The frame would be `{}`", modbus::hex(frame)),
+        frames => info!("The frames would be {}", frames.iter().map(|frame| format!("`{}`", modbus::hex(frame))).collect::<Vec<_>>().join(", ")),
+    }
+    if cmd.destructive {
+        info!("`{}` is destructive, so it would ask to confirm first", operation);
+    }
+}
+
+/// The frames a device command sends, in order, as the drivers encode them. Empty for commands
+/// whose frames depend on what the board answers, like `probe`, `watch`, and listing or setting
+/// every relay on an STR1, which asks the board how many it has first.
+fn frames(device: &Device, cmd: &DeviceCommand, values: &[Value]) -> Vec<Vec<u8>> {
+    let cn = device.conn.controller_addr();
+    let relay = device.conn.addr();
+    // Every command with a state takes it first
+    let on = || values[0].state() == BinaryState::On;
+    let coil = |on: bool| if on { 0xFF00 } else { 0x0000 };
+    let str1 = |data: Vec<u8>| Bytestring::from(data).to_bytes();
+
+    match (device.conn.controller(), cmd.name) {
+        // The Waveshare boards read every relay at once, even for just one
+        (Controller::Waveshare, None | Some("list_all")) if values.is_empty() => vec![modbus::request(cn, 0x01, 0x00FF, 0x0001)],
+        (Controller::WaveshareV2, None | Some("list_all")) if values.is_empty() => vec![modbus::request(cn, 0x01, 0x0000, 0x0008)],
+        (Controller::Waveshare | Controller::WaveshareV2, None) => vec![modbus::request(cn, 0x05, relay as u16, coil(on()))],
+        (Controller::Waveshare, Some("set_all")) => vec![modbus::request(cn, 0x05, 0x00FF, if on() { 0xFFFF } else { 0x0000 })],
+        (Controller::WaveshareV2, Some("set_all")) => vec![modbus::request(cn, 0x05, 0x00FF, coil(on()))],
+        // The board is asked on the broadcast address, since its own might not be known
+        (Controller::Waveshare | Controller::WaveshareV2, Some("get_cn")) => vec![modbus::request(0x00, 0x03, 0x4000, 0x0001)],
+        (Controller::Waveshare | Controller::WaveshareV2, Some("set_cn")) => vec![modbus::request(cn, 0x06, 0x4000, values[0].byte() as u16)],
+        (Controller::Waveshare | Controller::WaveshareV2, Some("software_revision")) => vec![modbus::request(cn, 0x03, 0x8000, 0x0001)],
+        (Controller::STR1, None) if values.is_empty() => vec![str1(vec![0x07, 0x14, cn, relay, 0x01])],
+        (Controller::STR1, None) => vec![str1(vec![0x08, 0x17, cn, relay, 0x01, on() as u8])],
+        (Controller::STR1, Some("set_cn")) => vec![str1(vec![0x06, 0x01, cn, values[0].byte()])],
+        (Controller::CN7500, None) => vec![
+            modbus::request(cn, 0x03, 0x1000, 1),
+            modbus::request(cn, 0x03, 0x1001, 1),
+            modbus::request(cn, 0x01, 0x0814, 1),
+        ],
+        (Controller::CN7500, Some("pv")) => vec![modbus::request(cn, 0x03, 0x1000, 1)],
+        (Controller::CN7500, Some("sv")) => vec![modbus::request(cn, 0x03, 0x1001, 1)],
+        // The SV register holds tenths of a degree
+        (Controller::CN7500, Some("set")) => vec![modbus::request(cn, 0x06, 0x1001, (values[0].float() * 10.0) as u16)],
+        (Controller::CN7500, Some("is_running")) => vec![modbus::request(cn, 0x01, 0x0814, 1)],
+        (Controller::CN7500, Some("run")) => vec![modbus::request(cn, 0x05, 0x0814, 0xFF00)],
+        (Controller::CN7500, Some("stop")) => vec![modbus::request(cn, 0x05, 0x0814, 0x0000)],
+        // Celsius is the coil on
+        (Controller::CN7500, Some("degrees")) => vec![modbus::request(cn, 0x05, 0x0811, coil(values[0].word() == "C"))],
+        _ => vec![],
+    }
+}
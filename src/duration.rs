@@ -0,0 +1,99 @@
+//! Parsing and printing human friendly durations, like `90s`, `2m`, or `1h30m`
+use std::time::Duration;
+
+/// Parses a duration like `30s`, `2m`, `1h`, or `1h30m`. A bare number is read as seconds.
+///
+/// Returns `None` if the input isn't a valid duration, or is too long to count in seconds.
+pub fn parse(input: &str) -> Option<Duration> {
+    if input.is_empty() {
+        return None;
+    }
+
+    if let Ok(secs) = input.parse::<u64>() {
+        return Some(Duration::from_secs(secs));
+    }
+
+    let mut total: u64 = 0;
+    let mut number = String::new();
+    for c in input.chars() {
+        if c.is_ascii_digit() {
+            number.push(c);
+            continue;
+        }
+
+        let value = number.parse::<u64>().ok()?;
+        number.clear();
+        let secs = match c {
+            'h' => value.checked_mul(3600)?,
+            'm' => value.checked_mul(60)?,
+            's' => value,
+            _ => return None,
+        };
+        total = total.checked_add(secs)?;
+    }
+
+    // Trailing digits without a unit, like `1m30`
+    if !number.is_empty() {
+        return None;
+    }
+
+    Some(Duration::from_secs(total))
+}
+
+/// Formats a duration as `1h02m03s`, `4m05s` or `12s`
+pub fn format(duration: Duration) -> String {
+    let secs = duration.as_secs();
+    let (h, m, s) = (secs / 3600, (secs % 3600) / 60, secs % 60);
+    if h > 0 {
+        format!("{}h{:02}m{:02}s", h, m, s)
+    } else if m > 0 {
+        format!("{}m{:02}s", m, s)
+    } else {
+        format!("{}s", s)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_units() {
+        assert_eq!(parse("30s"), Some(Duration::from_secs(30)));
+        assert_eq!(parse("2m"), Some(Duration::from_secs(120)));
+        assert_eq!(parse("1h"), Some(Duration::from_secs(3600)));
+        assert_eq!(parse("1h30m"), Some(Duration::from_secs(5400)));
+        assert_eq!(parse("1m30s"), Some(Duration::from_secs(90)));
+    }
+
+    #[test]
+    fn bare_numbers_are_seconds() {
+        assert_eq!(parse("45"), Some(Duration::from_secs(45)));
+        assert_eq!(parse("0"), Some(Duration::ZERO));
+    }
+
+    #[test]
+    fn rejects_bad_durations() {
+        assert_eq!(parse(""), None);
+        assert_eq!(parse("m"), None);
+        assert_eq!(parse("1m30"), None);
+        assert_eq!(parse("5d"), None);
+        assert_eq!(parse("-5s"), None);
+        assert_eq!(parse("1.5m"), None);
+    }
+
+    #[test]
+    fn rejects_durations_that_overflow() {
+        assert_eq!(parse("99999999999999999h"), None);
+        assert_eq!(parse("999999999999999999m"), None);
+        assert_eq!(parse("18446744073709551615s1s"), None);
+        assert_eq!(parse("99999999999999999999"), None);
+    }
+
+    #[test]
+    fn formats() {
+        assert_eq!(format(Duration::from_secs(12)), "12s");
+        assert_eq!(format(Duration::from_secs(245)), "4m05s");
+        assert_eq!(format(Duration::from_secs(3723)), "1h02m03s");
+    }
+}
@@ -2,18 +2,28 @@ use std::fmt::Display;
 
 use brewdrivers::drivers::InstrumentError;
 
-pub mod cn7500;
-pub mod str1;
-pub mod waveshare;
-pub mod wavesharev2;
+use crate::output::Failed;
 
+pub mod probe;
+pub mod relay;
+pub mod temperature;
+
+
+/// What a handler returns. Whatever went wrong has been logged already, so this only says whether
+/// the command worked.
+pub type Outcome = Result<(), Failed>;
 
 /// Converts Result<T, InstrumentError> into the string form of the value or the error.
 /// This is used to process values as CLI output, so that errors will be reported but will not panic,
 /// and to reduce boilerplate.
-fn stringify<T: Display>(value: Result<T, InstrumentError>) -> String {
+fn stringify<T: Display>(value: &Result<T, InstrumentError>) -> String {
     match value {
         Ok(val) => format!("{}", val),
         Err(e) => format!("Error: {}", e)
     }
-}
\ No newline at end of file
+}
+
+/// A handler's outcome from a driver result that's been shown with [`stringify`]
+fn outcome<T>(value: &Result<T, InstrumentError>) -> Outcome {
+    value.as_ref().map(|_| ()).map_err(|_| Failed)
+}
@@ -0,0 +1,133 @@
+//! Finding the baud rate and timeout a device answers at
+//!
+//! A board whose baud rate was changed, or a `timeout` that's too tight, both just look like a
+//! board that won't connect. `probe` tries the controller at every baud rate it supports, with
+//! longer and longer timeouts, until one combination answers reliably.
+
+use std::time::{Duration, Instant};
+
+use log::{error, info};
+
+use brewdrivers::controllers::cn7500::CN7500_BAUDRATES;
+use brewdrivers::controllers::str1::STR1_BAUDRATES;
+use brewdrivers::controllers::waveshare::WAVESHARE_BAUDRATES;
+use brewdrivers::controllers::wavesharev2::WAVESHAREV2_BAUDRATES;
+use brewdrivers::controllers::Controller;
+use brewdrivers::model::Device;
+
+use crate::boards::{self, Board};
+use crate::output::Failed;
+use crate::{config, prompt, transport};
+use super::Outcome;
+
+/// The timeouts to try, in milliseconds. Every baud rate is tried at one timeout before moving on to the next.
+const TIMEOUTS: [u64; 6] = [20, 40, 80, 160, 320, 640];
+
+/// How many reads in a row a combination has to answer before it's trusted
+const READS: usize = 5;
+
+/// The baud rates a controller can be set to
+fn baudrates(controller: &Controller) -> &'static [usize] {
+    match controller {
+        Controller::STR1 => &STR1_BAUDRATES,
+        Controller::CN7500 => &CN7500_BAUDRATES,
+        Controller::Waveshare => &WAVESHARE_BAUDRATES,
+        Controller::WaveshareV2 => &WAVESHAREV2_BAUDRATES,
+    }
+}
+
+/// Connects and reads from the device a few times. Returns how long each read took, if they all answered.
+async fn try_settings(device: &Device) -> Option<Vec<Duration>> {
+    let _lock = boards::lock(device).await.ok()?;
+    let mut board = boards::connect_once(device).await.ok()?;
+    let mut times = Vec::new();
+
+    for _ in 0..READS {
+        let start = Instant::now();
+        let answered = match &mut board {
+            Board::Relay(board) => board.get_relay(device.conn.addr()).await.is_ok(),
+            Board::Temp(controller) => controller.pv().await.is_ok(),
+        };
+        if !answered {
+            return None;
+        }
+        times.push(start.elapsed());
+    }
+    Some(times)
+}
+
+pub(crate) async fn probe(device: &Device) -> Outcome {
+    let configured_baudrate = *device.conn.baudrate();
+
+    // The configured baud rate goes first, since it's the most likely
+    let mut rates = vec![configured_baudrate];
+    // A gateway's baud rate is set on the gateway, so only the timeout can be probed through it
+    if !transport::is_network(&device.conn.port()) {
+        rates.extend(baudrates(device.conn.controller()).iter().filter(|rate| **rate != configured_baudrate));
+    }
+
+    info!(
+        "Probing `{}` ({} controller {} on `{}`). This can take a while",
+        device.id,
+        device.conn.controller(),
+        device.conn.controller_addr(),
+        device.conn.port()
+    );
+
+    for timeout in TIMEOUTS {
+        info!("Trying {} baud rates with a {}ms timeout", rates.len(), timeout);
+        for baudrate in &rates {
+            let mut candidate = device.clone();
+            candidate.conn.baudrate = *baudrate;
+            candidate.conn.timeout = timeout;
+
+            if let Some(times) = try_settings(&candidate).await {
+                return found(device, *baudrate, timeout, &times).await;
+            }
+        }
+    }
+
+    error!(
+        "`{}` didn't answer at any baud rate, with timeouts up to {}ms. Check the wiring, the port, and that controller {} is the right address",
+        device.id,
+        TIMEOUTS[TIMEOUTS.len() - 1],
+        device.conn.controller_addr()
+    );
+    Err(Failed)
+}
+
+fn millis(duration: Duration) -> String {
+    format!("{:.1}ms", duration.as_secs_f64() * 1000.0)
+}
+
+/// Reports the working settings, and offers to save them if the config file's don't work
+async fn found(device: &Device, baudrate: usize, timeout: u64, times: &[Duration]) -> Outcome {
+    let average = times.iter().sum::<Duration>() / times.len() as u32;
+    let slowest = times.iter().max().copied().unwrap_or_default();
+    info!("`{}` answers at {} baud with a {}ms timeout", device.id, baudrate, timeout);
+    info!("Round trip: {} on average, {} at most, over {} reads", millis(average), millis(slowest), times.len());
+
+    if baudrate == *device.conn.baudrate() && timeout <= device.conn.timeout {
+        info!("The config file's settings ({} baud, {}ms) already work", device.conn.baudrate(), device.conn.timeout);
+        return Ok(());
+    }
+
+    // The shortest timeout that answered leaves no room for a busy bus, so save double that
+    let timeout = timeout * 2;
+    info!("The config file has {} baud with a {}ms timeout", device.conn.baudrate(), device.conn.timeout);
+    let question = format!("Save `baudrate: {}` and `timeout: {}` for `{}` to `{}`?", baudrate, timeout, device.id, config::file());
+    if !prompt::confirm(&question).await {
+        info!("The config file wasn't changed");
+        return Ok(());
+    }
+    match config::save_connection(config::file(), &device.id, baudrate, timeout) {
+        Ok(_) => {
+            info!("Saved. Restart the CLI to use the new settings");
+            Ok(())
+        }
+        Err(e) => {
+            error!("{}", e);
+            Err(Failed)
+        }
+    }
+}
@@ -0,0 +1,101 @@
+//! Contains the functionality of the relay board commands
+
+use log::{info, error};
+
+use brewdrivers::model::Device;
+use brewdrivers::state::BinaryState;
+
+use crate::{audit, prompt};
+use crate::boards::{self, RelayBoard};
+use crate::output::Failed;
+use super::{outcome, stringify, Outcome};
+
+/// Connects to the board, logging the error if it can't
+pub(crate) async fn connect(device: &Device) -> Result<Box<dyn RelayBoard>, Failed> {
+    match boards::relay_board(device).await {
+        Ok(board) => Ok(board),
+        Err(e) => {
+            error!("Couldn't connect to {} board with ID: {}\nError: {}", device.conn.controller(), device.id, e);
+            Err(Failed)
+        }
+    }
+}
+
+pub(crate) async fn get_relay(board: &mut dyn RelayBoard, relay_num: u8) -> Outcome {
+    let state = board.get_relay(relay_num).await;
+    info!("{}", stringify(&state));
+    outcome(&state)
+}
+
+pub(crate) async fn list_all(board: &mut dyn RelayBoard) -> Outcome {
+    let states = match board.get_all().await {
+        Ok(list) => list,
+        Err(e) => {
+            error!("{}", e);
+            return Err(Failed);
+        }
+    };
+
+    for (i, state) in states.iter().enumerate() {
+        info!("Relay {}: {}", i, state);
+    }
+    Ok(())
+}
+
+pub(crate) async fn set_relay(device: &Device, board: &mut dyn RelayBoard, relay_num: u8, new_state: BinaryState) -> Outcome {
+    let previous = board.get_relay(relay_num).await.ok().map(|state| state.to_string());
+    let result = board.set_relay(relay_num, new_state).await;
+    audit::record(device, "set_relay", previous, format!("relay {}: {}", relay_num, new_state), &result);
+    match result {
+        Ok(_) => info!("Ok!"),
+        Err(ref e) => error!("{}", e)
+    }
+    outcome(&result)
+}
+
+pub(crate) async fn set_all(device: &Device, board: &mut dyn RelayBoard, new_state: BinaryState) -> Outcome {
+    let previous = board.get_all().await.ok().map(|states| states.iter().map(|state| state.to_string()).collect::<Vec<_>>().join(", "));
+    if !prompt::confirm_change(device, "set_all", previous.as_deref(), &new_state.to_string()).await {
+        info!("Cancelled");
+        return Ok(());
+    }
+    let result = board.set_all(new_state).await;
+    audit::record(device, "set_all", previous, new_state.to_string(), &result);
+    match result {
+        Ok(_) => info!("Ok!"),
+        Err(ref e) => error!("{}", e)
+    }
+    outcome(&result)
+}
+
+pub(crate) async fn get_cn(board: &mut dyn RelayBoard) -> Outcome {
+    let cn = board.get_cn().await;
+    info!("{}", stringify(&cn));
+    outcome(&cn)
+}
+
+pub(crate) async fn set_cn(device: &Device, board: &mut dyn RelayBoard, new_cn: u8) -> Outcome {
+    // Not every board can report its number, so fall back to the configured one
+    let previous = board.get_cn().await.unwrap_or(device.conn.controller_addr()).to_string();
+    if !prompt::confirm_change(device, "set_cn", Some(&previous), &new_cn.to_string()).await {
+        info!("Cancelled");
+        return Ok(());
+    }
+    let result = board.set_cn(new_cn).await;
+    audit::record(device, "set_cn", Some(previous), new_cn.to_string(), &result);
+    match result {
+        Ok(_) => info!("Ok! Don't forget to update your config file and restart the CLI"),
+        Err(ref e) => {
+            error!("{}", e);
+            // It isn't retried, and the board may have taken the new number anyway
+            info!("The board may have changed its number anyway. Use `get_cn` to check");
+        }
+    }
+    outcome(&result)
+}
+
+pub(crate) async fn software_revision(board: &mut dyn RelayBoard) -> Outcome {
+    let revision = board.software_revision().await;
+    info!("{}", stringify(&revision));
+    outcome(&revision)
+}
@@ -0,0 +1,117 @@
+//! Contains the functionality of the temperature controller commands
+
+use chrono::Local;
+use brewdrivers::model::Device;
+use std::time::Duration;
+
+use log::{info, error};
+
+use brewdrivers::controllers::cn7500::Degree;
+
+use crate::audit;
+use crate::boards::{self, TempController};
+use crate::output::Failed;
+use super::{outcome, stringify, Outcome};
+
+/// Connects to the controller, logging the error if it can't
+pub(crate) async fn connect(device: &Device) -> Result<Box<dyn TempController>, Failed> {
+    match boards::temp_controller(device).await {
+        Ok(cn) => Ok(cn),
+        Err(err) => {
+            error!("Couldn't connect to {} with ID: {}\nError: {}", device.conn.controller(), device.id, err);
+            Err(Failed)
+        }
+    }
+}
+
+pub(crate) async fn get_all(cn: &mut dyn TempController) -> Outcome {
+    let (pv, sv, running) = (cn.pv().await, cn.sv().await, cn.is_running().await);
+    info!("{{ PV: {}, SV: {}, Running: {} }}", stringify(&pv), stringify(&sv), stringify(&running));
+    outcome(&pv).and(outcome(&sv)).and(outcome(&running))
+}
+
+pub(crate) async fn get_pv(cn: &mut dyn TempController) -> Outcome {
+    let pv = cn.pv().await;
+    info!("PV: {}", stringify(&pv));
+    outcome(&pv)
+}
+
+pub(crate) async fn get_sv(cn: &mut dyn TempController) -> Outcome {
+    let sv = cn.sv().await;
+    info!("SV: {}", stringify(&sv));
+    outcome(&sv)
+}
+
+pub(crate) async fn is_running(cn: &mut dyn TempController) -> Outcome {
+    let running = cn.is_running().await;
+    info!("Running: {}", stringify(&running));
+    outcome(&running)
+}
+
+pub(crate) async fn run(device: &Device, cn: &mut dyn TempController) -> Outcome {
+    let previous = running(cn.is_running().await.ok());
+    let result = cn.run().await;
+    audit::record(device, "run", previous, "running".to_string(), &result);
+    // cn.run() returns Ok(()) so we won't use stringify
+    match result {
+        Ok(_) => info!("Ok!"),
+        Err(ref e) => error!("{}", e)
+    }
+    outcome(&result)
+}
+
+pub(crate) async fn stop(device: &Device, cn: &mut dyn TempController) -> Outcome {
+    let previous = running(cn.is_running().await.ok());
+    let result = cn.stop().await;
+    audit::record(device, "stop", previous, "stopped".to_string(), &result);
+    // cn.stop() returns Ok(()) so we won't use stringify
+    match result {
+        Ok(_) => info!("Stopped!"),
+        Err(ref e) => error!("{}", e)
+    }
+    outcome(&result)
+}
+
+pub(crate) async fn set_sv(device: &Device, cn: &mut dyn TempController, new_sv: f64) -> Outcome {
+    let previous = cn.sv().await.ok().map(|sv| sv.to_string());
+    let result = cn.set_sv(new_sv).await;
+    audit::record(device, "set_sv", previous, new_sv.to_string(), &result);
+    match result {
+        Ok(_) => info!("Ok! Set to {}", new_sv),
+        Err(ref e) => error!("{}", e)
+    }
+    outcome(&result)
+}
+
+pub(crate) async fn set_degrees(device: &Device, cn: &mut dyn TempController, deg_mode: Degree) -> Outcome {
+    // The controller can't report its degree mode, so there's no previous state
+    let result = cn.set_degrees(deg_mode.clone()).await;
+    audit::record(device, "set_degrees", None, format!("{:?}", deg_mode), &result);
+    match result {
+        Ok(_) => info!("Degree mode set to {:?}", deg_mode),
+        Err(ref e) => error!("{}", e)
+    }
+    outcome(&result)
+}
+
+/// How the audit journal describes whether a controller is running
+fn running(state: Option<bool>) -> Option<String> {
+    state.map(|running| if running { "running" } else { "stopped" }.to_string())
+}
+
+pub(crate) async fn watch(device: &Device) -> Outcome {
+    info!("");
+    loop {
+        out!("\n{}\t", Local::now().format(crate::TIME_FORMAT));
+        // I don't know why but we have to reconnect every time here
+        match boards::temp_controller(device).await {
+            Ok(mut cn) => {
+                let (pv, sv, running) = (cn.pv().await, cn.sv().await, cn.is_running().await);
+                outln!("{{ PV: {}, SV: {}, Running: {} }}", stringify(&pv), stringify(&sv), stringify(&running));
+            },
+            Err(e) => error!("{}", e)
+        }
+        // Not a blocking sleep, so the daemon can stop watching when its client goes
+        tokio::time::sleep(Duration::from_secs(5)).await;
+    }
+}
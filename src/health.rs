@@ -0,0 +1,150 @@
+//! Checking that every device answers
+//!
+//! `health` contacts every configured device and reports whether it answered, how long a read took,
+//! and, for boards that can say, their firmware and the controller number they're set to. Ports are
+//! checked at the same time. Devices that share a port take turns, like they do in the poller.
+//!
+//! If anything fails, `NBC_cli exec health` exits with status 1, so systemd units and playbooks can
+//! gate on it.
+use std::collections::HashMap;
+use std::error::Error;
+use std::time::{Duration, Instant};
+
+use brewdrivers::model::{Device, RTU};
+use log::{error, info};
+use tokio::runtime::Handle;
+
+use crate::boards::{self, Board};
+use crate::output::Failed;
+use crate::session::Session;
+use crate::{poller, rtus, tables};
+
+/// What checking one device found
+#[derive(Debug, Clone, Default)]
+pub struct Check {
+    /// Why the device couldn't be reached, if it couldn't
+    pub error: Option<String>,
+    /// How long one read took
+    pub latency: Option<Duration>,
+    /// The software revision, for boards that report one
+    pub firmware: Option<String>,
+    /// The controller number the board says it's set to, for boards that report one
+    pub address: Option<u8>,
+}
+
+impl Check {
+    /// What's wrong with the device, if anything
+    pub fn problem(&self, device: &Device) -> Option<String> {
+        if let Some(e) = &self.error {
+            return Some(e.clone());
+        }
+        match self.address {
+            Some(address) if address != device.conn.controller_addr() => Some(format!(
+                "the board is set to controller number {}, but the config file has {}",
+                address,
+                device.conn.controller_addr()
+            )),
+            _ => None,
+        }
+    }
+}
+
+async fn check(device: &Device) -> Check {
+    let mut check = Check::default();
+    // Another RTU's daemon can only be asked for a reading
+    if rtus::remote(device).is_some() {
+        let start = Instant::now();
+        match boards::read(device).await {
+            Ok(_) => check.latency = Some(start.elapsed()),
+            Err(e) => check.error = Some(e.to_string()),
+        }
+        return check;
+    }
+    // Once, without retries, so the latency is a single round trip
+    let board = match boards::lock(device).await {
+        Ok(lock) => boards::connect_once(device).await.map(|board| (lock, board)),
+        Err(e) => Err(e),
+    };
+    let (_lock, board) = match board {
+        Ok(connected) => connected,
+        Err(e) => {
+            check.error = Some(e.to_string());
+            return check;
+        }
+    };
+
+    let start = Instant::now();
+    match board {
+        Board::Relay(mut board) => {
+            if let Err(e) = board.get_relay(device.conn.addr()).await {
+                check.error = Some(e.to_string());
+                return check;
+            }
+            check.latency = Some(start.elapsed());
+            // Only some boards support these, so failing them isn't a problem
+            check.firmware = board.software_revision().await.ok();
+            check.address = board.get_cn().await.ok();
+        }
+        Board::Temp(mut controller) => {
+            if let Err(e) = controller.pv().await {
+                check.error = Some(e.to_string());
+                return check;
+            }
+            check.latency = Some(start.elapsed());
+        }
+    }
+    check
+}
+
+/// Checks every device in the RTU, returning what was found in the same order as the config file.
+///
+/// The relay board drivers block while they wait on the serial port, so each port is checked on a
+/// blocking thread of its own.
+pub async fn run(rtu: &RTU) -> Vec<(Device, Check)> {
+    let handle = Handle::current();
+    let ports: Vec<_> = poller::by_port(rtu)
+        .into_iter()
+        .map(|devices| {
+            let handle = handle.clone();
+            tokio::task::spawn_blocking(move || {
+                devices.into_iter().map(|device| (device.id.clone(), handle.block_on(check(&device)))).collect::<Vec<_>>()
+            })
+        })
+        .collect();
+
+    let mut checks: HashMap<String, Check> = HashMap::new();
+    for port in ports {
+        if let Ok(results) = port.await {
+            checks.extend(results);
+        }
+    }
+
+    rtu.devices
+        .iter()
+        .map(|device| {
+            let check = checks.remove(&device.id).unwrap_or_else(|| Check {
+                error: Some("the check didn't finish".to_string()),
+                ..Check::default()
+            });
+            (device.clone(), check)
+        })
+        .collect()
+}
+
+/// The `health` shell command
+pub async fn command(session: &mut Session, _: Vec<String>) -> Result<(), Box<dyn Error>> {
+    let checks = run(&session.rtu).await;
+    outln!("{}", tables::health::render(&checks));
+
+    let problems: Vec<(&Device, String)> = checks.iter().filter_map(|(device, check)| check.problem(device).map(|p| (device, p))).collect();
+    for (device, problem) in &problems {
+        error!("`{}`: {}", device.id, problem);
+    }
+
+    if !problems.is_empty() {
+        error!("{} of {} devices failed the health check", problems.len(), checks.len());
+        return Err(Failed.into());
+    }
+    info!("All {} devices are healthy", checks.len());
+    Ok(())
+}
@@ -0,0 +1,29 @@
+//! Notifying the brewer: the terminal bell and user configured shell commands, like the `alarm_hook`
+use log::{error, warn};
+
+/// Rings the terminal bell
+pub fn bell() {
+    out!("\x07");
+}
+
+/// Runs a hook with `sh -c` in the background. `env` is passed to the hook as environment variables.
+pub fn run(hook: &str, env: &[(&str, String)]) {
+    let child = tokio::process::Command::new("sh")
+        .arg("-c")
+        .arg(hook)
+        .envs(env.iter().map(|(k, v)| (k, v)))
+        .spawn();
+
+    match child {
+        Ok(mut child) => {
+            tokio::spawn(async move {
+                match child.wait().await {
+                    Ok(status) if !status.success() => warn!("Hook exited with {}", status),
+                    Ok(_) => {}
+                    Err(e) => error!("Hook failed: {}", e),
+                }
+            });
+        }
+        Err(e) => error!("Couldn't run hook `{}`: {}", hook, e),
+    }
+}
@@ -0,0 +1,121 @@
+//! Locking serial ports, so nothing talks over anything else on the bus
+//!
+//! Two CLIs on the same RS-485 port (or the dashboard and an alarm check in one CLI) would
+//! interleave their frames and corrupt both. Every connection takes an advisory lock on its port
+//! first, and holds it until it's dropped. Locks are lock files in `/run/lock` (or the temp
+//! directory, if that isn't writable), like `/run/lock/nbc_cli_dev_ttyUSB0.lock`, holding the PID
+//! and user of whoever has it. The OS releases them if the CLI dies.
+//!
+//! A port that's in use is waited on for `cli.lock_timeout` milliseconds, or not at all with
+//! `--no-wait`.
+use std::fs::{File, OpenOptions, Permissions, TryLockError};
+use std::io::{Read, Seek, SeekFrom, Write};
+use std::os::unix::fs::PermissionsExt;
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{Duration, Instant};
+
+use tokio::time::sleep;
+
+use crate::audit;
+
+/// How long to wait for a port, in milliseconds
+static TIMEOUT: AtomicU64 = AtomicU64::new(5000);
+
+/// How often a locked port is checked while waiting
+const RETRY: Duration = Duration::from_millis(20);
+
+/// Sets how long to wait for a port that's in use. 0 fails straight away.
+pub fn set_timeout(millis: u64) {
+    TIMEOUT.store(millis, Ordering::Relaxed);
+}
+
+/// A lock on a port. The port is unlocked when this is dropped.
+#[derive(Debug)]
+pub struct PortLock {
+    _file: File,
+}
+
+/// Opens the lock file for a port, in `/run/lock` if it can, or the temp directory if it can't
+fn open(port: &str) -> Result<File, String> {
+    let name = format!("nbc_cli{}.lock", port.replace('/', "_"));
+    let mut last_error = None;
+    for dir in [PathBuf::from("/run/lock"), std::env::temp_dir()] {
+        let path = dir.join(&name);
+        match OpenOptions::new().read(true).write(true).create(true).truncate(false).open(&path) {
+            Ok(file) => {
+                // Whoever uses the port next might be someone else
+                let _ = file.set_permissions(Permissions::from_mode(0o666));
+                return Ok(file);
+            }
+            Err(e) => last_error = Some(format!("Couldn't lock `{}` with `{}`: {}", port, path.display(), e)),
+        }
+    }
+    Err(last_error.unwrap_or_default())
+}
+
+/// Who holds a lock, from its lock file
+fn holder(file: &mut File) -> String {
+    let mut contents = String::new();
+    let _ = file.seek(SeekFrom::Start(0)).and_then(|_| file.read_to_string(&mut contents));
+    let mut fields = contents.split_whitespace();
+    match (fields.next().and_then(|pid| pid.parse::<u32>().ok()), fields.next()) {
+        (Some(pid), _) if pid == std::process::id() => "another task in this CLI".to_string(),
+        (Some(pid), Some(user)) => format!("PID {} ({})", pid, user),
+        _ => "another program".to_string(),
+    }
+}
+
+/// Locks a port, waiting for it if it's in use. The wait sleeps between tries, so other tasks
+/// carry on in the meantime.
+pub async fn acquire(port: &str) -> Result<PortLock, String> {
+    let mut file = open(port)?;
+    let timeout = Duration::from_millis(TIMEOUT.load(Ordering::Relaxed));
+    let start = Instant::now();
+    loop {
+        match file.try_lock() {
+            Ok(()) => break,
+            Err(TryLockError::WouldBlock) if start.elapsed() < timeout => sleep(RETRY).await,
+            Err(TryLockError::WouldBlock) if timeout.is_zero() => return Err(format!("`{}` is in use by {}", port, holder(&mut file))),
+            Err(TryLockError::WouldBlock) => {
+                return Err(format!(
+                    "`{}` is in use by {}. Gave up after waiting {}ms",
+                    port,
+                    holder(&mut file),
+                    timeout.as_millis()
+                ))
+            }
+            Err(TryLockError::Error(e)) => return Err(format!("Couldn't lock `{}`: {}", port, e)),
+        }
+    }
+
+    let _ = file.set_len(0).and_then(|_| writeln!(file, "{} {}", std::process::id(), audit::user()));
+    Ok(PortLock { _file: file })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn tasks_in_one_cli_take_turns_with_a_port() {
+        let port = format!("/test/nbc_cli_locks_{}", std::process::id());
+        let first = acquire(&port).await.unwrap();
+
+        set_timeout(0);
+        let error = acquire(&port).await.unwrap_err();
+        assert_eq!(error, format!("`{}` is in use by another task in this CLI", port));
+
+        // The test runtime has one thread, so the second task only gets the port if its wait lets
+        // the first carry on and unlock it
+        set_timeout(2000);
+        let second = tokio::spawn({
+            let port = port.clone();
+            async move { acquire(&port).await.map(|_| Instant::now()) }
+        });
+        sleep(Duration::from_millis(100)).await;
+        let unlocked = Instant::now();
+        drop(first);
+        assert!(second.await.unwrap().unwrap() >= unlocked);
+    }
+}
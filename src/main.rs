@@ -1,27 +1,59 @@
 #![allow(non_snake_case)]
-use std::convert::TryFrom;
-use std::{error::Error};
+use std::{collections::HashMap, error::Error, time::Duration};
 
-use brewdrivers::state::BinaryState;
-use env_logger::Env;
-use log::{error, info};
+use log::{error, info, warn};
 use shellfish::{Command, Shell, async_fn, app::App};
 use chrono::Local;
 
-use brewdrivers::controllers::*;
-use brewdrivers::controllers::cn7500::Degree;
-use brewdrivers::model::{RTU, Device};
 
-mod tables;
+#[macro_use]
+mod output;
+
+mod access;
+mod alarms;
+mod aliases;
+mod audit;
+mod boards;
+mod config;
+mod daemon;
+mod dispatch;
+mod dry_run;
+mod duration;
 mod handlers;
+mod health;
+mod hooks;
+mod locks;
+mod modbus;
+mod permissions;
+mod poller;
+mod prompt;
+mod raw;
+mod recipe;
+mod registry;
+mod replay;
+mod repl;
+mod resolve;
+mod retry;
+mod rtus;
+mod session;
+mod suggest;
+mod tables;
+mod timers;
+mod trace;
+mod transport;
 
-const TIME_FORMAT: &'static str = "%F %H:%M:%S";
+use config::CliConfig;
+use dispatch::Dispatcher;
+use output::Failed;
+use session::Session;
+
+const TIME_FORMAT: &str = "%F %H:%M:%S";
 
 
 #[tokio::main]
 async fn main() {
     // Initialize logging
-    env_logger::Builder::from_env(Env::default().default_filter_or("info")).format_timestamp(None).init();
+    output::init_logging();
 
     // Connect the command line arguments
     let mut args: Vec<String> = std::env::args().collect();
@@ -29,6 +61,29 @@ async fn main() {
     // if this is true, the program will parse the given CLI arguments and use those as a command.
     // If it's false, the shell will be opened.
     let mut run_exec = false;
+    // If this is true, the program runs the daemon (see `daemon.rs`)
+    let mut run_daemon = false;
+
+    // `--config <file>` uses another config file instead of the default
+    let config_file = take_flag(&mut args, "--config").unwrap_or_else(|| brewdrivers::CONFIG_FILE.to_string());
+    // `--trace-serial <file>` traces every port to that file
+    let trace_file = take_flag(&mut args, "--trace-serial");
+    // `--replay-serial <file>` replaces the ports in a trace with fake devices that replay it
+    let replay_file = take_flag(&mut args, "--replay-serial");
+    // `--yes` answers every confirmation with yes, for `exec` mode and scripts
+    if take_switch(&mut args, "--yes") {
+        prompt::assume_yes();
+    }
+    // `--no-wait` fails straight away if a port is in use, instead of waiting for it
+    let no_wait = take_switch(&mut args, "--no-wait");
+    // `--read-only` only allows commands that don't change anything
+    let read_only = take_switch(&mut args, "--read-only");
+    // `--dry-run` checks commands and says what they'd send, without touching the bus
+    if take_switch(&mut args, "--dry-run") {
+        dry_run::enable();
+    }
+    // `--local` runs commands here, even if a daemon is running
+    let local = take_switch(&mut args, "--local");
     
     // If the first arg is `exec`, then we want to run the application
     // with the provided command, not open the shell.
@@ -36,364 +91,414 @@ async fn main() {
         // If we found arg1 to be exec, set a flag and remove `exec` from the args
         // So that we can parse the commands properly
         run_exec = arg1 == "exec";
+        run_daemon = arg1 == "daemon";
         args.remove(1);
     }
 
+    // Load the CLI's own settings from the config file
+    let config = match CliConfig::load(&config_file) {
+        Ok(config) => config,
+        Err(e) => {
+            error!("Couldn't read the `cli` section of the config file: {}", e);
+            std::process::exit(1);
+        }
+    };
+    config::set_file(&config_file);
+    // The daemon works out whether each client is read-only itself
+    access::init(read_only, config.read_only && !run_daemon);
+    locks::set_timeout(if no_wait { 0 } else { config.lock_timeout });
+    if let Err(e) = permissions::init(&config) {
+        error!("{}", e);
+        std::process::exit(1);
+    }
+    let mode = match (run_exec, run_daemon) {
+        (true, _) => audit::Mode::Exec,
+        (_, true) => audit::Mode::Api,
+        _ => audit::Mode::Shell,
+    };
+    audit::init(config.audit_file.as_deref(), mode);
+
+    // The daemon runs each client's commands with the client's aliases already expanded
+    if !run_daemon {
+        aliases::load();
+    }
+
+    let general = general_commands();
+    registry::set_general(general.keys().copied());
+
+    // With a daemon running, the CLI is its client, and `exec` doesn't need anything else
+    let client = !run_daemon && !local && trace_file.is_none() && replay_file.is_none() && daemon::connect(&config).await;
+    let chain = match repl::chain_args(args.get(1..).unwrap_or_default()) {
+        Ok(chain) => chain,
+        Err(e) => {
+            error!("{}", e);
+            std::process::exit(1);
+        }
+    };
+    // `sleep` is run here by `forward`, but the rest of the local commands need the shell
+    if client && run_exec && !chain.is_empty() && !chain.iter().any(|(_, line)| dispatch::is_local(line) && line[0] != "sleep") {
+        std::process::exit(dispatch::forward(chain).await);
+    }
 
     // Load the RTU Digital Twin from the config file
-    // None => use brewdrivers default
-    let mut rtu = match RTU::generate(None) {
+    let mut rtu = match config::rtu(&config_file) {
         Ok(rtu) => rtu,
         Err(e) => {
             error!("Couldn't deserialize config file: {}", e);
             std::process::exit(1);
         }
     };
+    // And the devices on any other RTUs, namespaced
+    for warning in rtus::load(&mut rtu, &config).await {
+        warn!("{}", warning);
+    }
+
+    for warning in retry::init(&config.retry) {
+        warn!("{}", warning);
+    }
+
+    if let Some(path) = replay_file {
+        match replay::start(&path) {
+            Ok(ports) => info!("Replaying `{}` on {}", path, ports.join(", ")),
+            Err(e) => {
+                error!("{}", e);
+                std::process::exit(1);
+            }
+        }
+    }
+
+    if let Some(path) = trace_file {
+        if let Err(e) = trace::save_to(&path) {
+            error!("{}", e);
+            std::process::exit(1);
+        }
+        trace::start_all(&rtu);
+    }
 
     // Copy a list of device ids for use later
     let device_ids = &rtu.devices.iter().map(|dev| dev.id.clone() ).collect::<Vec<String>>();
     
     // Create a shell
-    let mut shell = Shell::new_async(rtu.clone(), format!("🍺 ==> "));
-
-    // Add a few basic commands
-    // this one lists the available commands, dynamically generated from the RTU configuration
-    shell.commands.insert(
-        "commands",
-        Command::new("Lists all commands".to_string(), |_, _| {
-            info!("Commands are run with a device ID, not the device name. Some commands affect the controller (like STR1.set_cn). Those commands can be run from any device configured to that controller.");
-            println!("{}", tables::commands::render());
-            Ok(())
-        })
-    );
+    let mut shell = Shell::new_with_async_handler(Session::new(rtu, config.clone()), "🍺 ==> ".to_string(), Dispatcher);
 
-    // This prints a list of connected devices
-    shell.commands.insert(
-        "devices",
-        Command::new("Lists all connected devices".to_string(), devices)
-    );
-
-    shell.commands.insert(
-        "time",
-        Command::new("Prints the current timestamp".to_string(), |_, _| {
-            println!("{}", Local::now().format("%F %H:%M:%S"));
-            Ok(())
-        })
-    );
-
-    shell.commands.insert(
-        "dashboard",
-        Command::new_async("Starts the device dashboard".to_string(), async_fn!(RTU, dashboard))
-    );
+    shell.commands.extend(general);
 
     // For each device, add that devices id as the command
     for device_id in device_ids {
         shell.commands.insert(
-            &device_id,
-            Command::new_async(format!("operations for {}", device_id), async_fn!(RTU, device_ops))
+            device_id,
+            Command::new_async(format!("operations for {}", device_id), async_fn!(Session, device_ops))
         );
     }
 
     
     
-    // Run either the cli or the shell
-    if run_exec {
+    // Run either the daemon, the cli, or the shell
+    if run_daemon {
+        info!("Navasota Brewing Company -- RTU CLI Version {} daemon", env!("CARGO_PKG_VERSION"));
+        info!("RTU config built successfully from file `{}`", config_file);
+        for warning in resolve::check(&shell.state.rtu, &shell.state.config) {
+            warn!("{}", warning);
+        }
+        alarms::spawn_poller(shell.state.alarms.clone(), shell.state.rtu.clone(), shell.state.config.clone());
+        let commands = shell.commands.iter().map(|(name, cmd)| (name.to_string(), cmd.clone())).collect();
+        timers::spawn_ticker(shell.state.clone(), commands);
+        let commands = shell.commands.iter().map(|(name, cmd)| (name.to_string(), cmd.clone())).collect();
+        if let Err(e) = daemon::serve(shell.state, commands).await {
+            error!("{}", e);
+            std::process::exit(1);
+        }
+        trace::stop_all();
+        transport::stop_all();
+        replay::stop_all();
+    } else if run_exec {
         // CLI
         let mut app = App::try_from_async(shell).unwrap();
         app.handler.proj_name = Some(String::from("nbc_cli"));
         app.load_cache().unwrap();
-        app.run_vec_async(args).await.unwrap();
+        // The cache only holds the RTU
+        app.state.config = config;
+        let (quit, ok) = dispatch::run_chain(&Dispatcher, chain, &app.commands, &mut app.state, &app.description).await;
+        // Shellfish saves the RTU to its cache, or deletes the cache after `quit`
+        let mut line = vec![args[0].clone()];
+        if quit {
+            line.push("quit".to_string());
+        }
+        app.run_vec_async(line).await.unwrap();
+        trace::stop_all();
+        transport::stop_all();
+        replay::stop_all();
+        if !ok {
+            std::process::exit(1);
+        }
     } else {
         // Run the shell
         info!("Navasota Brewing Company -- RTU CLI Version {}", env!("CARGO_PKG_VERSION"));
-        info!("RTU config built successfully from file `{}`", brewdrivers::CONFIG_FILE);
+        info!("RTU config built successfully from file `{}`", config_file);
         info!("Start the CLI with `RUST_LOG=trace NBC_cli` for full logging output");
-        devices(&mut rtu, vec![]).unwrap();
-        println!("Prost!");
-        match shell.run_async().await {
+        if access::read_only() {
+            warn!("This terminal is read-only. Devices can be watched but not changed. Use `unlock` to switch to write mode");
+        }
+        if dry_run::enabled() {
+            warn!("This is a dry run. Commands are checked and shown, but nothing is sent to the devices");
+        }
+        devices(&mut shell.state, vec![]).unwrap();
+        for warning in resolve::check(&shell.state.rtu, &shell.state.config) {
+            warn!("{}", warning);
+        }
+        // The daemon has its own alarms and timers
+        if let Some(socket) = daemon::socket() {
+            info!("Connected to the daemon at `{}`. Commands run there. Start the CLI with `--local` to run them here", socket.display());
+        } else {
+            alarms::spawn_poller(shell.state.alarms.clone(), shell.state.rtu.clone(), shell.state.config.clone());
+            timers::spawn_ticker(
+                shell.state.clone(),
+                shell.commands.iter().map(|(name, cmd)| (name.to_string(), cmd.clone())).collect()
+            );
+        }
+        outln!("Prost!");
+        match repl::run(&mut shell).await {
             Ok(_) => {},
             Err(e) => error!("Error: {}", e)
         }
+        trace::stop_all();
+        transport::stop_all();
+        replay::stop_all();
     }
 
 }
 
-fn devices(rtu: &mut RTU, _: Vec<String>) -> Result<(), Box<dyn Error>> {
-    println!("{}", tables::devices::render(&rtu));
-    Ok(())
-}
-
-async fn device_ops(rtu: &mut RTU, args: Vec<String>) -> Result<(), Box<dyn Error>> {
-    let device_id = args.get(0).expect("Arg not provided, this shouldn't be possible");
+/// The commands that aren't run on a device, by name
+fn general_commands() -> HashMap<&'static str, Command<Session>> {
+    let mut commands = HashMap::new();
 
-    if let Some(dev) = rtu.devices.iter().find(|dev| dev.id == *device_id ) {
-        match dev.conn.controller() {
-            Controller::STR1 => handle_str1(dev, args).await,
-            Controller::CN7500 => handle_cn7500(dev, args).await,
-            Controller::Waveshare => handle_ws(dev, args).await,
-            Controller::WaveshareV2 => handle_ws2(dev, args).await,
-        }
-    }
+    // Add a few basic commands
+    // this one lists the available commands, dynamically generated from the RTU configuration
+    commands.insert(
+        "commands",
+        Command::new("Lists all commands".to_string(), |_, _| {
+            info!("Devices can be addressed by ID, by name (spaces as underscores), or by a role from the `cli.roles` config section. Some commands affect the controller (like STR1.set_cn). Those commands can be run from any device configured to that controller.");
+            outln!("{}", tables::commands::render());
+            Ok(())
+        })
+    );
 
-    Ok(())
-}
+    // This prints a list of connected devices
+    commands.insert(
+        "devices",
+        Command::new("Lists all connected devices".to_string(), devices)
+    );
 
-async fn handle_ws(device: &Device, args: Vec<String>) {
-    use handlers::waveshare as ws;
-    let mut ws = match Waveshare::try_from(device) {
-        Ok(ws) => ws,
-        Err(e) => {
-            error!("Couldn't connect to Waveshare: {}", e);
-            return;
-        }
-    };
+    commands.insert(
+        "time",
+        Command::new("Prints the current timestamp".to_string(), |_, _| {
+            outln!("{}", Local::now().format("%F %H:%M:%S"));
+            Ok(())
+        })
+    );
 
+    commands.insert(
+        "dashboard",
+        Command::new_async("Starts the device dashboard".to_string(), async_fn!(Session, dashboard))
+    );
 
-    if args.len() == 1 {
-        // No arguments
+    commands.insert(
+        "health",
+        Command::new_async("Checks that every device answers".to_string(), async_fn!(Session, health::command))
+    );
 
-        ws::get_relay(&mut ws, device.conn.addr());
-    }
+    commands.insert(
+        "stats",
+        Command::new("Shows how operations on each device have gone".to_string(), stats)
+    );
 
-    if args.len() == 2 {
-        // 1 argument
-        if let Some(arg1) = args.get(1) {
-            if let Ok(state) = arg1.parse::<BinaryState>() {
-                ws::set_relay(&mut ws, device.conn.addr(), state);
-                return;
-            }
+    commands.insert(
+        "trace",
+        Command::new("Records the frames sent and received on serial ports".to_string(), trace::command)
+    );
 
-            match arg1.as_str() {
-                "list_all" => ws::list_all(&mut ws),
-                "get_cn" => ws::get_cn(&mut ws),
-                "software_revision" => ws::software_revision(&mut ws),
-                _ => error!("Argument `{}` not found, or you provided the wrong number of arguments", arg1)
-            }
-            
-            
-        }
-    }
+    commands.insert(
+        "raw",
+        Command::new_async("Reads and writes registers, or sends bytes, for debugging".to_string(), async_fn!(Session, raw::command))
+    );
 
-    if args.len() == 3 {
-        // 2 arguments
-
-        if let (Some(arg1), Some(arg2)) = (args.get(1), args.get(2)) {
-            match arg1.as_str() {
-                "set_all" => {
-                    match arg2.parse::<BinaryState>() {
-                        Ok(state) => ws::set_all(&mut ws, state),
-                        Err(e) => error!("{}", e)
-                    }
-                },
-                "set_cn" => {
-                    match arg2.parse::<u8>() {
-                        Ok(new_cn) => ws::set_cn(&mut ws, new_cn),
-                        Err(e) => error!("couldn't parse controller number (0-254): {}", e)
-                    }
-                },
-                _ => error!("Argument `{}` not found, or you provided the wrong number of arguments", arg1)
-            }
-        }
-    }
+    commands.insert(
+        "audit",
+        Command::new("Shows the journal of changes made to devices".to_string(), audit::command)
+    );
 
-}
+    commands.insert(
+        "unlock",
+        Command::new_async("Switches a read-only terminal to write mode, with the PIN".to_string(), async_fn!(Session, access::unlock))
+    );
 
-async fn handle_ws2(device: &Device, args: Vec<String>) {
-    use handlers::wavesharev2 as ws2;
-    let mut ws = match WaveshareV2::try_from(device) {
-        Ok(ws) => ws,
-        Err(e) => {
-            error!("Couldn't connect to Waveshare: {}", e);
-            return;
-        }
-    };
+    commands.insert(
+        "lock",
+        Command::new("Switches to read-only mode".to_string(), access::lock)
+    );
 
+    commands.insert(
+        "alias",
+        Command::new("Adds and lists aliases for one or more commands".to_string(), aliases::command)
+    );
 
-    if args.len() == 1 {
-        // No arguments
+    commands.insert(
+        "unalias",
+        Command::new("Removes an alias".to_string(), aliases::unalias)
+    );
 
-        ws2::get_relay(&mut ws, device.conn.addr());
-    }
+    commands.insert(
+        "sleep",
+        Command::new_async("Waits, between commands in a chain".to_string(), async_fn!(Session, dispatch::sleep))
+    );
 
-    if args.len() == 2 {
-        // 1 argument
-        if let Some(arg1) = args.get(1) {
-            if let Ok(state) = arg1.parse::<BinaryState>() {
-                ws2::set_relay(&mut ws, device.conn.addr(), state);
-                return;
-            }
+    commands.insert(
+        "alarm",
+        Command::new("Adds, lists, acknowledges, and clears alarms".to_string(), alarms::command)
+    );
 
-            match arg1.as_str() {
-                "list_all" => ws2::list_all(&mut ws),
-                "get_cn" => ws2::get_cn(&mut ws),
-                "software_revision" => ws2::software_revision(&mut ws),
-                _ => error!("Argument `{}` not found, or you provided the wrong number of arguments", arg1)
-            }
-            
-            
-        }
-    }
+    commands.insert(
+        "recipe",
+        Command::new_async("Loads a BeerXML/BeerJSON recipe and runs its brew day plan".to_string(), async_fn!(Session, recipe::command))
+    );
 
-    if args.len() == 3 {
-        // 2 arguments
-
-        if let (Some(arg1), Some(arg2)) = (args.get(1), args.get(2)) {
-            match arg1.as_str() {
-                "set_all" => {
-                    match arg2.parse::<BinaryState>() {
-                        Ok(state) => ws2::set_all(&mut ws, state),
-                        Err(e) => error!("{}", e)
-                    }
-                },
-                "set_cn" => {
-                    match arg2.parse::<u8>() {
-                        Ok(new_cn) => ws2::set_cn(&mut ws, new_cn),
-                        Err(e) => error!("Error, couldn't parse controller number (0-254): {}", e)
-                    }
-                },
-                _ => error!("Argument `{}` not found, or you provided the wrong number of arguments", arg1)
-            }
-        }
-    }
+    commands.insert(
+        "timer",
+        Command::new_async("Adds, lists, and cancels brew day timers".to_string(), async_fn!(Session, timers::command))
+    );
 
+    commands
 }
 
-async fn handle_str1(device: &Device, args: Vec<String>) {
-    use handlers::str1 as s;
-    let mut str1 = match STR1::try_from(device) {
-        Ok(str1) => str1,
-        Err(err) => {
-            error!("Couldn't connect to STR1 board with ID: {}\nError: {}", device.id, err);
-            return;
+/// Removes `switch` from the arguments, returning whether it was there
+fn take_switch(args: &mut Vec<String>, switch: &str) -> bool {
+    match args.iter().position(|arg| arg == switch) {
+        Some(i) => {
+            args.remove(i);
+            true
         }
-    };
-    
-
-
-    if args.len() == 1 {
-        s::get_relay(&mut str1, device.conn.addr());
+        None => false,
     }
+}
 
-    if args.len() == 2 {
-        if let Some(arg1) = args.get(1) {
-            if let Ok(state) = arg1.parse::<BinaryState>() {
-                s::set_relay(&mut str1, device.conn.addr(), state);
-                return;
-            }
-            
-            match arg1.as_str() {
-                "list_all" => s::list_all(&mut str1),
-                _ => error!("Argument `{}` not found, or you provided the wrong number of arguments", arg1)
-            }
-        }
+/// Removes `flag` and the value after it from the arguments, returning the value
+fn take_flag(args: &mut Vec<String>, flag: &str) -> Option<String> {
+    let i = args.iter().position(|arg| arg == flag)?;
+    if i + 1 >= args.len() {
+        error!("`{}` needs a file", flag);
+        std::process::exit(1);
     }
+    let value = args.remove(i + 1);
+    args.remove(i);
+    Some(value)
+}
 
-    if args.len() == 3 {
-        if let (Some(arg1), Some(arg2)) = (args.get(1), args.get(2)) {
-            match arg1.as_str() {
-                "set_cn" => {
-                    match arg2.parse::<u8>() {
-                        Ok(new_cn) => s::set_cn(&mut str1, new_cn),
-                        Err(e) => error!("Couldn't parse new controller number (0-255): {}", e)
-                    }
-                },
-                _ => error!("Argument `{}` not found, or you provided the wrong number of arguments", arg1)
+fn devices(session: &mut Session, _: Vec<String>) -> Result<(), Box<dyn Error>> {
+    let sections = rtus::sections(&session.rtu);
+    match sections.as_slice() {
+        [(_, devices)] => outln!("{}", tables::devices::render("Configured Devices", devices)),
+        _ => {
+            for (rtu, devices) in &sections {
+                outln!("{}", tables::devices::render(&format!("{} Devices", rtu), devices));
             }
         }
     }
-
-    if args.len() > 3 {
-        error!("Too many arguments ({}) provided: {:?}", args.len(), args);
-    }
+    Ok(())
 }
 
-async fn handle_cn7500(device: &Device, args: Vec<String>) {
-    // bring in all the CN7500
-    use handlers::cn7500 as c;
-    // CN7500 doesn't implement TryFrom<&Device> so we have to do it manually
-    let port = device.conn.port();
-    let connection = CN7500::connect(
-        device.conn.controller_addr(), 
-        &port,
-        *device.conn.baudrate() as u64,
-        device.conn.timeout()
-    );
-
-    let mut cn = match connection.await {
-        Ok(cn) => cn,
-        Err(err) => {
-            error!("Couldn't connect to CN7500 with ID: {}\nError: {}", device.id, err);
-            return;
+fn stats(session: &mut Session, args: Vec<String>) -> Result<(), Box<dyn Error>> {
+    match args.get(1).map(|s| s.as_str()) {
+        None => outln!("{}", tables::stats::render(&session.rtu, &retry::stats())),
+        Some("reset") => {
+            retry::reset_stats();
+            info!("Stats reset");
         }
-    };
-    
-
-
-    if args.len() == 1 {
-        // 0 argument commands
-
-        c::get_all(&mut cn).await;
-    }
-    
-    if args.len() == 2 {
-        // 1 arg commands
-        if let Some(arg1) = args.get(1) {
-            match arg1.as_str() {
-                "pv" => c::get_pv(&mut cn).await,
-                "sv" => c::get_sv(&mut cn).await,
-                "is_running" => c::is_running(&mut cn).await,
-                "run" => c::run(&mut cn).await,
-                "stop" => c::stop(&mut cn).await,
-                "watch" => c::watch(&device).await,
-                _ => error!("Argument {:?} not found, or you provided the wrong number of arguments", arg1)
-            }
+        Some(other) => {
+            error!("Unknown stats command `{}`. Use `stats` or `stats reset`", other);
+            return Err(Failed.into());
         }
     }
+    Ok(())
+}
+
+async fn device_ops(session: &mut Session, args: Vec<String>) -> Result<(), Box<dyn Error>> {
+    let device_id = args.first().expect("Arg not provided, this shouldn't be possible");
 
-    if args.len() == 3 {
-        // 2 arguments
-        if let (Some(arg1), Some(arg2)) = (args.get(1), args.get(2)) {
-            match arg1.as_str() {
-                "set" => {
-                    match arg2.parse::<f64>() {
-                        Ok(new_sv) => c::set_sv(&mut cn, new_sv).await,
-                        Err(e) => error!("`set` requires a floating point number as an argument: {}", e)
-                    }
-                },
-                "degrees" => {
-                    match arg2.as_str() {
-                        "F" => c::set_degrees(&mut cn, Degree::Fahrenheit).await,
-                        "C" => c::set_degrees(&mut cn, Degree::Celsius).await,
-                        _ => error!("Unkown argument `{}`", arg2),
-                    }
-                },
-                _ => error!("Argument `{}` not found, or you provided the wrong number of arguments", arg1)
+    if let Some(dev) = session.rtu.devices.iter().find(|dev| dev.id == *device_id ) {
+        // Commands that don't parse are left for the registry to explain
+        if let Some(category) = registry::category(dev, &args[1..]) {
+            if let Err(e) = access::check(dev, category, &format!("`{}`", args.join(" "))) {
+                error!("{}", e);
+                return Err(Failed.into());
             }
-        } else {
-            error!("Couldn't retrieve args 1 and 2");
+        }
+        // Devices on another RTU's daemon are run there, and say what went wrong themselves
+        match rtus::remote(dev) {
+            Some(remote) if rtus::run(remote, &args[1..]).await != 0 => return Err(Failed.into()),
+            Some(_) => {}
+            None => registry::run(dev, &args[1..]).await?,
         }
     }
 
-    if args.len() > 3 {
-        error!("Too many arguments ({}) provided: {:?}", args.len(), args);
-    }
-
+    Ok(())
 }
 
 fn clear() {
-    print!("\x1B[2J\x1B[1;1H");
+    out!("\x1B[2J\x1B[1;1H");
 }
 
-async fn dashboard(mut rtu: &mut RTU, _: Vec<String>) -> Result<(), Box<dyn Error>> {
+async fn dashboard(session: &mut Session, _: Vec<String>) -> Result<(), Box<dyn Error>> {
+    let readings = poller::spawn(&session.rtu, Duration::from_millis(session.config.poll_timeout));
+    // Enter quits. With nothing to read, like in a script, it redraws until the CLI is stopped.
+    let mut quit = Box::pin(prompt::answer());
+    let mut listening = true;
     clear();
     loop {
-        match tables::dashboard::render(&mut rtu).await {
-            Ok(table) => println!("{}", table),
-            Err(e) => {
-                error!("{}", e);
-                break;
+        let firing = session.alarms.firing_devices();
+        // Redraw over the last frame instead of scrolling
+        out!("\x1B[1;1H\x1B[J");
+        outln!("{}", tables::dashboard::render(&session.rtu, &readings, &firing, &session.timers.list()));
+        if listening {
+            outln!("Press Enter to quit");
+        }
+        tokio::select! {
+            answer = &mut quit, if listening => match answer {
+                Some(_) => break,
+                None => listening = false,
             },
+            _ = tokio::time::sleep(Duration::from_secs(1)) => {}
         }
     }
+    // The polling threads stop once the readings are gone
+    drop(readings);
     Ok(())
-}
\ No newline at end of file
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn the_commands_table_lists_every_general_command() {
+        let registered = general_commands();
+        let listed: Vec<&str> = registry::GENERAL.iter().filter_map(|cmd| cmd.usage.split(' ').next()).collect();
+        for name in registered.keys() {
+            assert!(listed.contains(name), "`{}` isn't in the commands table", name);
+        }
+        for name in listed {
+            assert!(
+                name == "[device]" || matches!(name, "help" | "quit" | "exit") || registered.contains_key(name),
+                "`{}` is in the commands table but isn't a command",
+                name
+            );
+        }
+    }
+
+    #[test]
+    fn only_registered_commands_are_general() {
+        registry::set_general(general_commands().keys().copied());
+        assert!(registry::is_general("dashboard"));
+        assert!(registry::is_general("help"));
+        assert!(!registry::is_general("[device]"));
+        assert!(!registry::is_general("mash_cn"));
+    }
+}
@@ -0,0 +1,191 @@
+//! Just enough Modbus RTU to make sense of raw frames
+//!
+//! The CN7500 speaks standard Modbus RTU, and the Waveshare boards use Modbus framing (address,
+//! function code, data, CRC) with a few non-standard registers. This is used to describe the
+//! frames in a serial trace, and to carry RTU frames over Modbus TCP (see [`crate::transport`]).
+
+/// The CRC-16/MODBUS checksum of `bytes`
+pub fn crc16(bytes: &[u8]) -> u16 {
+    let mut crc: u16 = 0xFFFF;
+    for byte in bytes {
+        crc ^= *byte as u16;
+        for _ in 0..8 {
+            crc = if crc & 1 == 1 { (crc >> 1) ^ 0xA001 } else { crc >> 1 };
+        }
+    }
+    crc
+}
+
+/// Whether the last two bytes of `frame` are the right CRC for the rest of it
+pub fn crc_ok(frame: &[u8]) -> bool {
+    if frame.len() < 4 {
+        return false;
+    }
+    let (body, crc) = frame.split_at(frame.len() - 2);
+    crc16(body).to_le_bytes() == [crc[0], crc[1]]
+}
+
+/// A Modbus request with two words of data, like every read and single write
+pub fn request(addr: u8, function: u8, first: u16, second: u16) -> Vec<u8> {
+    let mut frame = vec![addr, function];
+    frame.extend_from_slice(&first.to_be_bytes());
+    frame.extend_from_slice(&second.to_be_bytes());
+    frame.extend_from_slice(&crc16(&frame).to_le_bytes());
+    frame
+}
+
+/// Formats bytes as space separated hex, like `01 03 10 00`
+pub fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02X}", b)).collect::<Vec<_>>().join(" ")
+}
+
+/// Parses space separated (or unseparated) hex, like `01 03 10 00`
+pub fn parse_hex(text: &str) -> Result<Vec<u8>, String> {
+    let digits: String = text.chars().filter(|c| !c.is_whitespace()).collect();
+    if !digits.len().is_multiple_of(2) {
+        return Err(format!("`{}` has an odd number of hex digits", text));
+    }
+    (0..digits.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(&digits[i..i + 2], 16).map_err(|_| format!("`{}` isn't hex", &digits[i..i + 2])))
+        .collect()
+}
+
+/// How long a frame starting with `bytes` should be, if that can be told from its header
+fn expected_len(bytes: &[u8], request: bool) -> Option<usize> {
+    let function = *bytes.get(1)?;
+    match (request, function) {
+        (true, 0x01..=0x06) => Some(8),
+        (true, 0x0F) | (true, 0x10) => bytes.get(6).map(|count| 9 + *count as usize),
+        (false, f) if f & 0x80 != 0 => Some(5),
+        (false, 0x01..=0x04) => bytes.get(2).map(|count| 5 + *count as usize),
+        (false, 0x05) | (false, 0x06) | (false, 0x0F) | (false, 0x10) => Some(8),
+        _ => None,
+    }
+}
+
+/// The length of the Modbus frame at the start of `bytes`, if a whole one with a good CRC is there
+pub fn complete_frame(bytes: &[u8], request: bool) -> Option<usize> {
+    let len = expected_len(bytes, request)?;
+    if bytes.len() >= len && crc_ok(&bytes[..len]) {
+        Some(len)
+    } else {
+        None
+    }
+}
+
+/// The length of the Modbus TCP frame at the start of `bytes`, if a whole one is there
+pub fn complete_tcp_frame(bytes: &[u8]) -> Option<usize> {
+    if bytes.len() < 7 {
+        return None;
+    }
+    let len = 6 + word(bytes, 4) as usize;
+    if bytes.len() >= len {
+        Some(len)
+    } else {
+        None
+    }
+}
+
+/// Turns an RTU frame into a Modbus TCP one: the address goes in the MBAP header as the unit ID,
+/// and the CRC is dropped, since TCP checks the bytes itself
+pub fn rtu_to_tcp(transaction: u16, frame: &[u8]) -> Vec<u8> {
+    let pdu = &frame[1..frame.len() - 2];
+    let mut tcp = transaction.to_be_bytes().to_vec();
+    // Protocol 0 is Modbus
+    tcp.extend_from_slice(&[0, 0]);
+    tcp.extend_from_slice(&(pdu.len() as u16 + 1).to_be_bytes());
+    tcp.push(frame[0]);
+    tcp.extend_from_slice(pdu);
+    tcp
+}
+
+/// Turns a whole Modbus TCP frame back into an RTU one. Returns its transaction ID too.
+pub fn tcp_to_rtu(tcp: &[u8]) -> Option<(u16, Vec<u8>)> {
+    if tcp.len() < 8 {
+        return None;
+    }
+    let mut frame = tcp[6..].to_vec();
+    frame.extend_from_slice(&crc16(&frame).to_le_bytes());
+    Some((word(tcp, 0), frame))
+}
+
+fn word(bytes: &[u8], at: usize) -> u16 {
+    u16::from_be_bytes([bytes[at], bytes[at + 1]])
+}
+
+fn table(function: u8) -> &'static str {
+    match function {
+        0x01 | 0x05 | 0x0F => "coil",
+        0x02 => "discrete input",
+        0x04 => "input register",
+        _ => "register",
+    }
+}
+
+/// A coil value, as written by function 0x05
+fn coil(value: u16) -> String {
+    match value {
+        0xFF00 => "On".to_string(),
+        0x0000 => "Off".to_string(),
+        other => format!("0x{:04X}", other),
+    }
+}
+
+fn exception(code: u8) -> &'static str {
+    match code {
+        0x01 => "illegal function",
+        0x02 => "illegal data address",
+        0x03 => "illegal data value",
+        0x04 => "device failure",
+        0x06 => "device busy",
+        _ => "unknown exception",
+    }
+}
+
+/// Describes a request frame, like `addr 22: read register 0x1000 (1)`. `None` if it isn't Modbus.
+pub fn describe_request(frame: &[u8]) -> Option<String> {
+    if !crc_ok(frame) {
+        return None;
+    }
+    let (addr, function) = (frame[0], frame[1]);
+    let body = &frame[2..frame.len() - 2];
+
+    let what = match (function, body.len()) {
+        (0x01..=0x04, 4) => format!("read {} 0x{:04X} ({})", table(function), word(body, 0), word(body, 2)),
+        (0x05, 4) => format!("write coil 0x{:04X} = {}", word(body, 0), coil(word(body, 2))),
+        (0x06, 4) => format!("write register 0x{:04X} = {}", word(body, 0), word(body, 2)),
+        (0x0F, n) | (0x10, n) if n >= 5 => format!("write {} {}s from 0x{:04X}", word(body, 2), table(function), word(body, 0)),
+        _ => format!("function 0x{:02X}", function),
+    };
+    Some(format!("addr {}: {}", addr, what))
+}
+
+/// Describes a response frame, like `addr 22: registers [1500]`. `None` if it isn't Modbus.
+pub fn describe_response(frame: &[u8]) -> Option<String> {
+    if !crc_ok(frame) {
+        return None;
+    }
+    let (addr, function) = (frame[0], frame[1]);
+    let body = &frame[2..frame.len() - 2];
+
+    let what = match function {
+        f if f & 0x80 != 0 => {
+            let code = body.first().copied().unwrap_or(0);
+            format!("exception {} ({}) for function 0x{:02X}", code, exception(code), f & 0x7F)
+        }
+        0x01 | 0x02 if !body.is_empty() => {
+            let bits: Vec<String> = body[1..].iter().map(|b| format!("{:08b}", b.reverse_bits())).collect();
+            format!("{}s {}", table(function), bits.join(" "))
+        }
+        0x03 | 0x04 if !body.is_empty() => {
+            let values: Vec<String> = body[1..].chunks(2).filter(|c| c.len() == 2).map(|c| word(c, 0).to_string()).collect();
+            format!("{}s [{}]", table(function), values.join(", "))
+        }
+        0x05 if body.len() == 4 => format!("ok, coil 0x{:04X} = {}", word(body, 0), coil(word(body, 2))),
+        0x06 if body.len() == 4 => format!("ok, register 0x{:04X} = {}", word(body, 0), word(body, 2)),
+        0x0F | 0x10 if body.len() == 4 => format!("ok, wrote {} {}s from 0x{:04X}", word(body, 2), table(function), word(body, 0)),
+        _ => format!("function 0x{:02X}", function),
+    };
+    Some(format!("addr {}: {}", addr, what))
+}
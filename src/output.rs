@@ -0,0 +1,110 @@
+//! Where command output goes
+//!
+//! Commands print with [`outln!`] and [`out!`], and log with `log` like always. Normally that all
+//! goes to the terminal, but when the daemon runs a command for a client, it goes back down the
+//! client's socket instead (see [`crate::daemon`]).
+//!
+//! A command fails if it returns an error. Commands usually log what went wrong as it happens,
+//! and then return [`Failed`]. That's what `&&` and `||` go by, and the exit status of `exec`.
+use std::error::Error;
+use std::fmt;
+use std::future::Future;
+use std::io::{stdout, Write};
+use std::sync::atomic::{AtomicBool, Ordering};
+
+use env_logger::Env;
+use log::{Log, Metadata, Record};
+
+use crate::daemon;
+
+/// Like `println!`, but to the client the command is being run for, if there is one
+macro_rules! outln {
+    () => {
+        $crate::output::write("\n")
+    };
+    ($($arg:tt)*) => {
+        $crate::output::write(&format!("{}\n", format_args!($($arg)*)))
+    };
+}
+
+/// Like `print!`, but to the client the command is being run for, if there is one
+macro_rules! out {
+    ($($arg:tt)*) => {
+        $crate::output::write(&format!($($arg)*))
+    };
+}
+
+/// Writes command output
+pub fn write(text: &str) {
+    match daemon::client() {
+        Some(client) => client.write(text),
+        None => {
+            print!("{}", text);
+            stdout().flush().ok();
+        }
+    }
+}
+
+/// The error a command returns when it has already logged why it failed
+#[derive(Debug)]
+pub struct Failed;
+
+impl fmt::Display for Failed {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "the command failed")
+    }
+}
+
+impl Error for Failed {}
+
+tokio::task_local! {
+    /// Whether the command running in this task has failed
+    static FAILED: AtomicBool;
+}
+
+/// Runs a command, and returns whether it succeeded along with its output
+pub async fn succeeded<F: Future>(command: F) -> (F::Output, bool) {
+    FAILED
+        .scope(AtomicBool::new(false), async {
+            let output = command.await;
+            (output, !FAILED.with(|failed| failed.load(Ordering::Relaxed)))
+        })
+        .await
+}
+
+/// Marks the running command as failed
+pub fn fail() {
+    let _ = FAILED.try_with(|failed| failed.store(true, Ordering::Relaxed));
+}
+
+/// `env_logger`, except that records logged while running a command for a client go to the client
+struct Logger(env_logger::Logger);
+
+impl Log for Logger {
+    fn enabled(&self, metadata: &Metadata) -> bool {
+        self.0.enabled(metadata)
+    }
+
+    fn log(&self, record: &Record) {
+        if !self.0.matches(record) {
+            return;
+        }
+        match daemon::client() {
+            Some(client) => client.log(&format!("[{:<5} {}] {}", record.level(), record.target(), record.args())),
+            None => self.0.log(record),
+        }
+    }
+
+    fn flush(&self) {
+        self.0.flush()
+    }
+}
+
+/// Sets up logging, at `info` unless `RUST_LOG` says otherwise
+pub fn init_logging() {
+    let logger = env_logger::Builder::from_env(Env::default().default_filter_or("info")).format_timestamp(None).build();
+    let level = logger.filter();
+    if log::set_boxed_logger(Box::new(Logger(logger))).is_ok() {
+        log::set_max_level(level);
+    }
+}
@@ -0,0 +1,160 @@
+//! Who can change which devices
+//!
+//! A permissions file maps OS users and groups to the devices they can change, and how. Reading is
+//! always allowed; everything else (see [`Category`]) needs a rule:
+//!
+//! ```yaml
+//! rules:
+//!   - groups: [cellar]
+//!     devices: ["ferm_*"]
+//!     categories: [temperature]
+//!   - groups: [brewers]
+//!     devices: [hlt_heater, "wsrelay*"]
+//!     categories: [relay]
+//!   - groups: [maintainers]
+//!     devices: ["*"]
+//!     categories: ["*"]
+//! ```
+//!
+//! Devices are IDs or roles, and `*` matches anything. The file is `/etc/NavasotaBrewing/permissions.yaml`
+//! unless `cli.permissions_file` says otherwise. Without one, anyone can do anything, and root always can.
+use std::fs;
+use std::io;
+use std::sync::OnceLock;
+
+use brewdrivers::model::Device;
+use log::warn;
+use serde::Deserialize;
+
+use crate::config::CliConfig;
+use crate::registry::Category;
+use crate::{access, audit};
+
+pub const DEFAULT_FILE: &str = "/etc/NavasotaBrewing/permissions.yaml";
+
+/// What one group of people can do
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Rule {
+    #[serde(default)]
+    pub users: Vec<String>,
+    #[serde(default)]
+    pub groups: Vec<String>,
+    /// Device IDs or roles, or patterns with `*`
+    pub devices: Vec<String>,
+    /// Category names, or `*` for all of them
+    pub categories: Vec<String>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Permissions {
+    pub rules: Vec<Rule>,
+}
+
+/// The loaded permissions, or `None` if there's no permissions file
+static PERMISSIONS: OnceLock<Option<Permissions>> = OnceLock::new();
+
+/// The roles from the config file, so rules can name devices by role
+static ROLES: OnceLock<Vec<(String, String)>> = OnceLock::new();
+
+/// Loads the permissions file, if there is one
+pub fn init(config: &CliConfig) -> Result<(), String> {
+    let path = config.permissions_file.as_deref().unwrap_or(DEFAULT_FILE);
+    let permissions = match fs::read_to_string(path) {
+        Ok(contents) => {
+            let permissions: Permissions = serde_yaml::from_str(&contents).map_err(|e| format!("Couldn't read the permissions file `{}`: {}", path, e))?;
+            for rule in &permissions.rules {
+                for category in &rule.categories {
+                    if category != "*" && serde_yaml::from_str::<Category>(category).is_err() {
+                        return Err(format!(
+                            "Unknown category `{}` in `{}`. Use read, relay, temperature, address, settings, raw, or *",
+                            category, path
+                        ));
+                    }
+                }
+            }
+            Some(permissions)
+        }
+        Err(e) if e.kind() == io::ErrorKind::NotFound && config.permissions_file.is_none() => None,
+        Err(e) => return Err(format!("Couldn't read the permissions file `{}`: {}", path, e)),
+    };
+    let _ = PERMISSIONS.set(permissions);
+    let _ = ROLES.set(config.roles.iter().map(|(role, id)| (role.clone(), id.clone())).collect());
+    Ok(())
+}
+
+/// Whether there's a permissions file
+pub fn loaded() -> bool {
+    matches!(PERMISSIONS.get(), Some(Some(_)))
+}
+
+/// Whether `text` matches `pattern`, where `*` matches any run of characters
+fn glob(pattern: &str, text: &str) -> bool {
+    match pattern.split_once('*') {
+        None => pattern == text,
+        Some((prefix, rest)) => {
+            let Some(text) = text.strip_prefix(prefix) else {
+                return false;
+            };
+            (0..=text.len()).filter(|i| text.is_char_boundary(*i)).any(|i| glob(rest, &text[i..]))
+        }
+    }
+}
+
+/// The groups a user is in, from `/etc/passwd` and `/etc/group`
+fn groups(user: &str) -> Vec<String> {
+    let passwd = fs::read_to_string("/etc/passwd").unwrap_or_default();
+    let group = fs::read_to_string("/etc/group").unwrap_or_default();
+
+    // passwd lines are `name:x:uid:gid:...`, and group lines are `name:x:gid:member,member`
+    let primary = passwd.lines().map(|line| line.split(':').collect::<Vec<_>>()).find(|fields| fields[0] == user).and_then(|fields| fields.get(3).map(|gid| gid.to_string()));
+
+    group
+        .lines()
+        .map(|line| line.split(':').collect::<Vec<_>>())
+        .filter(|fields| fields.len() >= 4)
+        .filter(|fields| Some(fields[2]) == primary.as_deref() || fields[3].split(',').any(|member| member == user))
+        .map(|fields| fields[0].to_string())
+        .collect()
+}
+
+impl Rule {
+    fn applies_to(&self, user: &str, groups: &[String]) -> bool {
+        self.users.iter().any(|u| u == user) || self.groups.iter().any(|g| groups.contains(g))
+    }
+
+    fn covers_device(&self, device: &Device) -> bool {
+        let roles = ROLES.get().map(|roles| roles.as_slice()).unwrap_or_default();
+        self.devices.iter().any(|pattern| {
+            glob(pattern, &device.id) || roles.iter().any(|(role, id)| role == pattern && *id == device.id)
+        })
+    }
+
+    fn covers_category(&self, category: Category) -> bool {
+        self.categories.iter().any(|c| c == "*" || *c == category.to_string())
+    }
+}
+
+/// Checks that the person running the CLI may run a `category` command on `device`. Denials are
+/// logged, and recorded in the audit journal. `what` is the command, for the messages.
+pub fn check(device: &Device, category: Category, what: &str) -> Result<(), String> {
+    let permissions = match PERMISSIONS.get() {
+        Some(Some(permissions)) => permissions,
+        _ => return Ok(()),
+    };
+    if category == Category::Read || access::is_root() {
+        return Ok(());
+    }
+
+    let user = audit::user();
+    let groups = groups(&user);
+    if permissions.rules.iter().any(|rule| rule.applies_to(&user, &groups) && rule.covers_device(device) && rule.covers_category(category)) {
+        return Ok(());
+    }
+
+    let denied = format!("`{}` isn't allowed to run {} commands on `{}`", user, category, device.id);
+    warn!("Permission denied: {} ({})", what, denied);
+    audit::record(device, what.trim_matches('`'), None, "denied".to_string(), &Err::<(), _>(format!("permission denied: {}", denied)));
+    Err(format!("{}. Ask a maintainer to add it to the permissions file", denied))
+}
@@ -0,0 +1,123 @@
+//! Background polling for the dashboard
+//!
+//! Devices that share a serial port have to take turns, but devices on different ports don't. Each
+//! port gets its own polling thread that reads its devices one after another, forever. Every read
+//! has its own deadline, so a dead board only holds up the devices on its own port, and never the
+//! dashboard itself, which just draws the latest readings.
+//!
+//! The CN7500 driver is async, so its reads are just cut off at the deadline. The relay board
+//! drivers block on the serial port, where nothing can cut them off, so their serial timeout is
+//! shortened instead (see [`polled`]).
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex, MutexGuard, Weak};
+use std::time::{Duration, Instant};
+
+use brewdrivers::controllers::Controller;
+use brewdrivers::model::{Device, RTU};
+use brewdrivers::state::DeviceState;
+use tokio::runtime::Handle;
+
+use crate::{boards, retry};
+
+/// How long a port's thread rests after reading all of its devices
+const PASS_INTERVAL: Duration = Duration::from_millis(500);
+
+/// What the poller knows about one device
+#[derive(Debug, Clone, Default)]
+pub struct Reading {
+    /// The last values that were read successfully, and when
+    pub last_good: Option<(DeviceState, Instant)>,
+    /// Whether the most recent read succeeded
+    pub ok: bool,
+}
+
+impl Reading {
+    /// How old the values are, if the most recent read failed and there are older values to show
+    pub fn stale_for(&self) -> Option<Duration> {
+        match &self.last_good {
+            Some((_, at)) if !self.ok => Some(at.elapsed()),
+            _ => None,
+        }
+    }
+}
+
+/// The latest reading of every polled device. Cheap to clone, every clone shares the same readings.
+#[derive(Debug, Clone, Default)]
+pub struct Readings(Arc<Mutex<HashMap<String, Reading>>>);
+
+impl Readings {
+    fn map(&self) -> MutexGuard<'_, HashMap<String, Reading>> {
+        // A poisoned lock only means a polling thread panicked, the readings are still usable
+        self.0.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    /// The reading for a device, or `None` if it hasn't been polled yet
+    pub fn get(&self, device_id: &str) -> Option<Reading> {
+        self.map().get(device_id).cloned()
+    }
+
+    fn record(&self, device_id: &str, result: Option<DeviceState>) {
+        let mut map = self.map();
+        let reading = map.entry(device_id.to_string()).or_default();
+        reading.ok = result.is_some();
+        if let Some(state) = result {
+            reading.last_good = Some((state, Instant::now()));
+        }
+    }
+}
+
+/// Groups devices by serial port, keeping their order from the config file
+pub(crate) fn by_port(rtu: &RTU) -> Vec<Vec<Device>> {
+    let mut ports: Vec<(String, Vec<Device>)> = Vec::new();
+    for device in &rtu.devices {
+        let port = device.conn.port();
+        match ports.iter_mut().find(|(p, _)| *p == port) {
+            Some((_, devices)) => devices.push(device.clone()),
+            None => ports.push((port, vec![device.clone()])),
+        }
+    }
+    ports.into_iter().map(|(_, devices)| devices).collect()
+}
+
+/// The device as the poller reads it. A relay board's serial timeout is capped so that every
+/// attempt the retry policy allows, and the waits between them, fit in `timeout`.
+fn polled(device: &Device, timeout: Duration) -> Device {
+    let mut polled = device.clone();
+    if !matches!(device.conn.controller(), Controller::CN7500) {
+        let policy = retry::policy(device.conn.controller());
+        let per_attempt = timeout.saturating_sub(policy.total_wait()) / policy.attempts.max(1);
+        polled.conn.timeout = polled.conn.timeout.min(per_attempt.as_millis().max(1) as u64);
+    }
+    polled
+}
+
+/// Starts polling every device in the RTU, giving each read up to `timeout`. Polling stops once
+/// every clone of the returned `Readings` has been dropped.
+///
+/// The relay board drivers block while they wait on the serial port, so each port gets a thread
+/// of its own rather than a task on the runtime.
+pub fn spawn(rtu: &RTU, timeout: Duration) -> Readings {
+    let readings = Readings::default();
+    let handle = Handle::current();
+
+    for devices in by_port(rtu) {
+        let weak: Weak<_> = Arc::downgrade(&readings.0);
+        let handle = handle.clone();
+        std::thread::spawn(move || {
+            // The deadline timer needs the runtime's context
+            let _runtime = handle.enter();
+            loop {
+                for device in &devices {
+                    let result = handle.block_on(tokio::time::timeout(timeout, boards::read(&polled(device, timeout))));
+                    match weak.upgrade() {
+                        Some(map) => Readings(map).record(&device.id, result.ok().and_then(|r| r.ok())),
+                        None => return,
+                    }
+                }
+                std::thread::sleep(PASS_INTERVAL);
+            }
+        });
+    }
+
+    readings
+}
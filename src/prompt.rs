@@ -0,0 +1,82 @@
+//! Asking the brewer questions on the terminal
+//!
+//! `--yes` answers every question with yes, for `exec` mode and scripts that have no one to ask.
+//! In the daemon, questions are asked on the client's terminal.
+use std::io::stdin;
+use std::sync::atomic::{AtomicBool, Ordering};
+
+use brewdrivers::model::Device;
+use log::info;
+
+use crate::audit::{self, Mode};
+use crate::daemon;
+
+/// Whether `--yes` was given
+static ASSUME_YES: AtomicBool = AtomicBool::new(false);
+
+/// Answers every question from now on with yes
+pub fn assume_yes() {
+    ASSUME_YES.store(true, Ordering::Relaxed);
+}
+
+/// Whether every question is answered with yes, either with `--yes` or by the daemon's client
+pub fn assumed_yes() -> bool {
+    ASSUME_YES.load(Ordering::Relaxed) || daemon::client().is_some_and(|client| client.request.yes)
+}
+
+/// Reads an answer from the terminal, or the daemon's client. `None` if there's nothing to read.
+///
+/// Waiting on the terminal blocks, so it's done on a thread of its own rather than the runtime's.
+pub(crate) async fn answer() -> Option<String> {
+    if let Some(client) = daemon::client() {
+        return client.ask().await;
+    }
+    tokio::task::spawn_blocking(|| {
+        let mut answer = String::new();
+        match stdin().read_line(&mut answer) {
+            Ok(n) if n > 0 => Some(answer),
+            _ => None,
+        }
+    })
+    .await
+    .ok()
+    .flatten()
+}
+
+/// Asks a yes or no question. Anything other than `y` or `yes` is a no.
+pub async fn confirm(question: &str) -> bool {
+    out!("{} [y/N] ", question);
+    if assumed_yes() {
+        outln!("yes (--yes)");
+        return true;
+    }
+
+    match answer().await {
+        Some(answer) => matches!(answer.trim().to_lowercase().as_str(), "y" | "yes"),
+        None => {
+            // Nothing to read, like in a script. That's a no, but say how to make it a yes
+            outln!();
+            info!("No answer was given. Use `NBC_cli --yes` to confirm without being asked");
+            false
+        }
+    }
+}
+
+/// Asks before a destructive command changes a device, showing what it'll change from and to.
+///
+/// Timer commands aren't asked about when they run, since they were confirmed when the timer was added.
+pub async fn confirm_change(device: &Device, command: &str, current: Option<&str>, new: &str) -> bool {
+    if audit::mode() == Mode::Script {
+        return true;
+    }
+    confirm(&format!(
+        "`{}` on `{}` ({} controller {}): {} → {}. Are you sure?",
+        command,
+        device.id,
+        device.conn.controller(),
+        device.conn.controller_addr(),
+        current.unwrap_or("unknown"),
+        new
+    ))
+    .await
+}
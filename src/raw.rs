@@ -0,0 +1,295 @@
+//! Raw Modbus and serial commands, for commissioning and debugging
+//!
+//! The device commands only reach a handful of registers. `raw` can read or write any holding
+//! register on a Modbus device (the CN7500 and the Waveshare boards), or send any bytes down a
+//! port. It uses the device's configured port, baud rate, controller address and timeout, and goes
+//! through tracing and replay like the drivers do. Anything that could change a device asks first.
+//!
+//! ```text
+//! raw mash_cn read_holding 0x1001 1   # registers are decimal, or hex with 0x
+//! raw mash_cn write_single 0x1001 1485
+//! raw /dev/ttyUSB0 send 55 AA 05 02 FE 05 77
+//! ```
+use std::error::Error;
+use std::io::{ErrorKind, Read, Write};
+
+use brewdrivers::controllers::Controller;
+use brewdrivers::model::Device;
+use log::{error, info, warn};
+
+use crate::output::Failed;
+use crate::registry::Category;
+use crate::session::Session;
+use crate::{access, audit, boards, dry_run, modbus, prompt, replay, resolve, rtus, trace, transport};
+
+const USAGE: &str = "Use `raw [device] read_holding [reg] [count]`, `raw [device] write_single [reg] [value]` or `raw [port|device] send [hex]`";
+
+/// Parses a register, count or value. Decimal, or hex with `0x`.
+fn word(arg: &str) -> Result<u16, String> {
+    let parsed = match arg.strip_prefix("0x").or_else(|| arg.strip_prefix("0X")) {
+        Some(hex) => u16::from_str_radix(hex, 16),
+        None => arg.parse(),
+    };
+    parsed.map_err(|_| format!("expected a number from 0 to 65535 (or hex, like 0x1001), got `{}`", arg))
+}
+
+/// Whether `bytes` only reads from a device, so it's safe to send without asking
+fn is_read(bytes: &[u8]) -> bool {
+    modbus::crc_ok(bytes) && matches!(bytes[1], 0x01..=0x04)
+}
+
+/// Whether a response is a Modbus answer, rather than an exception or something garbled
+fn is_answer(response: &[u8]) -> bool {
+    modbus::crc_ok(response) && response[1] & 0x80 == 0
+}
+
+/// Sends `bytes` to the port `device` is on, and returns what came back. A Modbus response ends
+/// when a whole frame has arrived, anything else when the line has been quiet for the timeout.
+async fn transfer(device: &Device, bytes: &[u8], modbus_response: bool) -> Result<Vec<u8>, String> {
+    let _lock = boards::lock(device).await.map_err(|e| e.to_string())?;
+    let routed = transport::route(&trace::route(&replay::route(device)))?;
+    let (device, bytes) = (device.clone(), bytes.to_vec());
+    // The serial port blocks while it waits for the response
+    tokio::task::spawn_blocking(move || talk(&device, &routed.conn.port(), &bytes, modbus_response))
+        .await
+        .map_err(|e| e.to_string())?
+}
+
+/// Writes `bytes` to `port` with `device`'s settings, and reads the response (see [`transfer`])
+fn talk(device: &Device, port: &str, bytes: &[u8], modbus_response: bool) -> Result<Vec<u8>, String> {
+    let port_name = device.conn.port();
+    let failed = |e: &dyn std::fmt::Display| format!("Couldn't talk to `{}`: {}", port_name, e);
+
+    let mut port = serialport::new(port, *device.conn.baudrate() as u32)
+        .timeout(device.conn.timeout())
+        .open()
+        .map_err(|e| failed(&e))?;
+    port.write_all(bytes).map_err(|e| failed(&e))?;
+
+    let mut response = Vec::new();
+    let mut buf = [0u8; 256];
+    loop {
+        match port.read(&mut buf) {
+            Ok(0) => break,
+            Ok(n) => {
+                response.extend_from_slice(&buf[..n]);
+                if modbus_response && modbus::complete_frame(&response, false).is_some() {
+                    break;
+                }
+            }
+            Err(e) if e.kind() == ErrorKind::TimedOut => break,
+            Err(e) => return Err(failed(&e)),
+        }
+    }
+    Ok(response)
+}
+
+/// Sends a request and prints it and the response. Returns the response, or why there isn't
+/// one, or `None` in a dry run, where nothing is sent.
+async fn exchange(device: &Device, bytes: &[u8], modbus_response: bool) -> Option<Result<Vec<u8>, String>> {
+    if dry_run::enabled() {
+        info!("Dry run: would send {} to `{}` ({} baud)", modbus::hex(bytes), device.conn.port(), device.conn.baudrate());
+        return None;
+    }
+    info!("Sent:     {}", modbus::hex(bytes));
+    let result = transfer(device, bytes, modbus_response).await.and_then(|response| match response.is_empty() {
+        true => Err(format!("No response from `{}` within {}ms", device.conn.port(), device.conn.timeout().as_millis())),
+        false => Ok(response),
+    });
+
+    match &result {
+        Ok(response) => {
+            info!("Received: {}", modbus::hex(response));
+            match modbus::describe_response(response) {
+                Some(decoded) => info!("{}", decoded),
+                None if modbus_response => warn!("That isn't a valid Modbus response (the CRC doesn't match)"),
+                None => {}
+            }
+        }
+        Err(e) => error!("{}", e),
+    }
+    Some(result)
+}
+
+/// Reads one holding register without printing anything, for the journal's record of what a
+/// write replaced
+async fn read_register(device: &Device, register: u16) -> Option<u16> {
+    let response = transfer(device, &modbus::request(device.conn.controller_addr(), 0x03, register, 1), true).await.ok()?;
+    match response[..] {
+        [_, 0x03, 2, high, low, _, _] if modbus::crc_ok(&response) => Some(u16::from_be_bytes([high, low])),
+        _ => None,
+    }
+}
+
+/// The device a `raw` command is aimed at. A port means the first device configured on it.
+///
+/// A device behind another RTU's daemon is turned away, since its port is on that RTU.
+fn target<'a>(session: &'a Session, arg: &str) -> Result<&'a Device, String> {
+    let device = if arg.starts_with('/') || transport::is_network(arg) {
+        session
+            .rtu
+            .devices
+            .iter()
+            .find(|dev| dev.conn.port() == arg)
+            .ok_or_else(|| format!("No devices are configured on `{}`, so its baud rate and timeout aren't known", arg))?
+    } else {
+        resolve::device(&session.rtu, &session.config, arg).map_err(|e| e.to_string())?
+    };
+    match rtus::remote(device) {
+        Some(remote) => Err(format!(
+            "`{}` is on the `{}` RTU, which `raw` can't reach. Run `raw {}` on that RTU instead",
+            device.id, remote.rtu, remote.id
+        )),
+        None => Ok(device),
+    }
+}
+
+/// The devices a frame sent down `device`'s port is for. Every controller on the port sees the
+/// frame, so that's the devices on the controller it's addressed to, or every device on the port
+/// if it's a broadcast or it isn't addressed in a way that can be told.
+fn addressees<'a>(session: &'a Session, device: &Device, bytes: &[u8]) -> Vec<&'a Device> {
+    let on_port = session.rtu.devices.iter().filter(|dev| dev.conn.port() == device.conn.port());
+    // STR1 frames start with 55 AA, then the length, the command, and the controller number
+    let (str1, addr) = match bytes {
+        [0x55, 0xAA, _, _, cn, ..] => (true, *cn),
+        [addr, ..] if *addr != 0 && modbus::crc_ok(bytes) => (false, *addr),
+        _ => return on_port.collect(),
+    };
+    let addressed: Vec<&Device> = on_port
+        .clone()
+        .filter(|dev| dev.conn.controller_addr() == addr && (*dev.conn.controller() == Controller::STR1) == str1)
+        .collect();
+    match addressed.is_empty() {
+        true => on_port.collect(),
+        false => addressed,
+    }
+}
+
+fn modbus_device(device: &Device) -> Result<(), String> {
+    match device.conn.controller() {
+        Controller::STR1 => Err(format!("`{}` is on an STR1 board, which doesn't speak Modbus. Use `raw {} send [hex]`", device.id, device.id)),
+        _ => Ok(()),
+    }
+}
+
+/// The `raw` shell command
+///
+/// ```text
+/// raw [device] read_holding [reg] [count]
+/// raw [device] write_single [reg] [value]
+/// raw [port|device] send [hex]
+/// ```
+pub async fn command(session: &mut Session, args: Vec<String>) -> Result<(), Box<dyn Error>> {
+    let (device, op) = match (args.get(1), args.get(2)) {
+        (Some(target_arg), Some(op)) => match target(session, target_arg) {
+            Ok(device) => (device, op.as_str()),
+            Err(e) => {
+                error!("{}", e);
+                return Err(Failed.into());
+            }
+        },
+        _ => {
+            error!("{}", USAGE);
+            return Err(Failed.into());
+        }
+    };
+    let rest = &args[3..];
+
+    match op {
+        "read_holding" | "write_single" => {
+            if let Err(e) = modbus_device(device) {
+                error!("{}", e);
+                return Err(Failed.into());
+            }
+            let (first, second) = match rest {
+                [first, second] => match (word(first), word(second)) {
+                    (Ok(first), Ok(second)) => (first, second),
+                    (Err(e), _) | (_, Err(e)) => {
+                        error!("`raw {}`: {}", args[1..].join(" "), e);
+                        return Err(Failed.into());
+                    }
+                },
+                _ => {
+                    error!("`raw {}` takes a register and a {}", op, if op == "read_holding" { "count" } else { "value" });
+                    return Err(Failed.into());
+                }
+            };
+
+            let addr = device.conn.controller_addr();
+            if op == "write_single" {
+                if let Err(e) = access::check(device, Category::Raw, &format!("`raw {}`", args[1..].join(" "))) {
+                    error!("{}", e);
+                    return Err(Failed.into());
+                }
+            }
+            if op == "read_holding" {
+                if !(1..=125).contains(&second) {
+                    error!("A Modbus read can only return 1 to 125 registers, not {}", second);
+                    return Err(Failed.into());
+                }
+                match exchange(device, &modbus::request(addr, 0x03, first, second), true).await {
+                    Some(Ok(response)) if !is_answer(&response) => return Err(Failed.into()),
+                    Some(Err(_)) => return Err(Failed.into()),
+                    _ => {}
+                }
+            } else if dry_run::enabled() || prompt::confirm(&format!("Write {} to register 0x{:04X} on `{}` ({})?", second, first, device.id, device.name)).await {
+                let previous = if dry_run::enabled() { None } else { read_register(device, first).await };
+                let frame = modbus::request(addr, 0x06, first, second);
+                if let Some(result) = exchange(device, &frame, true).await {
+                    // A single write is answered with the request itself, anything else is an exception
+                    let result = result.and_then(|response| match response == frame {
+                        true => Ok(()),
+                        false => Err(modbus::describe_response(&response).unwrap_or_else(|| modbus::hex(&response))),
+                    });
+                    let register = |value: u16| format!("0x{:04X} = {}", first, value);
+                    audit::record(device, "raw write_single", previous.map(register), register(second), &result);
+                    if result.is_err() {
+                        return Err(Failed.into());
+                    }
+                }
+            } else {
+                info!("Nothing was written");
+            }
+        }
+        "send" => {
+            let bytes = match modbus::parse_hex(&rest.join(" ")) {
+                Ok(bytes) if !bytes.is_empty() => bytes,
+                Ok(_) => {
+                    error!("`raw {} send` needs the bytes to send, as hex", args[1]);
+                    return Err(Failed.into());
+                }
+                Err(e) => {
+                    error!("{}", e);
+                    return Err(Failed.into());
+                }
+            };
+            let addressees = addressees(session, device, &bytes);
+            if !is_read(&bytes) {
+                for addressee in &addressees {
+                    if let Err(e) = access::check(addressee, Category::Raw, &format!("`raw {}`", args[1..].join(" "))) {
+                        error!("{}", e);
+                        return Err(Failed.into());
+                    }
+                }
+            }
+            if is_read(&bytes) || dry_run::enabled() || prompt::confirm(&format!("Send {} to `{}`?", modbus::hex(&bytes), device.conn.port())).await {
+                let result = exchange(device, &bytes, false).await;
+                if let (Some(result), false) = (&result, is_read(&bytes)) {
+                    for addressee in addressees {
+                        audit::record(addressee, "raw send", None, modbus::hex(&bytes), result);
+                    }
+                }
+                if let Some(Err(_)) = result {
+                    return Err(Failed.into());
+                }
+            } else {
+                info!("Nothing was sent");
+            }
+        }
+        other => {
+            error!("Unknown raw command `{}`. {}", other, USAGE);
+            return Err(Failed.into());
+        }
+    }
+    Ok(())
+}
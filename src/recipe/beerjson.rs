@@ -0,0 +1,82 @@
+//! Reads BeerJSON 1.0 recipe exports
+//!
+//! BeerJSON quantities carry their own units (`{ "unit": "min", "value": 60 }`), so they're
+//! converted to Celsius and minutes here.
+use serde_json::Value;
+
+use super::{HopAddition, MashStep, Recipe};
+
+/// Converts a BeerJSON time to minutes
+fn minutes(value: &Value) -> Option<f64> {
+    let amount = value.get("value")?.as_f64()?;
+    match value.get("unit")?.as_str()? {
+        "sec" => Some(amount / 60.0),
+        "min" => Some(amount),
+        "hr" => Some(amount * 60.0),
+        "day" => Some(amount * 60.0 * 24.0),
+        "week" => Some(amount * 60.0 * 24.0 * 7.0),
+        _ => None,
+    }
+}
+
+/// Converts a BeerJSON temperature to Celsius
+fn celsius(value: &Value) -> Option<f64> {
+    let amount = value.get("value")?.as_f64()?;
+    match value.get("unit")?.as_str()? {
+        "C" => Some(amount),
+        "F" => Some((amount - 32.0) * 5.0 / 9.0),
+        _ => None,
+    }
+}
+
+/// Parses the first recipe out of a BeerJSON document
+pub fn parse(input: &str) -> Result<Recipe, String> {
+    let doc: Value = serde_json::from_str(input).map_err(|e| format!("invalid JSON: {}", e))?;
+    let recipe = doc
+        .pointer("/beerjson/recipes/0")
+        .ok_or("no recipes found, is this a BeerJSON file?")?;
+
+    let mash_steps = recipe
+        .pointer("/mash/mash_steps")
+        .and_then(Value::as_array)
+        .map(|steps| {
+            steps
+                .iter()
+                .map(|step| {
+                    Ok(MashStep {
+                        name: step["name"].as_str().unwrap_or("Mash step").to_string(),
+                        temp_c: celsius(&step["step_temperature"]).ok_or("mash step without a step_temperature")?,
+                        minutes: minutes(&step["step_time"]).unwrap_or(0.0),
+                    })
+                })
+                .collect::<Result<Vec<_>, String>>()
+        })
+        .transpose()?
+        .unwrap_or_default();
+
+    let hops = recipe
+        .pointer("/ingredients/hop_additions")
+        .and_then(Value::as_array)
+        .map(|hops| {
+            hops.iter()
+                .map(|hop| HopAddition {
+                    name: hop["name"].as_str().unwrap_or("Hop").to_string(),
+                    amount: match (hop.pointer("/amount/value"), hop.pointer("/amount/unit")) {
+                        (Some(value), Some(unit)) => format!("{}{}", value, unit.as_str().unwrap_or_default()),
+                        _ => String::new(),
+                    },
+                    use_: hop.pointer("/timing/use").and_then(Value::as_str).unwrap_or("add_to_boil").to_string(),
+                    minutes: hop.pointer("/timing/time").and_then(minutes).unwrap_or(0.0),
+                })
+                .collect()
+        })
+        .unwrap_or_default();
+
+    Ok(Recipe {
+        name: recipe["name"].as_str().unwrap_or("Unnamed recipe").to_string(),
+        mash_steps,
+        sparge_temp_c: recipe.pointer("/mash/sparge_temperature").and_then(celsius),
+        boil_minutes: recipe.pointer("/boil/boil_time").and_then(minutes).ok_or("recipe has no boil/boil_time")?,
+        hops,
+    })
+}
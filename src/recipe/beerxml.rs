@@ -0,0 +1,301 @@
+//! Reads BeerXML 1.0 recipe exports
+//!
+//! BeerXML is simple enough (no namespaces, and all the data is in element text) that it doesn't
+//! need a real XML library. This parses the document into a small element tree, with CDATA sections
+//! and character references decoded, and picks out the
+//! parts of the first `RECIPE` that the brew day plan needs. BeerXML uses Celsius, minutes, and kilograms.
+//!
+//! Exports are often saved as ISO-8859-1 or windows-1252 rather than UTF-8, so the file is decoded
+//! from the encoding in its `<?xml?>` declaration (see [`decode`]).
+use super::{HopAddition, MashStep, Recipe};
+
+/// An XML element. Tag names are uppercased, attributes are ignored.
+#[derive(Debug, Default)]
+struct Element {
+    name: String,
+    text: String,
+    children: Vec<Element>,
+}
+
+impl Element {
+    fn child(&self, name: &str) -> Option<&Element> {
+        self.children.iter().find(|c| c.name == name)
+    }
+
+    fn children<'a>(&'a self, name: &'a str) -> impl Iterator<Item = &'a Element> {
+        self.children.iter().filter(move |c| c.name == name)
+    }
+
+    fn text_of(&self, name: &str) -> Option<&str> {
+        self.child(name).map(|c| c.text.trim())
+    }
+
+    fn number(&self, name: &str) -> Option<f64> {
+        self.text_of(name)?.parse().ok()
+    }
+}
+
+/// What windows-1252 has at 0x80 to 0x9F, where ISO-8859-1 has control characters. The five bytes
+/// it leaves undefined are kept as the control characters.
+const WINDOWS_1252: [char; 32] = [
+    '€', '\u{81}', '‚', 'ƒ', '„', '…', '†', '‡', 'ˆ', '‰', 'Š', '‹', 'Œ', '\u{8D}', 'Ž', '\u{8F}', '\u{90}', '‘', '’', '“', '”', '•', '–', '—', '˜',
+    '™', 'š', '›', 'œ', '\u{9D}', 'ž', 'Ÿ',
+];
+
+/// The encoding named in the `<?xml?>` declaration at the start of a document, if there is one
+fn declared_encoding(bytes: &[u8]) -> Option<String> {
+    // The declaration is ASCII in every encoding BeerXML is saved in
+    let head = String::from_utf8_lossy(&bytes[..bytes.len().min(200)]);
+    let declaration = &head[head.find("<?xml")?..];
+    let declaration = &declaration[..declaration.find("?>")?];
+    let value = declaration[declaration.find("encoding")? + "encoding".len()..].trim_start().strip_prefix('=')?.trim_start();
+    let quote = value.chars().next().filter(|c| matches!(c, '"' | '\''))?;
+    let value = &value[1..];
+    Some(value[..value.find(quote)?].to_lowercase())
+}
+
+/// Decodes a BeerXML file from the encoding it declares. Without one, it's UTF-8 unless it isn't
+/// valid UTF-8, in which case it's read as ISO-8859-1 (Latin-1).
+pub fn decode(bytes: &[u8]) -> Result<String, String> {
+    let latin1 = || bytes.iter().map(|b| *b as char).collect::<String>();
+    match declared_encoding(bytes).as_deref() {
+        None | Some("utf-8" | "utf8") => match std::str::from_utf8(bytes) {
+            Ok(text) => Ok(text.trim_start_matches('\u{FEFF}').to_string()),
+            Err(_) => Ok(latin1()),
+        },
+        Some("iso-8859-1" | "iso8859-1" | "latin1" | "latin-1" | "us-ascii" | "ascii") => Ok(latin1()),
+        Some("windows-1252" | "cp1252") => Ok(bytes
+            .iter()
+            .map(|b| match b {
+                0x80..=0x9F => WINDOWS_1252[(b - 0x80) as usize],
+                _ => *b as char,
+            })
+            .collect()),
+        Some(other) => Err(format!("the file is in the `{}` encoding. Save it as UTF-8 or ISO-8859-1", other)),
+    }
+}
+
+/// Replaces the predefined XML entities and character references, like `&#176;` and `&#xB0;`.
+/// Anything else that looks like an entity is left as it is.
+fn unescape(text: &str) -> String {
+    let mut out = String::with_capacity(text.len());
+    let mut rest = text;
+    while let Some(start) = rest.find('&') {
+        out.push_str(&rest[..start]);
+        rest = &rest[start..];
+        let decoded = rest.find(';').and_then(|end| {
+            let c = match &rest[1..end] {
+                "lt" => '<',
+                "gt" => '>',
+                "quot" => '"',
+                "apos" => '\'',
+                "amp" => '&',
+                entity => {
+                    let code = match entity.strip_prefix("#x").or_else(|| entity.strip_prefix("#X")) {
+                        Some(hex) => u32::from_str_radix(hex, 16).ok()?,
+                        None => entity.strip_prefix('#')?.parse().ok()?,
+                    };
+                    char::from_u32(code)?
+                }
+            };
+            Some((c, end + 1))
+        });
+        match decoded {
+            Some((c, len)) => {
+                out.push(c);
+                rest = &rest[len..];
+            }
+            None => {
+                out.push('&');
+                rest = &rest[1..];
+            }
+        }
+    }
+    out.push_str(rest);
+    out
+}
+
+/// Parses an XML document into a tree, under an unnamed root element
+fn parse_tree(input: &str) -> Result<Element, String> {
+    let mut stack = vec![Element::default()];
+    let mut rest = input;
+
+    while let Some(start) = rest.find('<') {
+        stack.last_mut().unwrap().text.push_str(&unescape(&rest[..start]));
+        rest = &rest[start..];
+
+        // CDATA is text as it is, `<` and `>` and all
+        if let Some(cdata) = rest.strip_prefix("<![CDATA[") {
+            let end = cdata.find("]]>").ok_or("unterminated CDATA section")?;
+            stack.last_mut().unwrap().text.push_str(&cdata[..end]);
+            rest = &cdata[end + 3..];
+            continue;
+        }
+
+        // Things to skip over: declarations, comments, and doctypes. A doctype can have its own
+        // declarations in `[...]`, with `>` in them.
+        let doctype_subset = rest.starts_with("<!DOCTYPE") && rest.find('[').is_some_and(|open| Some(open) < rest.find('>'));
+        let skip = [("<?", "?>"), ("<!--", "-->"), ("<!", if doctype_subset { "]>" } else { ">" })];
+        if let Some((_, close)) = skip.iter().find(|(open, _)| rest.starts_with(open)) {
+            let end = rest.find(close).ok_or("unterminated XML declaration or comment")?;
+            rest = &rest[end + close.len()..];
+            continue;
+        }
+
+        let end = rest.find('>').ok_or("unterminated XML tag")?;
+        let tag = &rest[1..end];
+        rest = &rest[end + 1..];
+
+        if let Some(name) = tag.strip_prefix('/') {
+            let element = stack.pop().unwrap();
+            if stack.is_empty() || element.name != name.trim().to_uppercase() {
+                return Err(format!("unexpected closing tag `</{}>`", name.trim()));
+            }
+            stack.last_mut().unwrap().children.push(element);
+        } else {
+            let self_closing = tag.ends_with('/');
+            let name = tag.trim_end_matches('/').split_whitespace().next().unwrap_or_default().to_uppercase();
+            let element = Element { name, ..Default::default() };
+            if self_closing {
+                stack.last_mut().unwrap().children.push(element);
+            } else {
+                stack.push(element);
+            }
+        }
+    }
+
+    if stack.len() != 1 {
+        return Err(format!("unclosed tag `<{}>`", stack.last().unwrap().name));
+    }
+    Ok(stack.pop().unwrap())
+}
+
+/// Parses the first recipe out of a BeerXML document
+pub fn parse(input: &str) -> Result<Recipe, String> {
+    let root = parse_tree(input)?;
+    let recipe = root
+        .child("RECIPES")
+        .and_then(|r| r.child("RECIPE"))
+        .or_else(|| root.child("RECIPE"))
+        .ok_or("no <RECIPE> found, is this a BeerXML file?")?;
+
+    let mash = recipe.child("MASH");
+    let mash_steps = mash
+        .and_then(|m| m.child("MASH_STEPS"))
+        .map(|steps| {
+            steps
+                .children("MASH_STEP")
+                .map(|step| {
+                    Ok(MashStep {
+                        name: step.text_of("NAME").unwrap_or("Mash step").to_string(),
+                        temp_c: step.number("STEP_TEMP").ok_or("mash step without a STEP_TEMP")?,
+                        minutes: step.number("STEP_TIME").unwrap_or(0.0),
+                    })
+                })
+                .collect::<Result<Vec<_>, String>>()
+        })
+        .transpose()?
+        .unwrap_or_default();
+
+    let hops = recipe
+        .child("HOPS")
+        .map(|hops| {
+            hops.children("HOP")
+                .map(|hop| HopAddition {
+                    name: hop.text_of("NAME").unwrap_or("Hop").to_string(),
+                    amount: hop.number("AMOUNT").map(|kg| format!("{}g", (kg * 1000.0).round())).unwrap_or_default(),
+                    use_: hop.text_of("USE").unwrap_or("Boil").to_string(),
+                    minutes: hop.number("TIME").unwrap_or(0.0),
+                })
+                .collect()
+        })
+        .unwrap_or_default();
+
+    Ok(Recipe {
+        name: recipe.text_of("NAME").unwrap_or("Unnamed recipe").to_string(),
+        mash_steps,
+        sparge_temp_c: mash.and_then(|m| m.number("SPARGE_TEMP")),
+        boil_minutes: recipe.number("BOIL_TIME").ok_or("recipe has no BOIL_TIME")?,
+        hops,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const EXPORT: &str = r#"<?xml version="1.0" encoding="ISO-8859-1"?>
+<!DOCTYPE RECIPES [ <!ENTITY brewery "NBC"> ]>
+<RECIPES>
+  <RECIPE>
+    <NAME>Pils &#x26; Tell</NAME>
+    <NOTES><![CDATA[Mash at <b>66&#176;C</b>, then -> boil. Don't <STOP> early!]]></NOTES>
+    <BOIL_TIME>60</BOIL_TIME>
+    <HOPS>
+      <HOP>
+        <NAME>Saaz <![CDATA[<Czech>]]></NAME>
+        <AMOUNT>0.028</AMOUNT>
+        <USE>Boil</USE>
+        <TIME>60</TIME>
+      </HOP>
+    </HOPS>
+    <MASH>
+      <NAME>Single infusion &#176;C</NAME>
+      <SPARGE_TEMP>76</SPARGE_TEMP>
+      <MASH_STEPS>
+        <MASH_STEP>
+          <NAME>Saccharification at 66&#176;C</NAME>
+          <STEP_TEMP>66</STEP_TEMP>
+          <STEP_TIME>60</STEP_TIME>
+        </MASH_STEP>
+      </MASH_STEPS>
+    </MASH>
+  </RECIPE>
+</RECIPES>
+"#;
+
+    #[test]
+    fn reads_cdata_and_character_references() {
+        let recipe = parse(EXPORT).unwrap();
+        assert_eq!(recipe.name, "Pils & Tell");
+        assert_eq!(recipe.boil_minutes, 60.0);
+        assert_eq!(recipe.sparge_temp_c, Some(76.0));
+        assert_eq!(recipe.mash_steps.len(), 1);
+        assert_eq!(recipe.mash_steps[0].name, "Saccharification at 66°C");
+        assert_eq!(recipe.hops.len(), 1);
+        assert_eq!(recipe.hops[0].name, "Saaz <Czech>");
+        assert_eq!(recipe.hops[0].amount, "28g");
+    }
+
+    #[test]
+    fn cdata_text_is_kept_as_is() {
+        let root = parse_tree("<NOTES><![CDATA[a <b> &amp; c]]> &amp; d</NOTES>").unwrap();
+        assert_eq!(root.text_of("NOTES"), Some("a <b> &amp; c & d"));
+    }
+
+    #[test]
+    fn unescapes_entities() {
+        assert_eq!(unescape("&lt;&gt;&quot;&apos;&amp;"), "<>\"'&");
+        assert_eq!(unescape("66&#176;C, 66&#xB0;C"), "66°C, 66°C");
+        assert_eq!(unescape("Fish & Chips &nbsp;"), "Fish & Chips &nbsp;");
+    }
+
+    #[test]
+    fn decodes_the_declared_encoding() {
+        let latin1 = include_bytes!("../../tests/fixtures/latin1_recipe.xml");
+        let recipe = parse(&decode(latin1).unwrap()).unwrap();
+        assert_eq!(recipe.name, "Münchner Helles");
+        assert_eq!(recipe.mash_steps[0].name, "Rast bei 66°C");
+
+        assert_eq!(decode(b"<?xml version='1.0' encoding='windows-1252'?>\x93Hop\x94 \x80").unwrap(), "<?xml version='1.0' encoding='windows-1252'?>“Hop” €");
+        assert_eq!(decode("<?xml version=\"1.0\" encoding=\"UTF-8\"?>66°C".as_bytes()).unwrap(), "<?xml version=\"1.0\" encoding=\"UTF-8\"?>66°C");
+        // Not UTF-8 after all
+        assert_eq!(decode(b"<NAME>66\xB0C</NAME>").unwrap(), "<NAME>66°C</NAME>");
+        assert!(decode(b"<?xml version=\"1.0\" encoding=\"Shift_JIS\"?>").is_err());
+    }
+
+    #[test]
+    fn rejects_unterminated_cdata() {
+        assert!(parse_tree("<NOTES><![CDATA[never ends</NOTES>").is_err());
+    }
+}
@@ -0,0 +1,280 @@
+//! Recipe import and brew day plans
+//!
+//! `recipe load` reads a BeerXML or BeerJSON export and shows the brew day plan. The plan is
+//! applied in two parts, since the boil starts whenever lautering is done:
+//!
+//! * `recipe start mash` sets the `hlt` controller to the sparge temperature, sets the `mash`
+//!   controller to the first step, and adds a timer for every step that moves the mash to the next one.
+//! * `recipe start boil` runs the `kettle` controller and adds the boil timer (which stops the
+//!   kettle when it ends) and a timer for every hop addition.
+//!
+//! The `mash`, `hlt` and `kettle` controllers are found through `roles` in the `cli` config section.
+use std::error::Error;
+use std::fs;
+use std::sync::{Arc, Mutex, MutexGuard};
+
+use chrono::Local;
+use log::{error, info, warn};
+
+use brewdrivers::model::Device;
+
+use crate::output::Failed;
+use crate::registry::Category;
+use crate::session::Session;
+use crate::timers::Timer;
+use crate::{access, handlers, prompt, tables};
+
+mod beerjson;
+mod beerxml;
+
+#[derive(Debug, Clone)]
+pub struct MashStep {
+    pub name: String,
+    pub temp_c: f64,
+    pub minutes: f64,
+}
+
+#[derive(Debug, Clone)]
+pub struct HopAddition {
+    pub name: String,
+    /// The amount, with units, for display only
+    pub amount: String,
+    /// When the hop is used, ie. `Boil` or `Dry Hop`. Only boil additions get timers.
+    pub use_: String,
+    /// For boil additions, minutes before the end of the boil
+    pub minutes: f64,
+}
+
+impl HopAddition {
+    pub fn is_boil(&self) -> bool {
+        matches!(self.use_.to_lowercase().as_str(), "boil" | "add_to_boil")
+    }
+
+    /// The timer name for this addition, like `hop_cascade_15`
+    fn timer_name(&self) -> String {
+        format!("hop_{}_{}", self.name.to_lowercase().replace(char::is_whitespace, "_"), self.minutes.round())
+    }
+}
+
+/// The parts of a recipe that drive the hardware
+#[derive(Debug, Clone)]
+pub struct Recipe {
+    pub name: String,
+    pub mash_steps: Vec<MashStep>,
+    pub sparge_temp_c: Option<f64>,
+    pub boil_minutes: f64,
+    pub hops: Vec<HopAddition>,
+}
+
+impl Recipe {
+    /// Reads a BeerXML or BeerJSON file, depending on what it looks like
+    pub fn load(path: &str) -> Result<Recipe, String> {
+        let bytes = fs::read(path).map_err(|e| format!("couldn't read `{}`: {}", path, e))?;
+        if bytes.iter().find(|b| !b.is_ascii_whitespace()) == Some(&b'{') {
+            // JSON is always UTF-8
+            let contents = String::from_utf8(bytes).map_err(|e| format!("`{}` isn't UTF-8: {}", path, e))?;
+            beerjson::parse(&contents)
+        } else {
+            beerxml::parse(&beerxml::decode(&bytes).map_err(|e| format!("couldn't read `{}`: {}", path, e))?)
+        }
+    }
+}
+
+/// The recipe loaded with `recipe load`, if any. Cheap to clone, every clone shares the same recipe.
+#[derive(Debug, Clone, Default)]
+pub struct LoadedRecipe(Arc<Mutex<Option<Recipe>>>);
+
+impl LoadedRecipe {
+    fn recipe(&self) -> MutexGuard<'_, Option<Recipe>> {
+        self.0.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    pub fn get(&self) -> Option<Recipe> {
+        self.recipe().clone()
+    }
+
+    pub fn set(&self, recipe: Recipe) {
+        *self.recipe() = Some(recipe);
+    }
+}
+
+fn chrono_minutes(minutes: f64) -> chrono::Duration {
+    chrono::Duration::seconds((minutes * 60.0).round() as i64)
+}
+
+/// Finds the device configured for a role. It has to be a CN7500.
+fn role_device<'a>(session: &'a Session, role: &str) -> Result<&'a Device, String> {
+    let device_id = session
+        .config
+        .roles
+        .get(role)
+        .ok_or_else(|| format!("No device is configured for the `{}` role. Add it under `cli.roles` in the config file", role))?;
+
+    let device = session
+        .rtu
+        .devices
+        .iter()
+        .find(|dev| dev.id == *device_id)
+        .ok_or_else(|| format!("The `{}` role points to `{}`, but there's no device with that ID", role, device_id))?;
+
+    match device.conn.controller() {
+        brewdrivers::controllers::Controller::CN7500 => Ok(device),
+        other => Err(format!("The `{}` role has to be a CN7500, but `{}` is a {}", role, device_id, other)),
+    }
+}
+
+/// Sets the HLT and mash controllers and adds the mash step timers
+async fn start_mash(session: &Session, recipe: &Recipe) -> Result<(), String> {
+    let unit = session.config.temp_unit;
+    let mash = role_device(session, "mash")?;
+    let hlt = match recipe.sparge_temp_c {
+        Some(_) => Some(role_device(session, "hlt")?),
+        None => None,
+    };
+    let first = recipe.mash_steps.first().ok_or("The recipe has no mash steps")?;
+    for device in std::iter::once(mash).chain(hlt) {
+        access::check(device, Category::Temperature, "`recipe start mash`")?;
+    }
+
+    let mut summary = format!("Set `{}` to {} and run it", mash.id, unit.format(first.temp_c));
+    if let (Some(hlt), Some(sparge)) = (hlt, recipe.sparge_temp_c) {
+        summary.push_str(&format!(", and set `{}` to {}", hlt.id, unit.format(sparge)));
+    }
+    if !prompt::confirm(&format!("{}?", summary)).await {
+        info!("Cancelled");
+        return Ok(());
+    }
+
+    // The HLT isn't needed until the sparge, so the mash goes ahead without it
+    let mut hlt_set = Ok(());
+    if let (Some(hlt), Some(sparge)) = (hlt, recipe.sparge_temp_c) {
+        hlt_set = match handlers::temperature::connect(hlt).await {
+            Ok(mut cn) => handlers::temperature::set_sv(hlt, cn.as_mut(), unit.convert(sparge)).await,
+            Err(failed) => Err(failed),
+        };
+    }
+
+    let mut cn = handlers::temperature::connect(mash)
+        .await
+        .map_err(|_| "The mash controller didn't respond, no timers were added".to_string())?;
+    let started = match handlers::temperature::set_sv(mash, cn.as_mut(), unit.convert(first.temp_c)).await {
+        Ok(()) => handlers::temperature::run(mash, cn.as_mut()).await,
+        Err(failed) => Err(failed),
+    };
+    started.map_err(|_| "The mash controller didn't start, no timers were added".to_string())?;
+
+    // Each step's timer moves the mash controller on to the next step
+    let mut ends = Local::now();
+    for (i, step) in recipe.mash_steps.iter().enumerate() {
+        ends += chrono_minutes(step.minutes);
+        let then = match recipe.mash_steps.get(i + 1) {
+            Some(next) => vec![mash.id.clone(), "set".to_string(), format!("{:.1}", unit.convert(next.temp_c))],
+            None => vec![],
+        };
+        let name = format!("mash_{}", i + 1);
+        if let Err(e) = session.timers.add(Timer { name, ends, then }) {
+            warn!("{}", e);
+        }
+    }
+
+    info!("Mash started, see `timer` for the step schedule");
+    hlt_set.map_err(|_| format!("`{}` wasn't set to the sparge temperature", hlt.map(|hlt| hlt.id.as_str()).unwrap_or("hlt")))
+}
+
+/// Runs the kettle and adds the boil and hop timers
+async fn start_boil(session: &Session, recipe: &Recipe) -> Result<(), String> {
+    let kettle = role_device(session, "kettle")?;
+    access::check(kettle, Category::Temperature, "`recipe start boil`")?;
+
+    if !prompt::confirm(&format!("Run `{}` and start a {} minute boil?", kettle.id, recipe.boil_minutes)).await {
+        info!("Cancelled");
+        return Ok(());
+    }
+
+    let mut cn = handlers::temperature::connect(kettle)
+        .await
+        .map_err(|_| "The kettle controller didn't respond, no timers were added".to_string())?;
+    handlers::temperature::run(kettle, cn.as_mut())
+        .await
+        .map_err(|_| "The kettle controller didn't start, no timers were added".to_string())?;
+
+    let boil_ends = Local::now() + chrono_minutes(recipe.boil_minutes);
+    let boil = Timer {
+        name: "boil".to_string(),
+        ends: boil_ends,
+        then: vec![kettle.id.clone(), "stop".to_string()],
+    };
+    if let Err(e) = session.timers.add(boil) {
+        warn!("{}", e);
+    }
+
+    for hop in recipe.hops.iter().filter(|h| h.is_boil()) {
+        if hop.minutes >= recipe.boil_minutes {
+            warn!("Add {} {} now", hop.amount, hop.name);
+            continue;
+        }
+
+        let timer = Timer {
+            name: hop.timer_name(),
+            ends: boil_ends - chrono_minutes(hop.minutes),
+            then: vec![],
+        };
+        if let Err(e) = session.timers.add(timer) {
+            warn!("{}", e);
+        }
+    }
+
+    info!("Boil started, see `timer` for the hop schedule");
+    Ok(())
+}
+
+/// The `recipe` shell command
+pub async fn command(session: &mut Session, args: Vec<String>) -> Result<(), Box<dyn Error>> {
+    let unit = session.config.temp_unit;
+
+    match (args.get(1).map(|s| s.as_str()), args.get(2).map(|s| s.as_str())) {
+        (Some("load"), Some(path)) => match Recipe::load(path) {
+            Ok(recipe) => {
+                outln!("{}", tables::recipe::render(&recipe, unit));
+                info!("Loaded `{}`. Use `recipe start mash` and `recipe start boil` to run the plan", recipe.name);
+                session.recipe.set(recipe);
+            }
+            Err(e) => {
+                error!("Couldn't load recipe: {}", e);
+                return Err(Failed.into());
+            }
+        },
+        (None, _) | (Some("show"), _) => match session.recipe.get() {
+            Some(recipe) => outln!("{}", tables::recipe::render(&recipe, unit)),
+            None => {
+                error!("No recipe loaded, use `recipe load [file]`");
+                return Err(Failed.into());
+            }
+        },
+        (Some("start"), Some(stage @ "mash")) | (Some("start"), Some(stage @ "boil")) => {
+            let recipe = match session.recipe.get() {
+                Some(recipe) => recipe,
+                None => {
+                    error!("No recipe loaded, use `recipe load [file]`");
+                    return Err(Failed.into());
+                }
+            };
+
+            let result = match stage {
+                "mash" => start_mash(session, &recipe).await,
+                _ => start_boil(session, &recipe).await,
+            };
+
+            if let Err(e) = result {
+                error!("{}", e);
+                return Err(Failed.into());
+            }
+        }
+        _ => {
+            error!("Usage: recipe load [file] | recipe show | recipe start [mash|boil]");
+            return Err(Failed.into());
+        }
+    }
+
+    Ok(())
+}
@@ -0,0 +1,699 @@
+//! Every command the CLI knows about
+//!
+//! This is the one list of what each kind of device can do: each sub-command's name, its arguments
+//! and their types, its help text, and the handler that runs it. Dispatching, argument checking,
+//! the `commands` table, `[device] help`, and tab completion are all built from it, so a command
+//! added here shows up everywhere.
+use std::fmt;
+use std::future::Future;
+use std::pin::Pin;
+use std::str::FromStr;
+use std::sync::OnceLock;
+
+use brewdrivers::controllers::cn7500::Degree;
+use brewdrivers::controllers::Controller;
+use brewdrivers::model::Device;
+use brewdrivers::state::BinaryState;
+use log::{error, info};
+use serde::Deserialize;
+
+use crate::handlers::{self, Outcome};
+use crate::output::Failed;
+use crate::{dry_run, suggest, tables};
+
+/// What a handler returns
+pub type BoxFuture<'a> = Pin<Box<dyn Future<Output = Outcome> + Send + 'a>>;
+
+/// Runs a device command, given the device and the parsed arguments
+pub type Handler = for<'a> fn(&'a Device, &'a [Value]) -> BoxFuture<'a>;
+
+/// Makes a `Handler` out of a block of async code
+macro_rules! handler {
+    (|$device:ident, $args:ident| $body:expr) => {{
+        #[allow(unused_variables)]
+        fn handler<'a>($device: &'a Device, $args: &'a [Value]) -> BoxFuture<'a> {
+            Box::pin(async move { $body })
+        }
+        handler as Handler
+    }};
+}
+
+/// Connects to a relay board and runs the body with it
+macro_rules! relay {
+    ($device:ident, |$board:ident| $body:expr) => {
+        {
+            let mut board = handlers::relay::connect($device).await?;
+            let $board = board.as_mut();
+            $body
+        }
+    };
+}
+
+/// Connects to a temperature controller and runs the body with it
+macro_rules! temp {
+    ($device:ident, |$cn:ident| $body:expr) => {
+        {
+            let mut cn = handlers::temperature::connect($device).await?;
+            let $cn = cn.as_mut();
+            $body.await
+        }
+    };
+}
+
+/// The type of a command argument
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Arg {
+    /// `On` or `Off`
+    State,
+    /// A whole number from 0-255. The text describes it, like `0-254`
+    Byte(&'static str),
+    /// A decimal number. The text describes it, like `#.#`
+    Float(&'static str),
+    /// One of a few fixed words, like `F` or `C`
+    OneOf(&'static [&'static str]),
+}
+
+/// A parsed argument. The registry checks arguments before a handler runs, so a handler
+/// can count on getting the types it declared.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Value {
+    State(BinaryState),
+    Byte(u8),
+    Float(f64),
+    Word(&'static str),
+}
+
+impl Value {
+    pub fn state(&self) -> BinaryState {
+        match self {
+            Value::State(state) => *state,
+            other => unreachable!("expected a state, got {:?}", other),
+        }
+    }
+
+    pub fn byte(&self) -> u8 {
+        match self {
+            Value::Byte(byte) => *byte,
+            other => unreachable!("expected a byte, got {:?}", other),
+        }
+    }
+
+    pub fn float(&self) -> f64 {
+        match self {
+            Value::Float(float) => *float,
+            other => unreachable!("expected a float, got {:?}", other),
+        }
+    }
+
+    pub fn word(&self) -> &'static str {
+        match self {
+            Value::Word(word) => word,
+            other => unreachable!("expected a word, got {:?}", other),
+        }
+    }
+}
+
+impl Arg {
+    /// How the argument is shown in usage strings
+    fn usage(&self) -> String {
+        match self {
+            Arg::State => "[On|Off]".to_string(),
+            Arg::Byte(desc) | Arg::Float(desc) => format!("[{}]", desc),
+            Arg::OneOf(words) => format!("[{}]", words.join("|")),
+        }
+    }
+
+    /// The words that can be completed for this argument
+    fn candidates(&self) -> &'static [&'static str] {
+        match self {
+            Arg::State => &["On", "Off"],
+            Arg::OneOf(words) => words,
+            Arg::Byte(_) | Arg::Float(_) => &[],
+        }
+    }
+
+    fn parse(&self, input: &str) -> Result<Value, String> {
+        match self {
+            Arg::State => BinaryState::from_str(input)
+                .map(Value::State)
+                .map_err(|_| format!("expected On or Off, got `{}`{}", input, did_you_mean(input, self.candidates()))),
+            Arg::Byte(desc) => input
+                .parse::<u8>()
+                .map(Value::Byte)
+                .map_err(|_| format!("expected a whole number ({}), got `{}`", desc, input)),
+            Arg::Float(_) => input
+                .parse::<f64>()
+                .map(Value::Float)
+                .map_err(|_| format!("expected a decimal number, got `{}`", input)),
+            Arg::OneOf(words) => words
+                .iter()
+                .find(|word| word.eq_ignore_ascii_case(input))
+                .map(|word| Value::Word(word))
+                .ok_or_else(|| {
+                    format!("expected one of {}, got `{}`{}", words.join(", "), input, did_you_mean(input, words))
+                }),
+        }
+    }
+}
+
+/// A ". Did you mean `x`?" suffix for an error message, if `input` is close to one of the options
+fn did_you_mean(input: &str, options: &[&str]) -> String {
+    match suggest::closest(input, options.iter().copied()) {
+        Some(option) => format!(". Did you mean `{}`?", option),
+        None => String::new(),
+    }
+}
+
+/// What kind of thing a command does. Read-only mode only allows `Read` commands, and the
+/// permissions file grants the rest by category.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Category {
+    /// Reads a device without changing it
+    Read,
+    /// Switches relays
+    Relay,
+    /// Changes a temperature controller: its setpoint, whether it's running, or its units
+    Temperature,
+    /// Programs a new controller number into a board
+    Address,
+    /// Changes a device's connection settings in the config file
+    Settings,
+    /// Writes registers or sends bytes with `raw`
+    Raw,
+}
+
+impl fmt::Display for Category {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            Category::Read => "read",
+            Category::Relay => "relay",
+            Category::Temperature => "temperature",
+            Category::Address => "address",
+            Category::Settings => "settings",
+            Category::Raw => "raw",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+/// A command that can be run on a device
+pub struct DeviceCommand {
+    /// The sub-command. Commands without one are just the device and the arguments, like `[relayID] On`
+    pub name: Option<&'static str>,
+    pub args: &'static [Arg],
+    /// What the command does, for read-only mode and the permissions file
+    pub category: Category,
+    /// Whether the command can do real damage if it's run by mistake, like re-addressing a board or
+    /// switching a whole bank of relays. Destructive commands ask to confirm first.
+    pub destructive: bool,
+    /// `{relay}` and `{controller}` are filled in with the device's relay and controller numbers
+    /// when the help is shown for one device.
+    pub help: &'static str,
+    pub run: Handler,
+}
+
+impl DeviceCommand {
+    /// The command as shown in the commands table, like `[deviceID] set [#.#]`
+    pub fn usage(&self, device: &str) -> String {
+        let mut usage = device.to_string();
+        if let Some(name) = self.name {
+            usage.push(' ');
+            usage.push_str(name);
+        }
+        for arg in self.args {
+            usage.push(' ');
+            usage.push_str(&arg.usage());
+        }
+        usage
+    }
+
+    /// The help text, filled in for a device if there is one
+    pub fn help_for(&self, device: Option<&Device>) -> String {
+        match device {
+            Some(device) => self
+                .help
+                .replace("{relay}", &format!("relay {}", device.conn.addr()))
+                .replace("{controller}", &format!("controller {}", device.conn.controller_addr())),
+            None => self.help.replace("{relay}", "the relay").replace("{controller}", "this controller"),
+        }
+    }
+
+    /// The completions for each of this command's words: the name (if it has one) and the arguments
+    fn words(&'static self) -> Vec<&'static [&'static str]> {
+        let mut words: Vec<&'static [&'static str]> = Vec::new();
+        if let Some(name) = &self.name {
+            words.push(std::slice::from_ref(name));
+        }
+        words.extend(self.args.iter().map(|arg| arg.candidates()));
+        words
+    }
+}
+
+impl fmt::Debug for DeviceCommand {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("DeviceCommand").field("name", &self.name).field("args", &self.args).finish()
+    }
+}
+
+/// Why the words after a device don't make a command
+#[derive(Debug)]
+pub enum ParseError {
+    /// No command has this name
+    Unknown(String),
+    /// The command exists, but its arguments are wrong
+    Usage(&'static DeviceCommand, String),
+}
+
+/// The commands for one kind of controller
+#[derive(Debug)]
+pub struct CommandGroup {
+    pub title: &'static str,
+    /// How the device is shown in the commands table
+    pub device: &'static str,
+    pub commands: &'static [DeviceCommand],
+}
+
+impl CommandGroup {
+    /// Finds the command that `args` (the words after the device) are asking for, and parses its arguments
+    pub fn parse(&'static self, args: &[String]) -> Result<(&'static DeviceCommand, Vec<Value>), ParseError> {
+        let first = args.first().map(|a| a.as_str());
+
+        // Commands with a matching name, or without a name but whose first argument fits (like
+        // `[relayID] On`), are what the user was going for, so their errors are the useful ones
+        let specific = |cmd: &&DeviceCommand| match (cmd.name, cmd.args.first(), first) {
+            (Some(name), _, Some(first)) => name == first,
+            (None, Some(arg), Some(first)) => arg.parse(first).is_ok(),
+            _ => false,
+        };
+        let mut candidates: Vec<&'static DeviceCommand> = self.commands.iter().filter(specific).collect();
+        let has_specific = !candidates.is_empty();
+        candidates.extend(self.commands.iter().filter(|cmd| cmd.name.is_none() && !specific(cmd)));
+
+        let mut first_error = None;
+        for cmd in candidates {
+            let given = if cmd.name.is_some() { &args[1..] } else { args };
+            if given.len() != cmd.args.len() {
+                first_error.get_or_insert_with(|| {
+                    ParseError::Usage(cmd, format!("takes {} argument(s), {} given", cmd.args.len(), given.len()))
+                });
+                continue;
+            }
+
+            match cmd.args.iter().zip(given).map(|(arg, input)| arg.parse(input)).collect() {
+                Ok(values) => return Ok((cmd, values)),
+                Err(e) => {
+                    first_error.get_or_insert(ParseError::Usage(cmd, e));
+                }
+            }
+        }
+
+        match first_error {
+            Some(e) if has_specific => Err(e),
+            _ => Err(ParseError::Unknown(args.join(" "))),
+        }
+    }
+
+    /// The words that can come after `typed` (the words typed after the device so far)
+    pub fn completions(&self, typed: &[String]) -> Vec<&'static str> {
+        let mut words: Vec<&'static str> = Vec::new();
+        if typed.is_empty() {
+            words.push("help");
+        }
+
+        for cmd in self.commands {
+            let cmd_words = cmd.words();
+            if cmd_words.len() <= typed.len() {
+                continue;
+            }
+            // Arguments without candidates (numbers) match anything
+            let matches = cmd_words.iter().zip(typed).all(|(options, typed)| {
+                options.is_empty() || options.iter().any(|option| option.eq_ignore_ascii_case(typed))
+            });
+            if !matches {
+                continue;
+            }
+            for candidate in cmd_words[typed.len()] {
+                if !words.contains(candidate) {
+                    words.push(candidate);
+                }
+            }
+        }
+        words
+    }
+}
+
+/// Runs a device command. `args` are the words after the device.
+pub async fn run(device: &Device, args: &[String]) -> Outcome {
+    let group = for_controller(device.conn.controller());
+
+    if args.len() == 1 && args[0] == "help" {
+        outln!("{}", tables::commands::render_group(group, device));
+        return Ok(());
+    }
+
+    match group.parse(args) {
+        Ok((cmd, values)) if dry_run::enabled() => {
+            dry_run::report(device, cmd, &values);
+            Ok(())
+        }
+        Ok((cmd, values)) => (cmd.run)(device, &values).await,
+        Err(ParseError::Usage(cmd, problem)) => {
+            error!("`{} {}`: {}", device.id, args.join(" "), problem);
+            error!("Usage: {}", cmd.usage(&device.id));
+            Err(Failed)
+        }
+        Err(ParseError::Unknown(words)) => {
+            // The first word of each command: its name, or the choices for its first argument
+            let first_words: Vec<(&str, &DeviceCommand)> = group
+                .commands
+                .iter()
+                .flat_map(|cmd| cmd.words().first().copied().unwrap_or_default().iter().map(move |word| (*word, cmd)))
+                .collect();
+            let suggestion = args
+                .first()
+                .and_then(|first| suggest::closest(first, first_words.iter().map(|(word, _)| *word)))
+                .and_then(|word| first_words.iter().find(|(w, _)| *w == word));
+
+            match suggestion {
+                Some((word, cmd)) => {
+                    error!("`{}` isn't a {} command. Did you mean `{}`?", words, device.conn.controller(), word);
+                    error!("Usage: {}", cmd.usage(&device.id));
+                }
+                None if args.first().is_some_and(|first| suggest::closest(first, ["help"]).is_some()) => {
+                    error!("`{}` isn't a {} command. Did you mean `help`?", words, device.conn.controller());
+                }
+                None => {
+                    error!("`{}` isn't a {} command. Usage:", words, device.conn.controller());
+                    for cmd in group.commands {
+                        error!("    {}", cmd.usage(&device.id));
+                    }
+                }
+            }
+            info!("Use `{} help` to see what each command does", device.id);
+            Err(Failed)
+        }
+    }
+}
+
+/// What kind of command `args` (the words after the device) are, if they're a command
+pub fn category(device: &Device, args: &[String]) -> Option<Category> {
+    for_controller(device.conn.controller()).parse(args).ok().map(|(cmd, _)| cmd.category)
+}
+
+/// Whether `args` (the words after the device) are a destructive command
+pub fn is_destructive(device: &Device, args: &[String]) -> bool {
+    for_controller(device.conn.controller()).parse(args).is_ok_and(|(cmd, _)| cmd.destructive)
+}
+
+/// A command that isn't run on a device. They're registered with the shell in `main`;
+/// this is just what the `commands` table shows.
+pub struct GeneralCommand {
+    pub usage: &'static str,
+    pub help: &'static str,
+}
+
+/// The general commands `main` registers with the shell, by name
+static REGISTERED: OnceLock<Vec<String>> = OnceLock::new();
+
+/// Records the general commands registered with the shell. `main` does this before it builds
+/// the shell, since a client `exec` forwards its commands without one.
+pub fn set_general<'a>(names: impl IntoIterator<Item = &'a str>) {
+    let _ = REGISTERED.set(names.into_iter().map(String::from).collect());
+}
+
+/// Whether `name` is one of the general commands, or one shellfish has built in
+pub fn is_general(name: &str) -> bool {
+    matches!(name, "help" | "quit" | "exit") || REGISTERED.get().is_some_and(|names| names.iter().any(|n| n == name))
+}
+
+pub static GENERAL: &[GeneralCommand] = &[
+    GeneralCommand { usage: "help", help: "displays help information." },
+    GeneralCommand { usage: "quit", help: "quits the shell" },
+    GeneralCommand { usage: "exit", help: "exits the shell" },
+    GeneralCommand { usage: "commands", help: "lists the commands page (this page)" },
+    GeneralCommand { usage: "devices", help: "list all configured devices" },
+    GeneralCommand { usage: "time", help: "prints the current time" },
+    GeneralCommand { usage: "dashboard", help: "view a dashboard of all device states" },
+    GeneralCommand { usage: "[device] help", help: "lists the commands for a device" },
+    GeneralCommand { usage: "health", help: "checks that every device answers, and fails `exec` if one doesn't" },
+    GeneralCommand { usage: "stats", help: "shows successes, failures, retries and latency for each device" },
+    GeneralCommand { usage: "stats reset", help: "resets the stats" },
+    GeneralCommand { usage: "trace", help: "shows which ports are being traced, and the trace file" },
+    GeneralCommand { usage: "trace on [port]", help: "records the frames on a port, or on every port" },
+    GeneralCommand { usage: "trace off [port]", help: "stops tracing a port, or every port" },
+    GeneralCommand { usage: "trace show [n]", help: "shows the last n traced frames (default 20)" },
+    GeneralCommand { usage: "audit [device] (since [time]) (until [time])", help: "shows the journal of changes made to devices" },
+    GeneralCommand { usage: "unlock", help: "switches a read-only terminal to write mode, with the PIN" },
+    GeneralCommand { usage: "lock", help: "switches to read-only mode" },
+    GeneralCommand { usage: "raw [device] read_holding [reg] [count]", help: "reads holding registers from a Modbus device" },
+    GeneralCommand { usage: "raw [device] write_single [reg] [value]", help: "writes one holding register on a Modbus device" },
+    GeneralCommand { usage: "raw [port|device] send [hex]", help: "sends bytes down a port and prints the answer" },
+    GeneralCommand { usage: "alarm [list]", help: "lists all alarms and their status" },
+    GeneralCommand { usage: "alarm add [device] [cond] (for [dur])", help: "adds an alarm, ie. `alarm add mash pv < 148 for 2m`" },
+    GeneralCommand { usage: "alarm ack [alarmID|all]", help: "acknowledges a firing alarm, which stops the bell" },
+    GeneralCommand { usage: "alarm clear [alarmID|all]", help: "removes an alarm" },
+    GeneralCommand { usage: "alias", help: "lists your aliases" },
+    GeneralCommand { usage: "alias [name] = [cmd](; [cmd]...)", help: "adds an alias for one or more commands" },
+    GeneralCommand { usage: "unalias [name]", help: "removes an alias" },
+    GeneralCommand { usage: "sleep [dur]", help: "waits before the next command in a chain" },
+    GeneralCommand { usage: "timer [list]", help: "lists running timers" },
+    GeneralCommand { usage: "timer add [name] [dur] (then [cmd])", help: "starts a countdown, and runs [cmd] when it ends" },
+    GeneralCommand { usage: "timer add [name] at [timer]-[dur] (then [cmd])", help: "starts a countdown that ends relative to another timer" },
+    GeneralCommand { usage: "timer cancel [name|all]", help: "cancels a timer" },
+    GeneralCommand { usage: "recipe load [file]", help: "loads a BeerXML or BeerJSON recipe and shows the brew day plan" },
+    GeneralCommand { usage: "recipe [show]", help: "shows the brew day plan" },
+    GeneralCommand { usage: "recipe start mash", help: "sets the mash and hlt controllers and adds the mash step timers" },
+    GeneralCommand { usage: "recipe start boil", help: "runs the kettle and adds the boil and hop timers" },
+];
+
+pub static WAVESHARE: CommandGroup = CommandGroup {
+    title: "Waveshare (v1/v2) Commands",
+    device: "[relayID]",
+    commands: &[
+        DeviceCommand {
+            name: None,
+            args: &[],
+            category: Category::Read,
+            destructive: false,
+            help: "Gets the status of {relay}",
+            run: handler!(|device, args| relay!(device, |board| handlers::relay::get_relay(board, device.conn.addr()).await)),
+        },
+        DeviceCommand {
+            name: Some("list_all"),
+            args: &[],
+            category: Category::Read,
+            destructive: false,
+            help: "Lists the states of all the relays on {controller}",
+            run: handler!(|device, args| relay!(device, |board| handlers::relay::list_all(board).await)),
+        },
+        DeviceCommand {
+            name: None,
+            args: &[Arg::State],
+            category: Category::Relay,
+            destructive: false,
+            help: "Turns {relay} on or off",
+            run: handler!(|device, args| relay!(device, |board| handlers::relay::set_relay(device, board, device.conn.addr(), args[0].state()).await)),
+        },
+        DeviceCommand {
+            name: Some("set_all"),
+            args: &[Arg::State],
+            category: Category::Relay,
+            destructive: true,
+            help: "Sets all the relays on {controller}. Asks to confirm first",
+            run: handler!(|device, args| relay!(device, |board| handlers::relay::set_all(device, board, args[0].state()).await)),
+        },
+        DeviceCommand {
+            name: Some("get_cn"),
+            args: &[],
+            category: Category::Read,
+            destructive: false,
+            help: "Attempts to find the controller number the board is set to. The configured controller number (from the conf file) doesn't matter",
+            run: handler!(|device, args| relay!(device, |board| handlers::relay::get_cn(board).await)),
+        },
+        DeviceCommand {
+            name: Some("set_cn"),
+            args: &[Arg::Byte("0-254")],
+            category: Category::Address,
+            destructive: true,
+            help: "Sets a new controller number for {controller}. You'll need to update your rtu_conf.yaml file. Don't forget the controller number. Asks to confirm first",
+            run: handler!(|device, args| relay!(device, |board| handlers::relay::set_cn(device, board, args[0].byte()).await)),
+        },
+        DeviceCommand {
+            name: Some("software_revision"),
+            args: &[],
+            category: Category::Read,
+            destructive: false,
+            help: "Lists the software revision currently on the board",
+            run: handler!(|device, args| relay!(device, |board| handlers::relay::software_revision(board).await)),
+        },
+        DeviceCommand {
+            name: Some("probe"),
+            args: &[],
+            category: Category::Settings,
+            destructive: false,
+            help: "Tries {controller} at every baud rate with longer and longer timeouts, reports what it answers at and how fast, and offers to save that to the config file",
+            run: handler!(|device, args| handlers::probe::probe(device).await),
+        },
+    ],
+};
+
+pub static STR1: CommandGroup = CommandGroup {
+    title: "STR1 Commands",
+    device: "[relayID]",
+    commands: &[
+        DeviceCommand {
+            name: None,
+            args: &[],
+            category: Category::Read,
+            destructive: false,
+            help: "Gets the status of {relay}",
+            run: handler!(|device, args| relay!(device, |board| handlers::relay::get_relay(board, device.conn.addr()).await)),
+        },
+        DeviceCommand {
+            name: Some("list_all"),
+            args: &[],
+            category: Category::Read,
+            destructive: false,
+            help: "Lists the states of all the relays on {controller}",
+            run: handler!(|device, args| relay!(device, |board| handlers::relay::list_all(board).await)),
+        },
+        DeviceCommand {
+            name: None,
+            args: &[Arg::State],
+            category: Category::Relay,
+            destructive: false,
+            help: "Turns {relay} on or off",
+            run: handler!(|device, args| relay!(device, |board| handlers::relay::set_relay(device, board, device.conn.addr(), args[0].state()).await)),
+        },
+        DeviceCommand {
+            name: Some("set_all"),
+            args: &[Arg::State],
+            category: Category::Relay,
+            destructive: true,
+            help: "Sets all the relays on {controller}. Asks to confirm first",
+            run: handler!(|device, args| relay!(device, |board| handlers::relay::set_all(device, board, args[0].state()).await)),
+        },
+        DeviceCommand {
+            name: Some("set_cn"),
+            args: &[Arg::Byte("0-254")],
+            category: Category::Address,
+            destructive: true,
+            help: "Sets a new controller number for {controller}. You'll need to update your rtu_conf.yaml file. Don't forget the controller number. Asks to confirm first",
+            run: handler!(|device, args| relay!(device, |board| handlers::relay::set_cn(device, board, args[0].byte()).await)),
+        },
+        DeviceCommand {
+            name: Some("probe"),
+            args: &[],
+            category: Category::Settings,
+            destructive: false,
+            help: "Tries {controller} at every baud rate with longer and longer timeouts, reports what it answers at and how fast, and offers to save that to the config file",
+            run: handler!(|device, args| handlers::probe::probe(device).await),
+        },
+    ],
+};
+
+pub static CN7500: CommandGroup = CommandGroup {
+    title: "CN7500 Commands",
+    device: "[deviceID]",
+    commands: &[
+        DeviceCommand {
+            name: None,
+            args: &[],
+            category: Category::Read,
+            destructive: false,
+            help: "Gets the PV, SV, and status of the relay",
+            run: handler!(|device, args| temp!(device, |cn| handlers::temperature::get_all(cn))),
+        },
+        DeviceCommand {
+            name: Some("pv"),
+            args: &[],
+            category: Category::Read,
+            destructive: false,
+            help: "Gets the Process Value (actual)",
+            run: handler!(|device, args| temp!(device, |cn| handlers::temperature::get_pv(cn))),
+        },
+        DeviceCommand {
+            name: Some("sv"),
+            args: &[],
+            category: Category::Read,
+            destructive: false,
+            help: "Gets the Setpoint Value (target)",
+            run: handler!(|device, args| temp!(device, |cn| handlers::temperature::get_sv(cn))),
+        },
+        DeviceCommand {
+            name: Some("set"),
+            args: &[Arg::Float("#.#")],
+            category: Category::Temperature,
+            destructive: false,
+            help: "Sets the SV. Use a decimal number",
+            run: handler!(|device, args| temp!(device, |cn| handlers::temperature::set_sv(device, cn, args[0].float()))),
+        },
+        DeviceCommand {
+            name: Some("is_running"),
+            args: &[],
+            category: Category::Read,
+            destructive: false,
+            help: "Returns the status of the relay",
+            run: handler!(|device, args| temp!(device, |cn| handlers::temperature::is_running(cn))),
+        },
+        DeviceCommand {
+            name: Some("run"),
+            args: &[],
+            category: Category::Temperature,
+            destructive: false,
+            help: "Turns the relay on",
+            run: handler!(|device, args| temp!(device, |cn| handlers::temperature::run(device, cn))),
+        },
+        DeviceCommand {
+            name: Some("stop"),
+            args: &[],
+            category: Category::Temperature,
+            destructive: false,
+            help: "Turns the relay off",
+            run: handler!(|device, args| temp!(device, |cn| handlers::temperature::stop(device, cn))),
+        },
+        DeviceCommand {
+            name: Some("degrees"),
+            args: &[Arg::OneOf(&["F", "C"])],
+            category: Category::Temperature,
+            destructive: false,
+            help: "Sets degree units to F or C",
+            run: handler!(|device, args| {
+                let degree = match args[0].word() {
+                    "F" => Degree::Fahrenheit,
+                    _ => Degree::Celsius,
+                };
+                temp!(device, |cn| handlers::temperature::set_degrees(device, cn, degree))
+            }),
+        },
+        DeviceCommand {
+            name: Some("watch"),
+            args: &[],
+            category: Category::Read,
+            destructive: false,
+            help: "Prints the PV and SV every few seconds until you quit",
+            run: handler!(|device, args| handlers::temperature::watch(device).await),
+        },
+        DeviceCommand {
+            name: Some("probe"),
+            args: &[],
+            category: Category::Settings,
+            destructive: false,
+            help: "Tries {controller} at every baud rate with longer and longer timeouts, reports what it answers at and how fast, and offers to save that to the config file",
+            run: handler!(|device, args| handlers::probe::probe(device).await),
+        },
+    ],
+};
+
+/// Every device command group, in the order they're shown in the commands table
+pub static GROUPS: [&CommandGroup; 3] = [&WAVESHARE, &STR1, &CN7500];
+
+/// The commands for a kind of controller
+pub fn for_controller(controller: &Controller) -> &'static CommandGroup {
+    match controller {
+        Controller::Waveshare | Controller::WaveshareV2 => &WAVESHARE,
+        Controller::STR1 => &STR1,
+        Controller::CN7500 => &CN7500,
+    }
+}
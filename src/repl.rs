@@ -0,0 +1,324 @@
+//! The interactive shell loop
+//!
+//! Shellfish's own loop uses a bare rustyline editor, so this one is the same loop with a
+//! completion helper attached. Lines are still handed to the shell's handler to run, one command
+//! at a time, after being split at each `;`, `&&` and `||` (see [`chain`]).
+//!
+//! History is kept per user in `~/.local/share/nbc_cli/history`, so it carries over between
+//! sessions. Before the first prompt, the commands in `~/.nbc_clirc` are run, one per line, like
+//! they were typed. Lines starting with `#` are comments.
+use std::fs;
+use std::path::PathBuf;
+
+use log::{error, info, warn};
+use rustyline::completion::{Completer, Pair};
+use rustyline::error::ReadlineError;
+use rustyline::highlight::Highlighter;
+use rustyline::hint::Hinter;
+use rustyline::validate::Validator;
+use rustyline::{CompletionType, Config, Context, Editor, Helper};
+use shellfish::handler::AsyncHandler;
+use shellfish::Shell;
+
+use brewdrivers::model::RTU;
+
+use crate::config::CliConfig;
+use crate::session::Session;
+use crate::{aliases, dispatch, registry, resolve};
+
+/// Tab completion. The first word can be a command or a device ID, name, or role. After a
+/// device, the words come from that controller's commands in the registry.
+pub struct Completion {
+    commands: Vec<String>,
+    devices: Vec<String>,
+    rtu: RTU,
+    config: CliConfig,
+}
+
+impl Completion {
+    pub fn new(commands: impl Iterator<Item = String>, session: &Session) -> Self {
+        let mut commands: Vec<String> = commands.collect();
+        commands.extend(["help", "quit", "exit"].iter().map(|s| s.to_string()));
+        commands.sort();
+
+        Self {
+            commands,
+            devices: resolve::device_words(&session.rtu, &session.config),
+            rtu: session.rtu.clone(),
+            config: session.config.clone(),
+        }
+    }
+
+    /// Everything that could go in the next word, given the words before it
+    fn candidates(&self, before: &[String]) -> Vec<String> {
+        let (first, rest) = match before.split_first() {
+            Some(split) => split,
+            // Aliases can be added while the shell is running
+            None => return self.commands.iter().chain(&self.devices).cloned().chain(aliases::names()).collect(),
+        };
+
+        if self.commands.contains(first) {
+            return vec![];
+        }
+
+        match resolve::device(&self.rtu, &self.config, first) {
+            Ok(device) => registry::for_controller(device.conn.controller())
+                .completions(rest)
+                .into_iter()
+                .map(|word| word.to_string())
+                .collect(),
+            Err(_) => vec![],
+        }
+    }
+}
+
+impl Completer for Completion {
+    type Candidate = Pair;
+
+    fn complete(&self, line: &str, pos: usize, _: &Context<'_>) -> rustyline::Result<(usize, Vec<Pair>)> {
+        let line = &line[..pos];
+        let start = line.rfind(' ').map(|i| i + 1).unwrap_or(0);
+        let partial = line[start..].to_lowercase();
+        // Only the command after the last `;`, `&&` or `||` matters
+        let command = pieces(&line[..start]).pop().map(|(_, command)| command).unwrap_or_default();
+        let before = split(&command).unwrap_or_default();
+
+        let candidates = self
+            .candidates(&before)
+            .into_iter()
+            .filter(|word| word.to_lowercase().starts_with(&partial))
+            .map(|word| Pair { replacement: format!("{} ", word), display: word })
+            .collect();
+        Ok((start, candidates))
+    }
+}
+
+impl Hinter for Completion {
+    type Hint = String;
+}
+
+impl Highlighter for Completion {}
+
+impl Validator for Completion {}
+
+impl Helper for Completion {}
+
+/// Splits a line into words. Double quotes group words, and backslash escapes the next character.
+pub fn split(line: &str) -> Result<Vec<String>, String> {
+    let mut words = vec![String::new()];
+    let mut escape = false;
+    let mut quoted = false;
+
+    for c in line.chars() {
+        let word = words.last_mut().unwrap();
+        if escape {
+            match c {
+                'n' => word.push('\n'),
+                't' => word.push('\t'),
+                _ => word.push(c),
+            }
+            escape = false;
+        } else {
+            match c {
+                '\\' => escape = true,
+                '"' => quoted = !quoted,
+                ' ' if !quoted => words.push(String::new()),
+                _ => word.push(c),
+            }
+        }
+    }
+
+    if quoted {
+        return Err("Unterminated quote".to_string());
+    }
+
+    words.retain(|w| !w.is_empty());
+    Ok(words)
+}
+
+/// Puts a word back the way it'd be typed, quoting it if it has to be
+pub fn quote(word: &str) -> String {
+    if word.is_empty() || word.contains(|c: char| c.is_whitespace() || c == '"' || c == '\\') {
+        format!("\"{}\"", word.replace('\\', "\\\\").replace('"', "\\\"").replace('\n', "\\n").replace('\t', "\\t"))
+    } else {
+        word.to_string()
+    }
+}
+
+/// How a command in a chain depends on the one before it
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Link {
+    /// `;`, or the first command. It always runs.
+    Then,
+    /// `&&`. It runs if the command before it succeeded.
+    And,
+    /// `||`. It runs if the command before it failed.
+    Or,
+}
+
+/// Commands joined by `;`, `&&` and `||`, each split into words
+pub type Chain = Vec<(Link, Vec<String>)>;
+
+/// Cuts text at every `;`, `&&` and `||` that isn't quoted or escaped
+fn pieces(text: &str) -> Vec<(Link, String)> {
+    let chars: Vec<char> = text.chars().collect();
+    let mut pieces = vec![(Link::Then, String::new())];
+    let mut escape = false;
+    let mut quoted = false;
+
+    let mut i = 0;
+    while i < chars.len() {
+        let c = chars[i];
+        if !escape && !quoted {
+            let link = match (c, chars.get(i + 1)) {
+                (';', _) => Some((Link::Then, 1)),
+                ('&', Some('&')) => Some((Link::And, 2)),
+                ('|', Some('|')) => Some((Link::Or, 2)),
+                _ => None,
+            };
+            if let Some((link, len)) = link {
+                pieces.push((link, String::new()));
+                i += len;
+                continue;
+            }
+        }
+        // The words are split later, this only has to know which operators are in quotes
+        pieces.last_mut().unwrap().1.push(c);
+        if escape {
+            escape = false;
+        } else if c == '\\' {
+            escape = true;
+        } else if c == '"' {
+            quoted = !quoted;
+        }
+        i += 1;
+    }
+    pieces
+}
+
+/// Splits a line into commands at each `;`, `&&` and `||`, and each command into words (see
+/// [`split`]). An `alias` line is left whole, since it's defining a chain to run later.
+pub fn chain(text: &str) -> Result<Chain, String> {
+    let words = split(text)?;
+    if words.first().map(String::as_str) == Some("alias") {
+        return Ok(vec![(Link::Then, words)]);
+    }
+
+    let mut chain = Vec::new();
+    for (link, piece) in pieces(text) {
+        let words = split(piece.trim())?;
+        match link {
+            _ if !words.is_empty() => chain.push((link, words)),
+            // A line can end with `;`, and a doubled one doesn't hurt
+            Link::Then => {}
+            Link::And => return Err("`&&` needs a command on each side".to_string()),
+            Link::Or => return Err("`||` needs a command on each side".to_string()),
+        }
+    }
+    match chain.first() {
+        Some((Link::And, _)) => Err("`&&` needs a command on each side".to_string()),
+        Some((Link::Or, _)) => Err("`||` needs a command on each side".to_string()),
+        _ => Ok(chain),
+    }
+}
+
+/// The chain given as arguments to `exec`. Operators can be arguments of their own (quoted for the
+/// shell, like `'&&'`), or the whole chain can be one quoted argument.
+pub fn chain_args(args: &[String]) -> Result<Chain, String> {
+    if let [line] = args {
+        let chain = chain(line)?;
+        if chain.len() > 1 {
+            return Ok(chain);
+        }
+    }
+    let line: Vec<String> = args
+        .iter()
+        .map(|arg| match arg.as_str() {
+            ";" | "&&" | "||" => arg.clone(),
+            _ => quote(arg),
+        })
+        .collect();
+    chain(&line.join(" "))
+}
+
+/// A file in the user's home directory
+fn home_file(path: &str) -> Option<PathBuf> {
+    std::env::var_os("HOME").map(|home| PathBuf::from(home).join(path))
+}
+
+/// Runs the commands in `~/.nbc_clirc`. Returns true if one of them quit the shell.
+async fn run_rc<H: AsyncHandler<Session>>(shell: &mut Shell<'_, Session, String, H>) -> bool {
+    let path = match home_file(".nbc_clirc") {
+        Some(path) if path.exists() => path,
+        _ => return false,
+    };
+    let contents = match fs::read_to_string(&path) {
+        Ok(contents) => contents,
+        Err(e) => {
+            warn!("Couldn't read `{}`: {}", path.display(), e);
+            return false;
+        }
+    };
+
+    info!("Running `{}`", path.display());
+    for (i, line) in contents.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        match chain(line) {
+            Ok(chain) => {
+                if dispatch::run_chain(&shell.handler, chain, &shell.commands, &mut shell.state, &shell.description).await.0 {
+                    return true;
+                }
+            }
+            Err(e) => error!("`{}` line {}: {}", path.display(), i + 1, e),
+        }
+    }
+    false
+}
+
+/// Runs the shell until `quit`, `exit`, or EOF
+pub async fn run<H: AsyncHandler<Session>>(shell: &mut Shell<'_, Session, String, H>) -> Result<(), ReadlineError> {
+    let config = Config::builder().completion_type(CompletionType::List).max_history_size(1000).build();
+    let mut rl = Editor::<Completion>::with_config(config);
+    rl.set_helper(Some(Completion::new(shell.commands.keys().map(|k| k.to_string()), &shell.state)));
+
+    let history = home_file(".local/share/nbc_cli/history");
+    if let Some(path) = &history {
+        if let Some(dir) = path.parent() {
+            let _ = fs::create_dir_all(dir);
+        }
+        // There's no history the first time
+        let _ = rl.load_history(path);
+    }
+
+    if run_rc(shell).await {
+        return Ok(());
+    }
+
+    loop {
+        let line = match rl.readline(&shell.prompt) {
+            Ok(line) => line,
+            Err(ReadlineError::Interrupted) => continue,
+            Err(ReadlineError::Eof) => return Ok(()),
+            Err(e) => return Err(e),
+        };
+        rl.add_history_entry(line.as_str());
+        // Saved as it goes, so other shells and a crash don't lose it
+        if let Some(path) = &history {
+            if let Err(e) = rl.append_history(path) {
+                warn!("Couldn't save the history to `{}`: {}", path.display(), e);
+            }
+        }
+
+        match chain(line.trim()) {
+            Ok(chain) => {
+                if dispatch::run_chain(&shell.handler, chain, &shell.commands, &mut shell.state, &shell.description).await.0 {
+                    return Ok(());
+                }
+            }
+            Err(e) => error!("{}", e),
+        }
+    }
+}
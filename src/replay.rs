@@ -0,0 +1,268 @@
+//! Fake serial devices that replay a trace
+//!
+//! A trace file (see [`crate::trace`]) holds every request the CLI sent and every response it got
+//! back. Replaying one puts a fake device on each port in the trace. When the fake device gets a
+//! request it has a recording of, it sends back the response that was recorded for it. That lets
+//! the CLI run without any hardware, which is what the integration tests do.
+//!
+//! If the same request was recorded more than once, the responses are given in the order they were
+//! recorded, and the last one is repeated after that. Requests that weren't recorded get no answer,
+//! so the CLI sees a timeout.
+//!
+//! A port on the network (see [`crate::transport`]) is faked by a stand-in gateway listening on its
+//! address, so the CLI's side of it is the same as with a real gateway.
+use std::collections::{BTreeMap, HashMap, VecDeque};
+use std::fs;
+use std::io::{ErrorKind, Read, Write};
+use std::net::{TcpListener, TcpStream};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::thread::JoinHandle;
+use std::time::Duration;
+
+use brewdrivers::model::Device;
+use chrono::{DateTime, FixedOffset};
+use log::{error, warn};
+use serialport::{SerialPort, TTYPort};
+
+use crate::modbus;
+use crate::transport::{self, Kind};
+use crate::trace::{Direction, Frame};
+
+/// How long a request can pause in the middle before the fake device gives up on it
+const GAP: Duration = Duration::from_millis(10);
+
+/// How many reads in a row can fail before a fake device gives up
+const MAX_READ_ERRORS: u32 = 100;
+
+/// The recorded responses to each request on one port
+type Script = HashMap<Vec<u8>, VecDeque<Vec<u8>>>;
+
+/// Reads a trace file
+pub fn load(path: &str) -> Result<Vec<Frame>, String> {
+    let contents = fs::read_to_string(path).map_err(|e| format!("Couldn't read the trace `{}`: {}", path, e))?;
+    contents
+        .lines()
+        .enumerate()
+        .filter(|(_, line)| !line.trim().is_empty())
+        .map(|(i, line)| serde_json::from_str(line).map_err(|e| format!("`{}` line {}: {}", path, i + 1, e)))
+        .collect()
+}
+
+/// Pairs every request in a trace with the response that came after it, by port
+fn scripts(frames: &[Frame]) -> Result<BTreeMap<String, Script>, String> {
+    // Frames that aren't Modbus are written when the line goes quiet, so a response can land in
+    // the file before its request. Their times are still right.
+    let mut frames: Vec<(DateTime<FixedOffset>, &Frame)> = frames
+        .iter()
+        .map(|frame| DateTime::parse_from_rfc3339(&frame.time).map(|time| (time, frame)).map_err(|e| format!("`{}` isn't a time: {}", frame.time, e)))
+        .collect::<Result<_, _>>()?;
+    frames.sort_by_key(|(time, _)| *time);
+
+    let mut scripts: BTreeMap<String, Script> = BTreeMap::new();
+    // The request being answered on each port
+    let mut open: HashMap<String, (Vec<u8>, Vec<u8>)> = HashMap::new();
+
+    for (_, frame) in frames {
+        let bytes = modbus::parse_hex(&frame.hex)?;
+        match frame.dir {
+            Direction::Tx => {
+                if let Some((request, response)) = open.insert(frame.port.clone(), (bytes, Vec::new())) {
+                    scripts.entry(frame.port.clone()).or_default().entry(request).or_default().push_back(response);
+                }
+            }
+            Direction::Rx => match open.get_mut(&frame.port) {
+                Some((_, response)) => response.extend(bytes),
+                None => return Err(format!("The trace has a response on `{}` at {} before any request", frame.port, frame.time)),
+            },
+        }
+    }
+
+    for (port, (request, response)) in open {
+        scripts.entry(port).or_default().entry(request).or_default().push_back(response);
+    }
+    Ok(scripts)
+}
+
+/// The recorded response to a request, if there is one
+fn answer(script: &mut Script, request: &[u8]) -> Option<Vec<u8>> {
+    let responses = script.get_mut(request)?;
+    Some(if responses.len() > 1 { responses.pop_front().unwrap_or_default() } else { responses[0].clone() })
+}
+
+/// Answers requests on `port` from the script until `stop` is set, or it can't be read
+/// [`MAX_READ_ERRORS`] times in a row
+fn serve(name: String, mut port: TTYPort, mut script: Script, stop: Arc<AtomicBool>) {
+    let mut request: Vec<u8> = Vec::new();
+    let mut buf = [0u8; 256];
+    let mut errors = 0;
+
+    loop {
+        match port.read(&mut buf) {
+            Ok(n) if n > 0 => {
+                errors = 0;
+                request.extend_from_slice(&buf[..n]);
+                if let Some(response) = answer(&mut script, &request) {
+                    let _ = port.write_all(&response);
+                    request.clear();
+                }
+            }
+            Err(e) if e.kind() != ErrorKind::TimedOut => {
+                errors += 1;
+                if stop.load(Ordering::Relaxed) {
+                    return;
+                }
+                if errors == MAX_READ_ERRORS {
+                    error!("The replay of `{}` stopped, it can't be read: {}", name, e);
+                    return;
+                }
+                std::thread::sleep(GAP);
+            }
+            _ => {
+                errors = 0;
+                if !request.is_empty() {
+                    warn!("The replay of `{}` has no response recorded for {}", name, modbus::hex(&request));
+                    request.clear();
+                }
+                if stop.load(Ordering::Relaxed) {
+                    return;
+                }
+            }
+        }
+    }
+}
+
+/// Answers one connection to a stand-in gateway from the script, until it's closed or `stop` is set
+fn serve_connection(name: &str, kind: Kind, mut stream: TcpStream, script: &mut Script, stop: &AtomicBool) {
+    let mut request: Vec<u8> = Vec::new();
+    let mut buf = [0u8; 256];
+    if stream.set_read_timeout(Some(GAP)).is_err() {
+        return;
+    }
+
+    while !stop.load(Ordering::Relaxed) {
+        match stream.read(&mut buf) {
+            Ok(0) => return,
+            Ok(n) => request.extend_from_slice(&buf[..n]),
+            Err(e) if matches!(e.kind(), ErrorKind::WouldBlock | ErrorKind::TimedOut) => {
+                if !request.is_empty() {
+                    warn!("The replay of `{}` has no response recorded for {}", name, modbus::hex(&request));
+                    request.clear();
+                }
+                continue;
+            }
+            Err(_) => return,
+        }
+
+        match kind {
+            Kind::Raw => {
+                if let Some(response) = answer(script, &request) {
+                    let _ = stream.write_all(&response);
+                    request.clear();
+                }
+            }
+            // The trace holds the RTU frames from the CLI's side of the bridge
+            Kind::ModbusTcp => {
+                while let Some(len) = modbus::complete_tcp_frame(&request) {
+                    let rest = request.split_off(len);
+                    match modbus::tcp_to_rtu(&request) {
+                        Some((transaction, frame)) => match answer(script, &frame) {
+                            Some(response) if response.len() >= 4 => {
+                                let _ = stream.write_all(&modbus::rtu_to_tcp(transaction, &response));
+                            }
+                            _ => warn!("The replay of `{}` has no response recorded for {}", name, modbus::hex(&frame)),
+                        },
+                        None => warn!("The replay of `{}` got {}, which isn't Modbus TCP", name, modbus::hex(&request)),
+                    }
+                    request = rest;
+                }
+            }
+        }
+    }
+}
+
+/// Stands in for the gateway on a network port, answering every connection from the script until
+/// `stop` is set
+fn serve_gateway(name: String, kind: Kind, listener: TcpListener, mut script: Script, stop: Arc<AtomicBool>) {
+    while !stop.load(Ordering::Relaxed) {
+        match listener.accept() {
+            Ok((stream, _)) => {
+                let _ = stream.set_nonblocking(false);
+                serve_connection(&name, kind, stream, &mut script, &stop);
+            }
+            Err(_) => std::thread::sleep(GAP),
+        }
+    }
+}
+
+/// A fake device standing in for a port
+struct Fake {
+    /// The pseudo terminal that devices on the port are pointed at. Ports on the network aren't
+    /// pointed anywhere else, the stand-in gateway is on their address.
+    pty: Option<String>,
+    stop: Arc<AtomicBool>,
+    thread: JoinHandle<()>,
+    /// Held open so the pseudo terminal stays up between connections
+    _slave: Option<TTYPort>,
+}
+
+static FAKES: Mutex<BTreeMap<String, Fake>> = Mutex::new(BTreeMap::new());
+
+fn fakes() -> MutexGuard<'static, BTreeMap<String, Fake>> {
+    // A poisoned lock only means another thread panicked while holding it, the fakes still work
+    FAKES.lock().unwrap_or_else(|e| e.into_inner())
+}
+
+/// Puts a fake device on every port in the trace at `path`. Returns the ports.
+pub fn start(path: &str) -> Result<Vec<String>, String> {
+    let scripts = scripts(&load(path)?)?;
+    let mut ports = Vec::new();
+
+    for (port, script) in scripts {
+        if let Some((kind, address)) = transport::parse(&port) {
+            let open = |e: std::io::Error| format!("Couldn't stand in for the gateway on `{}`: {}", port, e);
+            let listener = TcpListener::bind(address).map_err(open)?;
+            listener.set_nonblocking(true).map_err(open)?;
+
+            let stop = Arc::new(AtomicBool::new(false));
+            let (name, thread_stop) = (port.clone(), stop.clone());
+            let thread = std::thread::spawn(move || serve_gateway(name, kind, listener, script, thread_stop));
+
+            fakes().insert(port.clone(), Fake { pty: None, stop, thread, _slave: None });
+            ports.push(port);
+            continue;
+        }
+
+        let open = |e: serialport::Error| format!("Couldn't fake `{}`: {}", port, e);
+        let (mut master, slave) = TTYPort::pair().map_err(open)?;
+        master.set_timeout(GAP).map_err(open)?;
+        let pty = slave.name().ok_or_else(|| format!("Couldn't fake `{}`: the pseudo terminal has no name", port))?;
+
+        let stop = Arc::new(AtomicBool::new(false));
+        let (name, thread_stop) = (port.clone(), stop.clone());
+        let thread = std::thread::spawn(move || serve(name, master, script, thread_stop));
+
+        fakes().insert(port.clone(), Fake { pty: Some(pty), stop, thread, _slave: Some(slave) });
+        ports.push(port);
+    }
+    Ok(ports)
+}
+
+/// Stops every fake device
+pub fn stop_all() {
+    let stopped = std::mem::take(&mut *fakes());
+    for fake in stopped.into_values() {
+        fake.stop.store(true, Ordering::Relaxed);
+        let _ = fake.thread.join();
+    }
+}
+
+/// The device to actually connect to. If its port is being faked, that's a copy of the device
+/// pointed at the fake.
+pub fn route(device: &Device) -> Device {
+    let mut routed = device.clone();
+    if let Some(pty) = fakes().get(&device.conn.port()).and_then(|fake| fake.pty.clone()) {
+        routed.conn.port = pty.into();
+    }
+    routed
+}
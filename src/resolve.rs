@@ -0,0 +1,128 @@
+//! Finding devices by ID, name, or role
+//!
+//! Device IDs are unique but cryptic (`wsrelay3`), so a device can also be addressed by its `name`
+//! or by a role from the `cli.roles` section of the config file (`hlt_heater: wsrelay3`). Names and
+//! roles are matched without regard to case, and spaces can be typed as underscores, so a device
+//! named `Mash Pump` can be addressed as `mash_pump`. An exact device ID always wins. Devices on
+//! another RTU can be addressed by name in that RTU's namespace too, like `cellar:fermenter_1`.
+use std::fmt;
+
+use brewdrivers::model::{Device, RTU};
+
+use crate::config::CliConfig;
+use crate::rtus;
+
+/// Lowercases a name and replaces whitespace with underscores
+pub fn normalize(name: &str) -> String {
+    name.trim().to_lowercase().split_whitespace().collect::<Vec<_>>().join("_")
+}
+
+/// Why a word couldn't be resolved to a device
+#[derive(Debug, Clone, PartialEq)]
+pub enum ResolveError {
+    /// Nothing matched
+    NotFound(String),
+    /// More than one device matched. The message lists them.
+    Ambiguous(String),
+    /// A role matched, but its device doesn't exist
+    BadRole(String),
+}
+
+impl fmt::Display for ResolveError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ResolveError::NotFound(token) => write!(f, "No device with the ID, name, or role `{}`", token),
+            ResolveError::Ambiguous(msg) | ResolveError::BadRole(msg) => write!(f, "{}", msg),
+        }
+    }
+}
+
+/// Finds the one device that `token` refers to
+pub fn device<'a>(rtu: &'a RTU, config: &CliConfig, token: &str) -> Result<&'a Device, ResolveError> {
+    if let Some(device) = rtu.devices.iter().find(|dev| dev.id == token) {
+        return Ok(device);
+    }
+
+    let wanted = normalize(token);
+    // (device, how it matched)
+    let mut matches: Vec<(&Device, String)> = Vec::new();
+
+    for device in &rtu.devices {
+        let namespaced = device.id.split_once(rtus::SEPARATOR).map(|(rtu, _)| format!("{}{}{}", rtu, rtus::SEPARATOR, normalize(&device.name)));
+        if normalize(&device.name) == wanted || namespaced.as_deref() == Some(wanted.as_str()) {
+            matches.push((device, format!("named `{}`", device.name)));
+        }
+    }
+
+    for (role, device_id) in &config.roles {
+        if normalize(role) != wanted {
+            continue;
+        }
+        match rtu.devices.iter().find(|dev| dev.id == *device_id) {
+            Some(device) => matches.push((device, format!("the `{}` role", role))),
+            None => {
+                return Err(ResolveError::BadRole(format!(
+                    "The `{}` role points to `{}`, but there's no device with that ID",
+                    role, device_id
+                )))
+            }
+        }
+    }
+
+    // A device can match both by name and by role
+    let mut seen: Vec<String> = Vec::new();
+    matches.retain(|(dev, _)| {
+        let new = !seen.contains(&dev.id);
+        seen.push(dev.id.clone());
+        new
+    });
+
+    match matches.len() {
+        0 => Err(ResolveError::NotFound(token.to_string())),
+        1 => Ok(matches[0].0),
+        _ => {
+            let candidates = matches
+                .iter()
+                .map(|(dev, how)| format!("`{}` ({})", dev.id, how))
+                .collect::<Vec<_>>()
+                .join(", ");
+            Err(ResolveError::Ambiguous(format!(
+                "`{}` is ambiguous, it could be {}. Use the device ID instead",
+                token, candidates
+            )))
+        }
+    }
+}
+
+/// Every word that addresses a device (IDs, normalized names, and roles), for tab completion
+pub fn device_words(rtu: &RTU, config: &CliConfig) -> Vec<String> {
+    let mut words: Vec<String> = rtu.devices.iter().map(|dev| dev.id.clone()).collect();
+    words.extend(rtu.devices.iter().map(|dev| normalize(&dev.name)));
+    words.extend(config.roles.keys().map(|role| normalize(role)));
+    words.sort();
+    words.dedup();
+    words
+}
+
+/// Warns about roles that point nowhere, and names or roles that are shadowed by another device's ID
+pub fn check(rtu: &RTU, config: &CliConfig) -> Vec<String> {
+    let mut warnings = Vec::new();
+
+    for (role, device_id) in &config.roles {
+        if !rtu.devices.iter().any(|dev| dev.id == *device_id) {
+            warnings.push(format!("The `{}` role points to `{}`, but there's no device with that ID", role, device_id));
+        }
+        if let Some(dev) = rtu.devices.iter().find(|dev| dev.id == *role && dev.id != *device_id) {
+            warnings.push(format!("The `{}` role is also the ID of another device, so `{}` always means that device", role, dev.id));
+        }
+    }
+
+    for device in &rtu.devices {
+        let name = normalize(&device.name);
+        if let Some(other) = rtu.devices.iter().find(|dev| dev.id == name && dev.id != device.id) {
+            warnings.push(format!("`{}` is named `{}`, which is the ID of another device, so `{}` always means that device", device.id, device.name, other.id));
+        }
+    }
+
+    warnings
+}
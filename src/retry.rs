@@ -0,0 +1,252 @@
+//! Retrying device operations, and counting how they went
+//!
+//! RS-485 lines pick up noise, so a CRC error or a timeout doesn't mean a board is gone. Every
+//! operation on a board goes through [`Policy::run`] (or [`Policy::run_async`]), which retries the
+//! kinds of errors the controller's policy allows, backing off a little more each time. Each
+//! operation is also counted in the per-device [`Stats`] that the `stats` command shows.
+//!
+//! Policies are set per controller type in the `cli.retry` section of the config file:
+//!
+//! ```yaml
+//! cli:
+//!   retry:
+//!     CN7500:
+//!       attempts: 5
+//!       backoff: 200
+//!       retry_on: [timeout, crc]
+//! ```
+use std::collections::{BTreeMap, HashMap, VecDeque};
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::{Mutex, MutexGuard, OnceLock};
+use std::time::{Duration, Instant};
+
+use brewdrivers::controllers::{Controller, InstrumentError};
+use log::debug;
+use serde::Deserialize;
+
+/// The controller names that can be used in the `retry` section
+const CONTROLLERS: [&str; 4] = ["STR1", "Waveshare", "WaveshareV2", "CN7500"];
+
+/// How many latencies are kept per device for the percentiles
+const LATENCY_SAMPLES: usize = 1000;
+
+/// Broad kinds of errors, so a policy can say which ones are worth retrying
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ErrorKind {
+    /// The device didn't answer, or didn't answer in full
+    Timeout,
+    /// The answer was garbled
+    Crc,
+    /// Reading or writing the port failed
+    Io,
+    /// Anything else, like a missing port or a bad argument. Retrying these rarely helps.
+    Other,
+}
+
+impl ErrorKind {
+    /// Sorts an error into a kind. The drivers mostly report problems as messages, so this looks at those.
+    pub fn of(error: &InstrumentError) -> Self {
+        if let InstrumentError::ModbusTimeoutError { .. } = error {
+            return ErrorKind::Timeout;
+        }
+
+        let msg = error.to_string().to_lowercase();
+        if msg.contains("crc") || msg.contains("checksum") {
+            ErrorKind::Crc
+        } else if msg.contains("timed out") || msg.contains("didn't return") || msg.contains("did not return") || msg.contains("proper response") {
+            ErrorKind::Timeout
+        } else if let InstrumentError::IOError(_) = error {
+            ErrorKind::Io
+        } else if msg.contains("error writing") {
+            ErrorKind::Io
+        } else {
+            ErrorKind::Other
+        }
+    }
+}
+
+/// How to retry operations on one type of controller
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(default)]
+pub struct Policy {
+    /// How many times an operation is tried in total. 1 means no retries.
+    pub attempts: u32,
+    /// How long to wait before the first retry, in milliseconds
+    pub backoff: u64,
+    /// What the wait is multiplied by after each retry
+    pub multiplier: f64,
+    /// Which kinds of errors are retried
+    pub retry_on: Vec<ErrorKind>,
+}
+
+impl Default for Policy {
+    fn default() -> Self {
+        Self {
+            attempts: 3,
+            backoff: 100,
+            multiplier: 2.0,
+            retry_on: vec![ErrorKind::Timeout, ErrorKind::Crc, ErrorKind::Io],
+        }
+    }
+}
+
+static POLICIES: OnceLock<HashMap<String, Policy>> = OnceLock::new();
+
+/// Sets the policies from the config file. Returns a warning for every controller name it doesn't know.
+pub fn init(policies: &HashMap<String, Policy>) -> Vec<String> {
+    let warnings = policies
+        .keys()
+        .filter(|name| !CONTROLLERS.contains(&name.as_str()))
+        .map(|name| format!("`retry` has a policy for `{}`, which isn't a controller type. Use one of {}", name, CONTROLLERS.join(", ")))
+        .collect();
+
+    let _ = POLICIES.set(policies.clone());
+    warnings
+}
+
+/// The retry policy for a type of controller
+pub fn policy(controller: &Controller) -> Policy {
+    POLICIES
+        .get()
+        .and_then(|policies| policies.get(&controller.to_string()))
+        .cloned()
+        .unwrap_or_default()
+}
+
+type BoxFuture<'a, T> = Pin<Box<dyn Future<Output = T> + Send + 'a>>;
+
+impl Policy {
+    fn should_retry(&self, attempt: u32, error: &InstrumentError) -> bool {
+        attempt < self.attempts && self.retry_on.contains(&ErrorKind::of(error))
+    }
+
+    /// The wait before retry number `retry` (starting at 1)
+    fn wait(&self, retry: u32) -> Duration {
+        Duration::from_millis(self.backoff).mul_f64(self.multiplier.max(1.0).powi(retry as i32 - 1))
+    }
+
+    /// This policy with a single attempt, for operations that can't safely be repeated
+    pub fn once(&self) -> Policy {
+        Policy { attempts: 1, ..self.clone() }
+    }
+
+    /// The waits between attempts, added up, for an operation that fails every time
+    pub fn total_wait(&self) -> Duration {
+        (1..self.attempts).map(|retry| self.wait(retry)).sum()
+    }
+
+    /// Runs a blocking operation on `device_id`, retrying it as the policy allows. It sleeps between
+    /// attempts, so it's only run on threads of its own (see [`crate::boards`]).
+    pub fn run<T>(&self, device_id: &str, op: &str, mut f: impl FnMut() -> Result<T, InstrumentError>) -> Result<T, InstrumentError> {
+        let start = Instant::now();
+        let mut attempt = 1;
+        loop {
+            match f() {
+                Ok(value) => {
+                    record(device_id, attempt, Some(start.elapsed()));
+                    return Ok(value);
+                }
+                Err(e) if self.should_retry(attempt, &e) => {
+                    debug!("{} {} failed (attempt {} of {}), retrying: {}", device_id, op, attempt, self.attempts, e);
+                    std::thread::sleep(self.wait(attempt));
+                    attempt += 1;
+                }
+                Err(e) => {
+                    record(device_id, attempt, None);
+                    return Err(e);
+                }
+            }
+        }
+    }
+
+    /// Runs an async operation on `device_id`, retrying it as the policy allows
+    pub async fn run_async<'a, S: ?Sized, T>(
+        &self,
+        device_id: &str,
+        op: &str,
+        target: &'a mut S,
+        mut f: impl for<'b> FnMut(&'b mut S) -> BoxFuture<'b, Result<T, InstrumentError>>,
+    ) -> Result<T, InstrumentError> {
+        let start = Instant::now();
+        let mut attempt = 1;
+        loop {
+            match f(target).await {
+                Ok(value) => {
+                    record(device_id, attempt, Some(start.elapsed()));
+                    return Ok(value);
+                }
+                Err(e) if self.should_retry(attempt, &e) => {
+                    debug!("{} {} failed (attempt {} of {}), retrying: {}", device_id, op, attempt, self.attempts, e);
+                    tokio::time::sleep(self.wait(attempt)).await;
+                    attempt += 1;
+                }
+                Err(e) => {
+                    record(device_id, attempt, None);
+                    return Err(e);
+                }
+            }
+        }
+    }
+}
+
+/// How operations on one device have gone since the CLI started
+#[derive(Debug, Clone, Default)]
+pub struct Stats {
+    /// Operations that succeeded, possibly after retries
+    pub successes: u64,
+    /// Operations that failed after every attempt
+    pub failures: u64,
+    /// Extra attempts, across all operations
+    pub retries: u64,
+    /// How long the most recent successful operations took, retries included
+    latencies: VecDeque<Duration>,
+}
+
+impl Stats {
+    /// The latency that `percent` percent of recent successful operations came in under
+    pub fn percentile(&self, percent: f64) -> Option<Duration> {
+        if self.latencies.is_empty() {
+            return None;
+        }
+        let mut sorted: Vec<Duration> = self.latencies.iter().copied().collect();
+        sorted.sort();
+        let rank = ((percent / 100.0) * sorted.len() as f64).ceil() as usize;
+        Some(sorted[rank.clamp(1, sorted.len()) - 1])
+    }
+}
+
+static STATS: Mutex<BTreeMap<String, Stats>> = Mutex::new(BTreeMap::new());
+
+fn stats_map() -> MutexGuard<'static, BTreeMap<String, Stats>> {
+    // A poisoned lock only means another thread panicked while holding it, the counts are still usable
+    STATS.lock().unwrap_or_else(|e| e.into_inner())
+}
+
+/// Counts one operation. `latency` is `None` if it failed.
+fn record(device_id: &str, attempts: u32, latency: Option<Duration>) {
+    let mut map = stats_map();
+    let stats = map.entry(device_id.to_string()).or_default();
+    stats.retries += (attempts - 1) as u64;
+    match latency {
+        Some(latency) => {
+            stats.successes += 1;
+            if stats.latencies.len() == LATENCY_SAMPLES {
+                stats.latencies.pop_front();
+            }
+            stats.latencies.push_back(latency);
+        }
+        None => stats.failures += 1,
+    }
+}
+
+/// The stats for every device that has been used, by device ID
+pub fn stats() -> BTreeMap<String, Stats> {
+    stats_map().clone()
+}
+
+/// Forgets all the stats
+pub fn reset_stats() {
+    stats_map().clear();
+}
@@ -0,0 +1,128 @@
+//! Controlling other RTUs alongside this one
+//!
+//! The `cli.rtus` section of the config file lists other RTUs by name, each with its own config
+//! file (an RTU on this machine) or daemon socket (an RTU on another machine, usually reached with
+//! `ssh -L` forwarding its socket here):
+//!
+//! ```yaml
+//! cli:
+//!   rtus:
+//!     cellar:
+//!       config: /etc/NavasotaBrewing/cellar.yaml
+//!     hot:
+//!       socket: /run/nbc_cli/hot.sock
+//! ```
+//!
+//! Their devices are added to this RTU's, with their IDs namespaced by the RTU's name, like
+//! `cellar:fv1`. Devices from a config file are then used like any other. Commands for devices
+//! behind a socket are run by that RTU's daemon, and their values are read through it too.
+use std::collections::HashMap;
+use std::path::PathBuf;
+use std::sync::OnceLock;
+
+use brewdrivers::controllers::InstrumentError;
+use brewdrivers::model::{Device, RTU};
+use brewdrivers::state::DeviceState;
+
+use crate::config::{self, CliConfig, RtuSource};
+use crate::daemon::{self, Query};
+
+/// Separates an RTU's name from a device ID, like `cellar:fv1`
+pub const SEPARATOR: char = ':';
+
+/// A device on an RTU whose daemon runs its commands
+#[derive(Debug, Clone)]
+pub struct Remote {
+    /// The RTU's name, from `cli.rtus`
+    pub rtu: String,
+    pub socket: PathBuf,
+    /// The device's ID on its own RTU
+    pub id: String,
+}
+
+/// Devices behind a daemon, by their namespaced ID
+static REMOTES: OnceLock<HashMap<String, Remote>> = OnceLock::new();
+
+/// Whether a device ID belongs to another RTU
+pub fn is_namespaced(id: &str) -> bool {
+    id.contains(SEPARATOR)
+}
+
+/// The devices on another RTU, from its config file or its daemon
+async fn devices(source: &RtuSource) -> Result<(Vec<Device>, Option<PathBuf>), String> {
+    match (&source.config, &source.socket) {
+        (Some(config), None) => config::rtu(config).map(|rtu| (rtu.devices, None)).map_err(|e| format!("Couldn't read `{}`: {}", config, e)),
+        (None, Some(socket)) => {
+            let socket = PathBuf::from(socket);
+            daemon::query::<RTU>(&socket, Query::Rtu).await.map(|rtu| (rtu.devices, Some(socket)))
+        }
+        _ => Err("give either a `config` file or a daemon `socket`".to_string()),
+    }
+}
+
+/// Adds the devices of every RTU in `cli.rtus` to `rtu`, namespaced. RTUs that can't be reached
+/// are left out, with a warning for each.
+pub async fn load(rtu: &mut RTU, config: &CliConfig) -> Vec<String> {
+    let mut warnings = Vec::new();
+    let mut remotes = HashMap::new();
+
+    for (name, source) in &config.rtus {
+        if name.contains(SEPARATOR) || name.contains(char::is_whitespace) {
+            warnings.push(format!("Leaving out the `{}` RTU: its name can't have spaces or `{}` in it", name, SEPARATOR));
+            continue;
+        }
+        let (devices, socket) = match devices(source).await {
+            Ok(found) => found,
+            Err(e) => {
+                warnings.push(format!("Leaving out the `{}` RTU: {}", name, e));
+                continue;
+            }
+        };
+        for mut device in devices {
+            let id = format!("{}{}{}", name, SEPARATOR, device.id);
+            if let Some(socket) = &socket {
+                remotes.insert(id.clone(), Remote { rtu: name.clone(), socket: socket.clone(), id: device.id.clone() });
+                // Its port is on another machine, so it shouldn't be mistaken for one here
+                device.conn.port = PathBuf::from(format!("{}{}{}", name, SEPARATOR, device.conn.port.display()));
+            }
+            device.id = id;
+            rtu.devices.push(device);
+        }
+    }
+
+    let _ = REMOTES.set(remotes);
+    warnings
+}
+
+/// Where a device's commands are run, if it's on another RTU's daemon
+pub fn remote(device: &Device) -> Option<&'static Remote> {
+    REMOTES.get()?.get(&device.id)
+}
+
+/// Reads a device's values through its RTU's daemon
+pub async fn read(remote: &Remote) -> Result<DeviceState, InstrumentError> {
+    daemon::query::<Result<DeviceState, String>>(&remote.socket, Query::Read(remote.id.clone()))
+        .await
+        .and_then(|state| state)
+        .map_err(|e| InstrumentError::serialError(format!("`{}` RTU: {}", remote.rtu, e), None))
+}
+
+/// Has a device's RTU run a command for it, returning the exit status
+pub async fn run(remote: &Remote, args: &[String]) -> i32 {
+    let mut line = vec![remote.id.clone()];
+    line.extend(args.iter().cloned());
+    daemon::send_to(&remote.socket, line).await
+}
+
+/// Groups devices by RTU, this one first, with a title for each
+pub fn sections(rtu: &RTU) -> Vec<(String, Vec<&Device>)> {
+    let mut sections: Vec<(String, Vec<&Device>)> = vec![(rtu.name.clone(), Vec::new())];
+    for device in &rtu.devices {
+        let name = device.id.split_once(SEPARATOR).map(|(name, _)| name.to_string()).unwrap_or_else(|| rtu.name.clone());
+        match sections.iter_mut().find(|(section, _)| *section == name) {
+            Some((_, devices)) => devices.push(device),
+            None => sections.push((name, vec![device])),
+        }
+    }
+    sections
+}
@@ -0,0 +1,44 @@
+//! The shell state that every command is handed
+use serde::{Deserialize, Serialize};
+
+use brewdrivers::model::RTU;
+
+use crate::alarms::Alarms;
+use crate::config::CliConfig;
+use crate::recipe::LoadedRecipe;
+use crate::timers::Timers;
+
+/// Everything a command might need while the CLI is running.
+///
+/// Only the RTU is serialized (`exec` mode caches it between runs), so a `Session`
+/// serializes exactly like an `RTU` does. Everything else is runtime state.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(transparent)]
+pub struct Session {
+    /// The digital twin built from the config file
+    pub rtu: RTU,
+    /// CLI settings from the `cli` section of the config file
+    #[serde(skip)]
+    pub config: CliConfig,
+    /// Alarms, shared with the background poller
+    #[serde(skip)]
+    pub alarms: Alarms,
+    /// Brew day timers, shared with the background ticker
+    #[serde(skip)]
+    pub timers: Timers,
+    /// The recipe loaded with `recipe load`, if any, shared by the daemon's clients
+    #[serde(skip)]
+    pub recipe: LoadedRecipe,
+}
+
+impl Session {
+    pub fn new(rtu: RTU, config: CliConfig) -> Self {
+        Self {
+            rtu,
+            config,
+            alarms: Alarms::default(),
+            timers: Timers::default(),
+            recipe: LoadedRecipe::default(),
+        }
+    }
+}
@@ -0,0 +1,43 @@
+//! "Did you mean" suggestions for misspelled words
+
+/// The edit distance between two words, counting insertions, deletions, substitutions, and
+/// swapping two neighboring letters as one edit each
+fn distance(a: &str, b: &str) -> usize {
+    let a: Vec<char> = a.chars().collect();
+    let b: Vec<char> = b.chars().collect();
+    let mut d = vec![vec![0; b.len() + 1]; a.len() + 1];
+
+    for (i, row) in d.iter_mut().enumerate() {
+        row[0] = i;
+    }
+    for (j, cell) in d[0].iter_mut().enumerate() {
+        *cell = j;
+    }
+
+    for i in 1..=a.len() {
+        for j in 1..=b.len() {
+            let cost = if a[i - 1] == b[j - 1] { 0 } else { 1 };
+            d[i][j] = (d[i - 1][j] + 1).min(d[i][j - 1] + 1).min(d[i - 1][j - 1] + cost);
+            if i > 1 && j > 1 && a[i - 1] == b[j - 2] && a[i - 2] == b[j - 1] {
+                d[i][j] = d[i][j].min(d[i - 2][j - 2] + 1);
+            }
+        }
+    }
+
+    d[a.len()][b.len()]
+}
+
+/// The option closest to `word`, if any is close enough to be a likely typo
+pub fn closest<'a>(word: &str, options: impl IntoIterator<Item = &'a str>) -> Option<&'a str> {
+    let word = word.to_lowercase();
+    // Allow about one mistake per three letters
+    let limit = (word.chars().count() / 3).max(1);
+
+    options
+        .into_iter()
+        .map(|option| (distance(&word, &option.to_lowercase()), option))
+        .filter(|(d, _)| *d <= limit)
+        // Ties go to options that start with what was typed
+        .min_by_key(|(d, option)| (*d, !option.to_lowercase().starts_with(&word)))
+        .map(|(_, option)| option)
+}
@@ -10,6 +10,11 @@ fn bold(text: &str) -> String {
     format!("{}{}{}", termion::style::Bold, text, termion::style::Reset)
 }
 
+/// Makes text bold and red when printed. Used to highlight devices with an alarm going off
+fn alarm(text: &str) -> String {
+    format!("{}{}{}{}", termion::style::Bold, termion::color::Fg(termion::color::Red), text, termion::style::Reset)
+}
+
 /// Adds a row of 2 columns to a table.
 /// Used for cmds
 fn cmd(cmd: &str, help: &str) -> Row<'static> {
@@ -22,15 +27,17 @@ fn cmd(cmd: &str, help: &str) -> Row<'static> {
 /// Functions for creating the commands table
 pub mod commands {
     use super::*;
-    /// Creates the commands table. All static content
+    use brewdrivers::model::Device;
+    use crate::registry::{self, CommandGroup};
+    /// Creates the commands table from the registry
     pub fn render() -> String {
         let mut table = Table::new();
         table.max_column_width = 80;
         
         general_commands(&mut table);
-        waveshare_commands(&mut table);
-        str1_commands(&mut table);
-        cn7500_commands(&mut table);
+        for group in registry::GROUPS {
+            device_commands(&mut table, group);
+        }
         table.render()
     }
     
@@ -45,85 +52,68 @@ pub mod commands {
             TableCell::new_with_alignment(bold("Help"), 1, Alignment::Center),
         ]));
     
-        table.add_row(cmd("help", "displays help information."));
-        table.add_row(cmd("quit", "quits the shell"));
-        table.add_row(cmd("exit", "exits the shell"));
-        table.add_row(cmd("commands", "lists the commands page (this page)"));
-        table.add_row(cmd("devices", "list all configured devices"));
-        table.add_row(cmd("time", "prints the current time"));
-        table.add_row(cmd("dashboard", "view a dashboard of all device states"));
-    }
-    
-    /// Adds waveshare (v1 and v2) commands to the commands table
-    fn waveshare_commands(table: &mut Table) {
-        // Header row
-        table.add_row(Row::new(vec![
-            TableCell::new_with_alignment(bold("Waveshare (v1/v2) Commands"), 2, Alignment::Center)
-        ]));
-    
-        table.add_row(Row::new(vec![
-            TableCell::new_with_alignment(bold("Command"), 1, Alignment::Center),
-            TableCell::new_with_alignment(bold("Help"), 1, Alignment::Center),
-        ]));
-    
-        table.add_row(cmd("[relayID]", "Gets a relay status"));
-        table.add_row(cmd("[relayID] list_all", "Lists states of this and all the neighboring relays on this controller"));
-        table.add_row(cmd("[relayID] [On|Off]", "Turns a relay on or off"));
-        table.add_row(cmd("[relayID] set_all [On|Off]", "Sets this and all the neighboring relays on this controller"));
-        table.add_row(cmd("[relayID] get_cn", "Attempts to find the controller number the board is set to. The configured controller number (from the conf file) doesn't matter"));
-        table.add_row(cmd("[relayID] set_cn [0-254]", "Sets a new controller number for this controller. You'll need to update your rtu_conf.yaml file. Don't forget the controller number"));
-        table.add_row(cmd("[relayID] software_revision", "Lists the software revision currently on the board"));
+        for command in registry::GENERAL {
+            table.add_row(cmd(command.usage, command.help));
+        }
     }
     
-    /// Adds str1 commands to the commands table
-    fn str1_commands(table: &mut Table) {
+    /// Adds a group of device commands to the commands table
+    fn device_commands(table: &mut Table, group: &CommandGroup) {
         // Header row
         table.add_row(Row::new(vec![
-            TableCell::new_with_alignment(bold("STR1 Commands"), 2, Alignment::Center)
+            TableCell::new_with_alignment(bold(group.title), 2, Alignment::Center)
         ]));
-        
+
         table.add_row(Row::new(vec![
             TableCell::new_with_alignment(bold("Command"), 1, Alignment::Center),
             TableCell::new_with_alignment(bold("Help"), 1, Alignment::Center),
         ]));
-    
-        table.add_row(cmd("[relayID]", "Gets a relay status"));
-        table.add_row(cmd("[relayID] list_all", "Lists states of all the neighboring relays on this controller"));
-        table.add_row(cmd("[relayID] [On|Off]", "Turns a relay on or off"));
-        table.add_row(cmd("[relayID] set_cn [0-254]", "Sets a new controller number for this controller. You'll need to update your rtu_conf.yaml file. Don't forget the controller number"));
+
+        for command in group.commands {
+            table.add_row(cmd(&command.usage(group.device), &command.help_for(None)));
+        }
     }
-    
-    /// Adds cn7500 commands to the commands table
-    fn cn7500_commands(table: &mut Table) {
-        // Header row
+
+    /// Creates a table of the commands for one device, with its details filled in
+    pub fn render_group(group: &CommandGroup, device: &Device) -> String {
+        let mut table = Table::new();
+        table.max_column_width = 80;
+
         table.add_row(Row::new(vec![
-            TableCell::new_with_alignment(bold("CN7500 Commands"), 2, Alignment::Center)
+            TableCell::new_with_alignment(bold(&format!("{} ({})", device.id, device.name)), 2, Alignment::Center)
         ]));
-        
         table.add_row(Row::new(vec![
-            TableCell::new_with_alignment(bold("CN7500 Commands"), 1, Alignment::Center),
-            TableCell::new_with_alignment(bold("Help"), 1, Alignment::Center),
+            TableCell::new_with_alignment(
+                format!(
+                    "{}, controller {}, relay {}, on {} at {} baud",
+                    device.conn.controller(),
+                    device.conn.controller_addr(),
+                    device.conn.addr(),
+                    device.conn.port(),
+                    device.conn.baudrate()
+                ),
+                2,
+                Alignment::Center
+            )
         ]));
-    
-        table.add_row(cmd("[deviceID]", "Gets the PV, SV, and status of the relay"));
-        table.add_row(cmd("[deviceID] pv", "Gets the Process Value (actual)"));
-        table.add_row(cmd("[deviceID] sv", "Gets the Setpoint Value (target)"));
-        table.add_row(cmd("[deviceID] set [#.#]", "Sets the SV. Use a decimal number"));
-        table.add_row(cmd("[deviceID] is_running", "Returns the status of the relay"));
-        table.add_row(cmd("[deviceID] run", "Turns the relay on"));
-        table.add_row(cmd("[deviceID] stop", "Turns the relay off"));
-        table.add_row(cmd("[deviceID] degrees [F|C]", "Sets degree units to F or C"));
-        table.add_row(cmd("[deviceID] watch", "Prints the PV and SV every few seconds until you quit"));
+
+        for command in group.commands {
+            table.add_row(cmd(&command.usage(&device.id), &command.help_for(Some(device))));
+        }
+        table.add_row(cmd(&format!("{} help", device.id), "lists these commands"));
+        table.render()
     }
 
 }
 
 /// Functions for creating the devices table
 pub mod devices {
+    use brewdrivers::model::Device;
+
     use super::*;
 
-    /// Creates a table of all devices from the configuration
-    pub fn render(rtu: &RTU) -> String {
+    /// Creates a table of devices from the configuration, under `title`
+    pub fn render(title: &str, devices: &[&Device]) -> String {
         let mut table = Table::new();
         table.max_column_width = 40;
     
@@ -131,7 +121,7 @@ pub mod devices {
     
         // Title row
         table.add_row(Row::new(vec![
-            TableCell::new_with_alignment(bold("Configured Devices"), 3, Alignment::Center),
+            TableCell::new_with_alignment(bold(title), 3, Alignment::Center),
             TableCell::new_with_alignment(bold("Connection Details"), 5, Alignment::Center)
         ]));
     
@@ -148,16 +138,16 @@ pub mod devices {
         ]));
     
         // Values from devices
-        for device in &rtu.devices {
+        for device in devices {
             table.add_row(Row::new(vec![
                 TableCell::new_with_alignment(&device.id, 1, Alignment::Left),
                 TableCell::new_with_alignment(&device.name, 1, Alignment::Left),
-                TableCell::new_with_alignment(&device.conn.controller().to_string(), 1, Alignment::Left),
-                TableCell::new_with_alignment(&device.conn.controller_addr(), 1, Alignment::Left),
-                TableCell::new_with_alignment(&device.conn.addr(), 1, Alignment::Left),
-                TableCell::new_with_alignment(&device.conn.port(), 1, Alignment::Left),
-                TableCell::new_with_alignment(&device.conn.baudrate(), 1, Alignment::Left),
-                TableCell::new_with_alignment(&format!("{:?}", device.conn.timeout()), 1, Alignment::Left),
+                TableCell::new_with_alignment(device.conn.controller().to_string(), 1, Alignment::Left),
+                TableCell::new_with_alignment(device.conn.controller_addr(), 1, Alignment::Left),
+                TableCell::new_with_alignment(device.conn.addr(), 1, Alignment::Left),
+                TableCell::new_with_alignment(device.conn.port(), 1, Alignment::Left),
+                TableCell::new_with_alignment(device.conn.baudrate(), 1, Alignment::Left),
+                TableCell::new_with_alignment(format!("{:?}", device.conn.timeout()), 1, Alignment::Left),
             ]));
         }
     
@@ -168,14 +158,19 @@ pub mod devices {
 
 /// Functions for creating the dashboard table
 pub mod dashboard {
-    use std::convert::TryFrom;
-
-    use brewdrivers::{controllers::*, drivers::InstrumentError};
     use brewdrivers::model::Device;
 
     use super::*;
+    use super::timers::timer_row;
+    use crate::duration;
+    use crate::poller::{Reading, Readings};
+    use crate::rtus;
+    use crate::timers::Timer;
 
-    pub async fn render(rtu: &mut RTU) -> Result<String, InstrumentError> {
+    /// Renders the dashboard from the poller's latest readings. Devices in `firing` (device IDs) have an
+    /// alarm going off and are highlighted. Running timers are listed below the devices. With more
+    /// than one RTU, each gets its own section.
+    pub fn render(rtu: &RTU, readings: &Readings, firing: &[String], timers: &[Timer]) -> String {
         let mut table = Table::new();
         table.max_column_width = 80;
     
@@ -191,74 +186,362 @@ pub mod dashboard {
             TableCell::new_with_alignment(bold("SV"), 1, Alignment::Center)
         ]));
     
-        for dev in rtu.devices.iter_mut() {
-            match dev.conn.controller() {
-                Controller::CN7500 => cn7500_status(dev, &mut table).await?,
-                Controller::STR1 => str1_status(dev, &mut table)?,
-                Controller::Waveshare => waveshare_status(dev, &mut table)?,
-                Controller::WaveshareV2 => wavesharev2_status(dev, &mut table)?,
+        let sections = rtus::sections(rtu);
+        for (name, devices) in &sections {
+            if sections.len() > 1 {
+                table.add_row(Row::new(vec![TableCell::new_with_alignment(bold(name), 4, Alignment::Left)]));
+            }
+            for dev in devices {
+                device_status(dev, readings.get(&dev.id).as_ref(), firing, &mut table);
             }
         }
 
-        Ok(table.render())
+        if !timers.is_empty() {
+            table.add_row(Row::new(vec![
+                TableCell::new_with_alignment(bold("Timer"), 1, Alignment::Center),
+                TableCell::new_with_alignment(bold("Remaining"), 1, Alignment::Center),
+                TableCell::new_with_alignment(bold("Ends"), 1, Alignment::Center),
+                TableCell::new_with_alignment(bold("Then"), 1, Alignment::Center)
+            ]));
+
+            for timer in timers {
+                table.add_row(timer_row(timer));
+            }
+        }
+
+        table.render()
+    }
+
+    /// The device name, highlighted if the device has an alarm going off, and marked if its values are stale.
+    /// `reading` is `None` until the device has been polled once.
+    fn device_name(device: &Device, reading: Option<&Reading>, firing: &[String]) -> String {
+        let name = match reading {
+            None => format!("{} (waiting)", device.name),
+            Some(reading) if reading.last_good.is_none() => format!("{} (no response)", device.name),
+            Some(reading) => match reading.stale_for() {
+                Some(age) => format!("{} (stale {})", device.name, duration::format(age)),
+                None => device.name.clone(),
+            },
+        };
+
+        if firing.contains(&device.id) {
+            alarm(&format!("{} (ALARM)", name))
+        } else {
+            name
+        }
+    }
+
+    /// Formats a value, or `N/A` if the device doesn't have it
+    fn or_na<T: std::fmt::Display>(value: Option<T>) -> String {
+        value.map(|v| v.to_string()).unwrap_or_else(|| "N/A".to_string())
+    }
+
+    /// Adds a row for one device, showing the last values that were read from it
+    fn device_status(device: &Device, reading: Option<&Reading>, firing: &[String], table: &mut Table<'static>) {
+        let state = reading
+            .and_then(|r| r.last_good.as_ref())
+            .map(|(state, _)| state.clone())
+            .unwrap_or_default();
+
+        table.add_row(Row::new(vec![
+            TableCell::new_with_alignment(device_name(device, reading, firing), 1, Alignment::Left),
+            TableCell::new_with_alignment(or_na(state.relay_state), 1, Alignment::Left),
+            TableCell::new_with_alignment(or_na(state.pv), 1, Alignment::Left),
+            TableCell::new_with_alignment(or_na(state.sv), 1, Alignment::Left)
+        ]));
+    }
+}
+
+
+/// Functions for creating the alarms table
+pub mod alarms {
+    use super::*;
+    use crate::alarms::Alarm;
+    use crate::duration;
+
+    /// Creates a table of all alarms and their status
+    pub fn render(alarms: &[Alarm]) -> String {
+        let mut table = Table::new();
+        table.max_column_width = 40;
+
+        table.add_row(Row::new(vec![
+            TableCell::new_with_alignment(bold("ID"), 1, Alignment::Center),
+            TableCell::new_with_alignment(bold("Device"), 1, Alignment::Center),
+            TableCell::new_with_alignment(bold("Condition"), 1, Alignment::Center),
+            TableCell::new_with_alignment(bold("For"), 1, Alignment::Center),
+            TableCell::new_with_alignment(bold("Status"), 1, Alignment::Center),
+        ]));
+
+        for a in alarms {
+            let status = if a.is_firing() { alarm(&a.status.to_string()) } else { a.status.to_string() };
+            table.add_row(Row::new(vec![
+                TableCell::new_with_alignment(a.id, 1, Alignment::Left),
+                TableCell::new_with_alignment(&a.device_id, 1, Alignment::Left),
+                TableCell::new_with_alignment(a.condition.to_string(), 1, Alignment::Left),
+                TableCell::new_with_alignment(duration::format(a.hold), 1, Alignment::Left),
+                TableCell::new_with_alignment(status, 1, Alignment::Left),
+            ]));
+        }
+
+        table.render()
     }
+}
 
-    async fn cn7500_status(device: &mut Device, table: &mut Table<'static>) -> Result<(), InstrumentError> {
-        let c = &device.conn;
-        let port = c.port();
+/// Functions for creating the timers table
+pub mod stats {
+    use super::*;
+    use crate::retry::Stats;
 
-        let mut cont = CN7500::connect(
-            c.controller_addr(),
-            &port,
-            *c.baudrate() as u64,
-            c.timeout(),
-        ).await?;
+    /// Formats a latency in milliseconds
+    fn millis(latency: Option<std::time::Duration>) -> String {
+        latency.map(|l| format!("{:.1}ms", l.as_secs_f64() * 1000.0)).unwrap_or_else(|| "N/A".to_string())
+    }
+
+    /// Creates a table of operation counts and latencies for every device
+    pub fn render(rtu: &RTU, stats: &std::collections::BTreeMap<String, Stats>) -> String {
+        let mut table = Table::new();
+        table.max_column_width = 40;
 
         table.add_row(Row::new(vec![
-            TableCell::new_with_alignment(format!("{}", device.name), 1, Alignment::Left),
-            TableCell::new_with_alignment(format!("{}", cont.is_running().await?), 1, Alignment::Left),
-            TableCell::new_with_alignment(format!("{}", cont.get_pv().await?), 1, Alignment::Left),
-            TableCell::new_with_alignment(format!("{}", cont.get_sv().await?), 1, Alignment::Left)
+            TableCell::new_with_alignment(bold("Device"), 1, Alignment::Center),
+            TableCell::new_with_alignment(bold("OK"), 1, Alignment::Center),
+            TableCell::new_with_alignment(bold("Failed"), 1, Alignment::Center),
+            TableCell::new_with_alignment(bold("Retries"), 1, Alignment::Center),
+            TableCell::new_with_alignment(bold("p50"), 1, Alignment::Center),
+            TableCell::new_with_alignment(bold("p90"), 1, Alignment::Center),
+            TableCell::new_with_alignment(bold("p99"), 1, Alignment::Center),
+            TableCell::new_with_alignment(bold("Max"), 1, Alignment::Center),
         ]));
 
-        Ok(())
+        for device in &rtu.devices {
+            let stats = stats.get(&device.id).cloned().unwrap_or_default();
+            table.add_row(Row::new(vec![
+                TableCell::new_with_alignment(&device.id, 1, Alignment::Left),
+                TableCell::new_with_alignment(stats.successes, 1, Alignment::Right),
+                TableCell::new_with_alignment(stats.failures, 1, Alignment::Right),
+                TableCell::new_with_alignment(stats.retries, 1, Alignment::Right),
+                TableCell::new_with_alignment(millis(stats.percentile(50.0)), 1, Alignment::Right),
+                TableCell::new_with_alignment(millis(stats.percentile(90.0)), 1, Alignment::Right),
+                TableCell::new_with_alignment(millis(stats.percentile(99.0)), 1, Alignment::Right),
+                TableCell::new_with_alignment(millis(stats.percentile(100.0)), 1, Alignment::Right),
+            ]));
+        }
+
+        table.render()
     }
+}
+
+pub mod audit {
+    use super::*;
+    use chrono::DateTime;
+    use crate::audit::Entry;
+
+    /// Creates a table of audit journal entries
+    pub fn render(entries: &[Entry]) -> String {
+        let mut table = Table::new();
+        table.max_column_width = 40;
 
-    fn str1_status(device: &Device, table: &mut Table<'static>) -> Result<(), InstrumentError> {
-        let mut cont = STR1::try_from(device)?;
         table.add_row(Row::new(vec![
-            TableCell::new_with_alignment(format!("{}", device.name), 1, Alignment::Left),
-            TableCell::new_with_alignment(format!("{}", cont.get_relay(device.conn.addr())?), 1, Alignment::Left),
-            TableCell::new_with_alignment(format!("N/A"), 1, Alignment::Left),
-            TableCell::new_with_alignment(format!("N/A"), 1, Alignment::Left)
+            TableCell::new_with_alignment(bold("Time"), 1, Alignment::Center),
+            TableCell::new_with_alignment(bold("User"), 1, Alignment::Center),
+            TableCell::new_with_alignment(bold("Mode"), 1, Alignment::Center),
+            TableCell::new_with_alignment(bold("Device"), 1, Alignment::Center),
+            TableCell::new_with_alignment(bold("Command"), 1, Alignment::Center),
+            TableCell::new_with_alignment(bold("Previous"), 1, Alignment::Center),
+            TableCell::new_with_alignment(bold("New"), 1, Alignment::Center),
+            TableCell::new_with_alignment(bold("Result"), 1, Alignment::Center),
         ]));
 
-        Ok(())
+        for entry in entries {
+            let time = match DateTime::parse_from_rfc3339(&entry.time) {
+                Ok(time) => time.with_timezone(&Local).format(crate::TIME_FORMAT).to_string(),
+                Err(_) => entry.time.clone(),
+            };
+            let result = match entry.result.as_str() {
+                "ok" => "ok".to_string(),
+                error => alarm(error),
+            };
+            table.add_row(Row::new(vec![
+                TableCell::new_with_alignment(time, 1, Alignment::Left),
+                TableCell::new_with_alignment(&entry.user, 1, Alignment::Left),
+                TableCell::new_with_alignment(entry.mode, 1, Alignment::Left),
+                TableCell::new_with_alignment(&entry.device, 1, Alignment::Left),
+                TableCell::new_with_alignment(&entry.command, 1, Alignment::Left),
+                TableCell::new_with_alignment(entry.previous.as_deref().unwrap_or("N/A"), 1, Alignment::Left),
+                TableCell::new_with_alignment(&entry.new, 1, Alignment::Left),
+                TableCell::new_with_alignment(result, 1, Alignment::Left),
+            ]));
+        }
+
+        table.render()
     }
+}
+
+pub mod health {
+    use super::*;
+    use brewdrivers::model::Device;
+    use crate::health::Check;
+
+    /// Creates a table of what the health check found for each device
+    pub fn render(checks: &[(Device, Check)]) -> String {
+        let mut table = Table::new();
+        table.max_column_width = 40;
 
-    fn waveshare_status(device: &Device, table: &mut Table) -> Result<(), InstrumentError> {
-        let mut cont = Waveshare::try_from(device)?;
         table.add_row(Row::new(vec![
-            TableCell::new_with_alignment(format!("{}", device.name), 1, Alignment::Left),
-            TableCell::new_with_alignment(format!("{}", cont.get_relay(device.conn.addr())?), 1, Alignment::Left),
-            TableCell::new_with_alignment(format!("N/A"), 1, Alignment::Left),
-            TableCell::new_with_alignment(format!("N/A"), 1, Alignment::Left)
+            TableCell::new_with_alignment(bold("Device"), 1, Alignment::Center),
+            TableCell::new_with_alignment(bold("Port"), 1, Alignment::Center),
+            TableCell::new_with_alignment(bold("Status"), 1, Alignment::Center),
+            TableCell::new_with_alignment(bold("Latency"), 1, Alignment::Center),
+            TableCell::new_with_alignment(bold("Firmware"), 1, Alignment::Center),
+            TableCell::new_with_alignment(bold("Controller #"), 1, Alignment::Center),
         ]));
 
-        Ok(())
+        for (device, check) in checks {
+            let status = match (&check.error, check.problem(device)) {
+                (Some(_), _) => alarm("Unreachable"),
+                (None, Some(_)) => alarm("Mismatch"),
+                (None, None) => "OK".to_string(),
+            };
+            let latency = check.latency.map(|l| format!("{:.1}ms", l.as_secs_f64() * 1000.0)).unwrap_or_else(|| "N/A".to_string());
+            let address = match check.address {
+                Some(address) if address != device.conn.controller_addr() => alarm(&format!("{} (config: {})", address, device.conn.controller_addr())),
+                Some(address) => address.to_string(),
+                None => device.conn.controller_addr().to_string(),
+            };
+            table.add_row(Row::new(vec![
+                TableCell::new_with_alignment(&device.id, 1, Alignment::Left),
+                TableCell::new_with_alignment(device.conn.port(), 1, Alignment::Left),
+                TableCell::new_with_alignment(status, 1, Alignment::Left),
+                TableCell::new_with_alignment(latency, 1, Alignment::Right),
+                TableCell::new_with_alignment(check.firmware.as_deref().unwrap_or("N/A"), 1, Alignment::Left),
+                TableCell::new_with_alignment(address, 1, Alignment::Right),
+            ]));
+        }
+
+        table.render()
     }
+}
+
+pub mod trace {
+    use super::*;
+    use crate::trace::Frame;
+
+    /// Creates a hex dump of traced frames
+    pub fn render(frames: &[Frame]) -> String {
+        let mut table = Table::new();
+        table.max_column_width = 60;
 
-    fn wavesharev2_status(device: &Device, table: &mut Table) -> Result<(), InstrumentError> {
-        let mut cont = WaveshareV2::try_from(device)?;
         table.add_row(Row::new(vec![
-            TableCell::new_with_alignment(format!("{}", device.name), 1, Alignment::Left),
-            TableCell::new_with_alignment(format!("{}", cont.get_relay(device.conn.addr())?), 1, Alignment::Left),
-            TableCell::new_with_alignment(format!("N/A"), 1, Alignment::Left),
-            TableCell::new_with_alignment(format!("N/A"), 1, Alignment::Left)
+            TableCell::new_with_alignment(bold("Time"), 1, Alignment::Center),
+            TableCell::new_with_alignment(bold("Port"), 1, Alignment::Center),
+            TableCell::new_with_alignment(bold("Dir"), 1, Alignment::Center),
+            TableCell::new_with_alignment(bold("Device"), 1, Alignment::Center),
+            TableCell::new_with_alignment(bold("Hex"), 1, Alignment::Center),
+            TableCell::new_with_alignment(bold("Decoded"), 1, Alignment::Center),
         ]));
 
-        Ok(())
+        for frame in frames {
+            // Just the time of day, the full timestamp is in the trace file
+            let time = frame.time.get(11..26).unwrap_or(&frame.time);
+            table.add_row(Row::new(vec![
+                TableCell::new_with_alignment(time, 1, Alignment::Left),
+                TableCell::new_with_alignment(&frame.port, 1, Alignment::Left),
+                TableCell::new_with_alignment(frame.dir, 1, Alignment::Left),
+                TableCell::new_with_alignment(frame.device.as_deref().unwrap_or("?"), 1, Alignment::Left),
+                TableCell::new_with_alignment(&frame.hex, 1, Alignment::Left),
+                TableCell::new_with_alignment(frame.decoded.as_deref().unwrap_or(""), 1, Alignment::Left),
+            ]));
+        }
+
+        table.render()
+    }
+}
+
+pub mod timers {
+    use super::*;
+    use crate::duration;
+    use crate::timers::Timer;
+
+    /// A row for one timer: name, time remaining, when it ends, and the command it runs
+    pub fn timer_row(timer: &Timer) -> Row<'static> {
+        Row::new(vec![
+            TableCell::new_with_alignment(&timer.name, 1, Alignment::Left),
+            TableCell::new_with_alignment(duration::format(timer.remaining()), 1, Alignment::Left),
+            TableCell::new_with_alignment(timer.ends.format("%H:%M:%S"), 1, Alignment::Left),
+            TableCell::new_with_alignment(timer.then.join(" "), 1, Alignment::Left),
+        ])
     }
 
+    /// Creates a table of all running timers
+    pub fn render(timers: &[Timer]) -> String {
+        let mut table = Table::new();
+        table.max_column_width = 40;
+
+        table.add_row(Row::new(vec![
+            TableCell::new_with_alignment(bold("Timer"), 1, Alignment::Center),
+            TableCell::new_with_alignment(bold("Remaining"), 1, Alignment::Center),
+            TableCell::new_with_alignment(bold("Ends"), 1, Alignment::Center),
+            TableCell::new_with_alignment(bold("Then"), 1, Alignment::Center),
+        ]));
+
+        for timer in timers {
+            table.add_row(timer_row(timer));
+        }
+
+        table.render()
+    }
 }
 
+/// Functions for creating the brew day plan table
+pub mod recipe {
+    use super::*;
+    use crate::config::TempUnit;
+    use crate::recipe::Recipe;
+
+    fn header(table: &mut Table, title: &str, columns: [&str; 4]) {
+        table.add_row(Row::new(vec![
+            TableCell::new_with_alignment(bold(title), 4, Alignment::Center)
+        ]));
+        table.add_row(Row::new(columns.iter().map(|c| TableCell::new_with_alignment(bold(c), 1, Alignment::Center)).collect::<Vec<_>>()));
+    }
+
+    /// Creates the brew day plan for a recipe. Temperatures are shown in `unit`.
+    pub fn render(recipe: &Recipe, unit: TempUnit) -> String {
+        let mut table = Table::new();
+        table.max_column_width = 40;
+
+        table.add_row(Row::new(vec![
+            TableCell::new_with_alignment(bold(&recipe.name), 4, Alignment::Center)
+        ]));
+
+        header(&mut table, "Mash", ["Timer", "Step", "Target", "Time"]);
+        for (i, step) in recipe.mash_steps.iter().enumerate() {
+            table.add_row(Row::new(vec![
+                TableCell::new_with_alignment(format!("mash_{}", i + 1), 1, Alignment::Left),
+                TableCell::new_with_alignment(&step.name, 1, Alignment::Left),
+                TableCell::new_with_alignment(unit.format(step.temp_c), 1, Alignment::Left),
+                TableCell::new_with_alignment(format!("{} min", step.minutes), 1, Alignment::Left),
+            ]));
+        }
+        if let Some(sparge) = recipe.sparge_temp_c {
+            table.add_row(Row::new(vec![
+                TableCell::new_with_alignment("hlt", 1, Alignment::Left),
+                TableCell::new_with_alignment("Sparge", 1, Alignment::Left),
+                TableCell::new_with_alignment(unit.format(sparge), 1, Alignment::Left),
+                TableCell::new_with_alignment("", 1, Alignment::Left),
+            ]));
+        }
+
+        header(&mut table, &format!("Boil ({} min)", recipe.boil_minutes), ["Hop", "Amount", "Use", "Time"]);
+        for hop in &recipe.hops {
+            table.add_row(Row::new(vec![
+                TableCell::new_with_alignment(&hop.name, 1, Alignment::Left),
+                TableCell::new_with_alignment(&hop.amount, 1, Alignment::Left),
+                TableCell::new_with_alignment(&hop.use_, 1, Alignment::Left),
+                TableCell::new_with_alignment(format!("{} min", hop.minutes), 1, Alignment::Left),
+            ]));
+        }
+
+        table.render()
+    }
+}
@@ -0,0 +1,232 @@
+//! Brew day timers
+//!
+//! Named countdowns for the boil and hop additions. A timer either runs for a duration starting
+//! now, or ends relative to another timer, so hop additions can be scheduled against the boil:
+//!
+//! ```text
+//! timer add boil 60m then kettle_heater stop
+//! timer add cascade at boil-15m
+//! ```
+//!
+//! When a timer ends the bell rings, the `timer_hook` is run, and the `then` command is dispatched
+//! like it was typed into the shell. Quote it to run a chain, like `then "kettle stop && kettle_pump Off"`.
+use std::collections::HashMap;
+use std::error::Error;
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::time::Duration;
+
+use chrono::{DateTime, Local};
+use log::{error, info, warn};
+use shellfish::Command;
+
+use crate::audit::{self, Mode};
+use crate::dispatch::{self, Dispatcher};
+use crate::output::Failed;
+use crate::session::Session;
+use crate::{duration, hooks, prompt, registry, repl, resolve, tables};
+
+#[derive(Debug, Clone)]
+pub struct Timer {
+    pub name: String,
+    pub ends: DateTime<Local>,
+    /// A command to run when the timer ends, split into arguments. Empty if there isn't one.
+    pub then: Vec<String>,
+}
+
+impl Timer {
+    /// Time left on the timer, zero once it has ended
+    pub fn remaining(&self) -> Duration {
+        (self.ends - Local::now()).to_std().unwrap_or(Duration::ZERO)
+    }
+}
+
+/// All running timers. Cheap to clone, every clone shares the same timers.
+#[derive(Debug, Clone, Default)]
+pub struct Timers(Arc<Mutex<Vec<Timer>>>);
+
+impl Timers {
+    fn timers(&self) -> MutexGuard<'_, Vec<Timer>> {
+        self.0.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    /// Adds a timer. Fails if there's already a timer with that name.
+    pub fn add(&self, timer: Timer) -> Result<(), String> {
+        let mut timers = self.timers();
+        if timers.iter().any(|t| t.name == timer.name) {
+            return Err(format!("There's already a timer named `{}`", timer.name));
+        }
+        timers.push(timer);
+        timers.sort_by_key(|t| t.ends);
+        Ok(())
+    }
+
+    /// A copy of every timer, soonest first
+    pub fn list(&self) -> Vec<Timer> {
+        self.timers().clone()
+    }
+
+    /// Removes a timer. `None` removes every timer.
+    ///
+    /// Returns the number of timers removed
+    pub fn cancel(&self, name: Option<&str>) -> usize {
+        let mut timers = self.timers();
+        let before = timers.len();
+        timers.retain(|t| name.is_some() && name != Some(t.name.as_str()));
+        before - timers.len()
+    }
+
+    /// Removes and returns every timer that has ended
+    fn take_due(&self) -> Vec<Timer> {
+        let now = Local::now();
+        let mut timers = self.timers();
+        let (due, running) = timers.drain(..).partition(|t| t.ends <= now);
+        *timers = running;
+        due
+    }
+
+    /// Works out when an `at` timer ends from a spec like `boil-15m`, `boil+5m` or just `boil`
+    fn resolve_at(&self, spec: &str) -> Result<DateTime<Local>, String> {
+        let timers = self.timers();
+        let find = |name: &str| timers.iter().find(|t| t.name == name).map(|t| t.ends);
+
+        if let Some(ends) = find(spec) {
+            return Ok(ends);
+        }
+
+        let split = spec.rfind(['-', '+']).ok_or_else(|| format!("No timer named `{}`", spec))?;
+        let (name, offset) = spec.split_at(split);
+        let ends = find(name).ok_or_else(|| format!("No timer named `{}`", name))?;
+        let offset = duration::parse(&offset[1..])
+            .and_then(|d| chrono::Duration::from_std(d).ok())
+            .ok_or_else(|| format!("Couldn't parse `{}` as a duration", &offset[1..]))?;
+
+        let moved = if spec[split..].starts_with('+') { ends.checked_add_signed(offset) } else { ends.checked_sub_signed(offset) };
+        moved.ok_or_else(|| format!("`{}` is too far off to set a timer for", spec))
+    }
+}
+
+/// Starts the background task that ends timers. `commands` is a copy of the shell's commands, so
+/// `then` commands can be dispatched without the shell.
+pub fn spawn_ticker(mut session: Session, commands: HashMap<String, Command<Session>>) {
+    tokio::spawn(async move {
+        let commands: HashMap<&str, Command<Session>> = commands.iter().map(|(name, cmd)| (name.as_str(), cmd.clone())).collect();
+        loop {
+            tokio::time::sleep(Duration::from_secs(1)).await;
+
+            for timer in session.timers.take_due() {
+                hooks::bell();
+                warn!("Timer `{}` is done!", timer.name);
+
+                if let Some(hook) = &session.config.timer_hook {
+                    hooks::run(hook, &[("NBC_TIMER_NAME", timer.name.clone())]);
+                }
+
+                if !timer.then.is_empty() {
+                    info!("Running `{}`", timer.then.join(" "));
+                    // A quoted chain, like `then "kettle stop && kettle_pump Off"`
+                    match repl::chain_args(&timer.then) {
+                        Ok(chain) => {
+                            audit::scoped(Mode::Script, dispatch::run_chain(&Dispatcher, chain, &commands, &mut session, "")).await;
+                        }
+                        Err(e) => error!("{}", e),
+                    }
+                }
+            }
+        }
+    });
+}
+
+/// Prints the usage for `timer add`
+fn add_usage() {
+    error!("Usage: timer add [name] [duration] (then [command])");
+    error!("       timer add [name] at [timer](-|+)[duration] (then [command])");
+}
+
+/// The `timer` shell command
+pub async fn command(session: &mut Session, args: Vec<String>) -> Result<(), Box<dyn Error>> {
+    let timers = &session.timers;
+
+    match args.get(1).map(|s| s.as_str()) {
+        None | Some("list") => outln!("{}", tables::timers::render(&timers.list())),
+        Some("add") => {
+            let (name, when) = match (args.get(2), args.get(3)) {
+                (Some(name), Some(when)) => (name.clone(), when.as_str()),
+                _ => {
+                    add_usage();
+                    return Err(Failed.into());
+                }
+            };
+
+            // `at` takes one more argument than a plain duration
+            let (ends, rest) = if when == "at" {
+                match args.get(4).map(|spec| timers.resolve_at(spec)) {
+                    Some(Ok(ends)) => (ends, &args[5..]),
+                    Some(Err(e)) => {
+                        error!("{}", e);
+                        return Err(Failed.into());
+                    }
+                    None => {
+                        add_usage();
+                        return Err(Failed.into());
+                    }
+                }
+            } else {
+                match duration::parse(when).and_then(|d| chrono::Duration::from_std(d).ok()).and_then(|d| Local::now().checked_add_signed(d)) {
+                    Some(ends) => (ends, &args[4..]),
+                    None => {
+                        error!("Couldn't parse `{}` as a duration, try something like `60m` or `1h30m`", when);
+                        return Err(Failed.into());
+                    }
+                }
+            };
+
+            let then = match rest.split_first() {
+                None => vec![],
+                Some((kw, cmd)) if kw == "then" && !cmd.is_empty() => cmd.to_vec(),
+                _ => {
+                    add_usage();
+                    return Err(Failed.into());
+                }
+            };
+
+            if ends <= Local::now() {
+                error!("Timer `{}` would have already ended at {}", name, ends.format("%H:%M:%S"));
+                return Err(Failed.into());
+            }
+
+            // Nobody's there to confirm a destructive command when the timer ends, so ask now
+            if let Some(device) = then.first().and_then(|first| resolve::device(&session.rtu, &session.config, first).ok()) {
+                if registry::is_destructive(device, &then[1..])
+                    && !prompt::confirm(&format!("`{}` will run without asking when the timer ends. Add the timer?", then.join(" "))).await
+                {
+                    info!("Cancelled");
+                    return Ok(());
+                }
+            }
+
+            let timer = Timer { name, ends, then };
+            let summary = format!("Timer `{}` ends at {} ({})", timer.name, timer.ends.format("%H:%M:%S"), duration::format(timer.remaining()));
+            match timers.add(timer) {
+                Ok(_) => info!("{}", summary),
+                Err(e) => {
+                    error!("{}", e);
+                    return Err(Failed.into());
+                }
+            }
+        }
+        Some("cancel") => match args.get(2).map(|s| s.as_str()) {
+            Some("all") => info!("Cancelled {} timer(s)", timers.cancel(None)),
+            Some(name) => info!("Cancelled {} timer(s)", timers.cancel(Some(name))),
+            None => {
+                error!("Usage: timer cancel [name|all]");
+                return Err(Failed.into());
+            }
+        },
+        Some(other) => {
+            error!("Unknown timer command `{}`. Use list, add, or cancel", other);
+            return Err(Failed.into());
+        }
+    }
+
+    Ok(())
+}
@@ -0,0 +1,447 @@
+//! Serial bus tracing
+//!
+//! The drivers open their serial ports themselves, so the CLI can't see what they send. To trace a
+//! port, the CLI opens it and puts a pseudo terminal in front of it. Devices on that port are
+//! pointed at the pseudo terminal when they connect (see [`route`]), and every byte that passes
+//! through in either direction is copied to the other side and recorded.
+//!
+//! Bytes are grouped into frames by the gaps between them. Each frame is written to the trace file
+//! as one line of JSON:
+//!
+//! ```json
+//! {"time":"2026-10-19T09:17:28.123456-05:00","port":"/dev/ttyUSB0","dir":"tx","device":"mash_cn","hex":"16 03 10 00 00 01 80 E0","decoded":"addr 22: read register 0x1000 (1)"}
+//! ```
+//!
+//! `hex` holds the exact bytes, so a trace can be replayed. `decoded` is only there for people.
+use std::collections::{BTreeMap, VecDeque};
+use std::error::Error;
+use std::fmt;
+use std::fs::File;
+use std::io::{ErrorKind, Read, Write};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::thread::JoinHandle;
+use std::time::Duration;
+
+use brewdrivers::controllers::Controller;
+use brewdrivers::model::{Device, RTU};
+use chrono::{Local, SecondsFormat};
+use log::{error, info};
+use serde::{Deserialize, Serialize};
+use serialport::{SerialPort, TTYPort};
+
+use crate::output::Failed;
+use crate::session::Session;
+use crate::{modbus, resolve, rtus, tables, transport};
+
+/// Bytes that arrive closer together than this are part of the same frame
+const GAP: Duration = Duration::from_millis(10);
+
+/// How many reads in a row can fail, like after a USB adapter is unplugged, before a tap gives up
+const MAX_READ_ERRORS: u32 = 100;
+
+/// How many frames `trace show` can look back on
+const RECENT_FRAMES: usize = 500;
+
+/// Which way a frame went
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Direction {
+    /// From the CLI to the device
+    Tx,
+    /// From the device to the CLI
+    Rx,
+}
+
+impl fmt::Display for Direction {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Direction::Tx => write!(f, "tx"),
+            Direction::Rx => write!(f, "rx"),
+        }
+    }
+}
+
+/// One frame on the bus. This is what each line of a trace file holds.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Frame {
+    /// RFC 3339, with microseconds
+    pub time: String,
+    pub port: String,
+    pub dir: Direction,
+    /// The device the frame was addressed to (or answering for), if it could be worked out
+    pub device: Option<String>,
+    /// The bytes, as space separated hex
+    pub hex: String,
+    /// What the frame means, for Modbus frames
+    pub decoded: Option<String>,
+}
+
+/// What a tap's threads share
+struct Bus {
+    port: String,
+    devices: Vec<Device>,
+    /// The device that connected most recently, used when an address matches more than one device
+    current: Mutex<Option<String>>,
+    /// The device the last request was sent to, which the next response is from
+    last_request: Mutex<Option<Device>>,
+}
+
+impl Bus {
+    /// Works out which device a request is for, from the address in it
+    fn addressee(&self, frame: &[u8]) -> Option<Device> {
+        // STR1 frames start with 55 AA, then the length, the command, and the controller number
+        let (str1, addr) = match frame {
+            [0x55, 0xAA, _, _, cn, ..] => (true, *cn),
+            [addr, ..] => (false, *addr),
+            [] => return None,
+        };
+
+        let matches: Vec<&Device> = self
+            .devices
+            .iter()
+            .filter(|dev| dev.conn.controller_addr() == addr && (*dev.conn.controller() == Controller::STR1) == str1)
+            .collect();
+
+        let current = self.current.lock().unwrap_or_else(|e| e.into_inner()).clone();
+        matches
+            .iter()
+            .find(|dev| Some(&dev.id) == current.as_ref())
+            .or_else(|| matches.first())
+            .map(|dev| (*dev).clone())
+    }
+
+    fn frame(&self, dir: Direction, bytes: &[u8]) -> Frame {
+        let device = match dir {
+            Direction::Tx => self.addressee(bytes),
+            Direction::Rx => self.last_request.lock().unwrap_or_else(|e| e.into_inner()).clone(),
+        };
+        let device = device.as_ref();
+        let modbus = device.map(|dev| *dev.conn.controller() != Controller::STR1).unwrap_or(true);
+        let decoded = match (modbus, dir) {
+            (false, _) => None,
+            (true, Direction::Tx) => modbus::describe_request(bytes),
+            (true, Direction::Rx) => modbus::describe_response(bytes),
+        };
+
+        Frame {
+            time: Local::now().to_rfc3339_opts(SecondsFormat::Micros, false),
+            port: self.port.clone(),
+            dir,
+            device: device.map(|dev| dev.id.clone()),
+            hex: modbus::hex(bytes),
+            decoded,
+        }
+    }
+}
+
+/// A traced port
+struct Tap {
+    /// The pseudo terminal that devices on the port are pointed at
+    pty: String,
+    bus: Arc<Bus>,
+    stop: Arc<AtomicBool>,
+    threads: Vec<JoinHandle<()>>,
+    /// Held open so the pseudo terminal stays up between connections
+    _slave: TTYPort,
+}
+
+#[derive(Default)]
+struct Tracer {
+    taps: BTreeMap<String, Tap>,
+    file: Option<(String, File)>,
+    recent: VecDeque<Frame>,
+}
+
+static TRACER: Mutex<Option<Tracer>> = Mutex::new(None);
+
+fn with_tracer<T>(f: impl FnOnce(&mut Tracer) -> T) -> T {
+    // A poisoned lock only means another thread panicked while holding it, the trace is still usable
+    let mut guard: MutexGuard<'_, Option<Tracer>> = TRACER.lock().unwrap_or_else(|e| e.into_inner());
+    f(guard.get_or_insert_with(Tracer::default))
+}
+
+/// Records a frame in the trace file and the recent frames
+fn record(frame: Frame) {
+    with_tracer(|tracer| {
+        if let Some((path, file)) = &mut tracer.file {
+            let line = serde_json::to_string(&frame).unwrap_or_default();
+            if let Err(e) = writeln!(file, "{}", line) {
+                error!("Couldn't write to the trace file `{}`: {}", path, e);
+            }
+        }
+        if tracer.recent.len() == RECENT_FRAMES {
+            tracer.recent.pop_front();
+        }
+        tracer.recent.push_back(frame);
+    });
+}
+
+/// Copies bytes from `from` to `to`, recording each frame that passes. Modbus frames are recorded
+/// as soon as they're complete, anything else when the bytes stop coming.
+///
+/// If `from` can't be read [`MAX_READ_ERRORS`] times in a row, the tap is stopped and taken off
+/// the port, so its devices are connected to directly again.
+fn pump(dir: Direction, mut from: TTYPort, mut to: TTYPort, bus: Arc<Bus>, stop: Arc<AtomicBool>) {
+    let mut frame: Vec<u8> = Vec::new();
+    let mut buf = [0u8; 256];
+    let mut errors = 0;
+
+    loop {
+        let stopping = stop.load(Ordering::Relaxed);
+        match from.read(&mut buf) {
+            Ok(n) if n > 0 => {
+                errors = 0;
+                let chunk = &buf[..n];
+                if dir == Direction::Tx && frame.is_empty() {
+                    let device = bus.addressee(chunk);
+                    // Devices on the same port can run at different speeds
+                    if let Some(dev) = &device {
+                        let _ = to.set_baud_rate(*dev.conn.baudrate() as u32);
+                    }
+                    // Set before the request goes out, so it's there when the response comes back
+                    *bus.last_request.lock().unwrap_or_else(|e| e.into_inner()) = device;
+                }
+
+                // Recorded before they're passed on, so a request is always recorded before its response
+                frame.extend_from_slice(chunk);
+                while let Some(len) = modbus::complete_frame(&frame, dir == Direction::Tx) {
+                    let rest = frame.split_off(len);
+                    record(bus.frame(dir, &frame));
+                    frame = rest;
+                }
+
+                if let Err(e) = to.write_all(chunk) {
+                    error!("Trace on `{}` couldn't pass on {} bytes: {}", bus.port, n, e);
+                }
+            }
+            Err(e) if e.kind() != ErrorKind::TimedOut => {
+                errors += 1;
+                if stopping {
+                    return;
+                }
+                if errors == MAX_READ_ERRORS {
+                    error!("Stopped tracing `{}`, it can't be read: {}", bus.port, e);
+                    stop.store(true, Ordering::Relaxed);
+                    // Unless it's been stopped and traced again already
+                    with_tracer(|tracer| {
+                        if tracer.taps.get(&bus.port).is_some_and(|tap| Arc::ptr_eq(&tap.stop, &stop)) {
+                            tracer.taps.remove(&bus.port);
+                        }
+                    });
+                    return;
+                }
+                std::thread::sleep(GAP);
+            }
+            _ => {
+                errors = 0;
+                if !frame.is_empty() {
+                    record(bus.frame(dir, &frame));
+                    frame.clear();
+                }
+                if stopping {
+                    return;
+                }
+            }
+        }
+    }
+}
+
+/// Starts tracing a port
+pub fn start(rtu: &RTU, port: &str) -> Result<(), String> {
+    let devices: Vec<Device> = rtu.devices.iter().filter(|dev| dev.conn.port() == port).cloned().collect();
+    let baudrate = match devices.first() {
+        Some(dev) => *dev.conn.baudrate() as u32,
+        None => return Err(format!("No devices are configured on `{}`", port)),
+    };
+
+    if with_tracer(|tracer| tracer.taps.contains_key(port)) {
+        return Err(format!("`{}` is already being traced", port));
+    }
+
+    let open = |e: serialport::Error| format!("Couldn't trace `{}`: {}", port, e);
+    // A port on the network is traced on its side of the bridge
+    let real = serialport::new(transport::local(port)?, baudrate).timeout(GAP).open_native().map_err(open)?;
+    let (mut master, slave) = TTYPort::pair().map_err(open)?;
+    master.set_timeout(GAP).map_err(open)?;
+    let pty = slave.name().ok_or_else(|| format!("Couldn't trace `{}`: the pseudo terminal has no name", port))?;
+
+    let bus = Arc::new(Bus {
+        port: port.to_string(),
+        devices,
+        current: Mutex::new(None),
+        last_request: Mutex::new(None),
+    });
+    let stop = Arc::new(AtomicBool::new(false));
+
+    let (tx_from, tx_to) = (master.try_clone_native().map_err(open)?, real.try_clone_native().map_err(open)?);
+    let (tx_bus, tx_stop) = (bus.clone(), stop.clone());
+    let (rx_bus, rx_stop) = (bus.clone(), stop.clone());
+    let threads = vec![
+        std::thread::spawn(move || pump(Direction::Tx, tx_from, tx_to, tx_bus, tx_stop)),
+        std::thread::spawn(move || pump(Direction::Rx, real, master, rx_bus, rx_stop)),
+    ];
+
+    with_tracer(|tracer| {
+        if tracer.file.is_none() {
+            let path = format!("nbc_trace_{}.jsonl", Local::now().format("%Y%m%d_%H%M%S"));
+            match File::create(&path) {
+                Ok(file) => {
+                    info!("Saving the trace to `{}`", path);
+                    tracer.file = Some((path, file));
+                }
+                Err(e) => error!("Couldn't create the trace file `{}`: {}. Frames are only kept in memory", path, e),
+            }
+        }
+        tracer.taps.insert(port.to_string(), Tap { pty, bus, stop, threads, _slave: slave });
+    });
+    Ok(())
+}
+
+/// Stops tracing a port, once the frame in progress has been recorded. Returns false if it wasn't being traced.
+pub fn stop(port: &str) -> bool {
+    match with_tracer(|tracer| tracer.taps.remove(port)) {
+        Some(tap) => {
+            tap.stop.store(true, Ordering::Relaxed);
+            for thread in tap.threads {
+                let _ = thread.join();
+            }
+            true
+        }
+        None => false,
+    }
+}
+
+/// Stops tracing every port
+pub fn stop_all() {
+    for port in with_tracer(|tracer| tracer.taps.keys().cloned().collect::<Vec<_>>()) {
+        stop(&port);
+    }
+}
+
+/// Sets the file that frames are saved to. Any file already open is closed.
+pub fn save_to(path: &str) -> Result<(), String> {
+    let file = File::create(path).map_err(|e| format!("Couldn't create the trace file `{}`: {}", path, e))?;
+    with_tracer(|tracer| tracer.file = Some((path.to_string(), file)));
+    Ok(())
+}
+
+/// Traces every port in the RTU, logging the ports that can't be traced. Returns whether every
+/// port is being traced.
+pub fn start_all(rtu: &RTU) -> bool {
+    // Ports on another RTU's machine can't be traced from here
+    let mut ports: Vec<String> = rtu.devices.iter().filter(|dev| rtus::remote(dev).is_none()).map(|dev| dev.conn.port()).collect();
+    ports.sort();
+    ports.dedup();
+    let mut all = true;
+    for port in ports {
+        match start(rtu, &port) {
+            Ok(_) => info!("Tracing `{}`", port),
+            Err(e) => {
+                error!("{}", e);
+                all = false;
+            }
+        }
+    }
+    all
+}
+
+/// The device to actually connect to. If its port is being traced, that's a copy of the device
+/// pointed at the trace's pseudo terminal.
+pub fn route(device: &Device) -> Device {
+    let mut routed = device.clone();
+    with_tracer(|tracer| {
+        if let Some(tap) = tracer.taps.get(&device.conn.port()) {
+            *tap.bus.current.lock().unwrap_or_else(|e| e.into_inner()) = Some(device.id.clone());
+            routed.conn.port = tap.pty.clone().into();
+        }
+    });
+    routed
+}
+
+/// The port a `trace` argument refers to. It can be a port, or a device on that port. `None` means every port.
+fn port_of(session: &Session, arg: Option<&String>) -> Result<Option<String>, String> {
+    match arg {
+        None => Ok(None),
+        Some(port) if port.starts_with('/') || transport::is_network(port) => Ok(Some(port.to_string())),
+        Some(name) => resolve::device(&session.rtu, &session.config, name)
+            .map(|dev| Some(dev.conn.port()))
+            .map_err(|e| e.to_string()),
+    }
+}
+
+/// The `trace` shell command
+///
+/// ```text
+/// trace                  # which ports are being traced, and where to
+/// trace on [port]        # start tracing a port (or a device's port), or every port
+/// trace off [port]       # stop tracing a port, or every port
+/// trace show [n]         # the last n frames (default 20)
+/// ```
+pub fn command(session: &mut Session, args: Vec<String>) -> Result<(), Box<dyn Error>> {
+    let target = match args.get(1).map(|s| s.as_str()) {
+        Some("on") | Some("off") => match port_of(session, args.get(2)) {
+            Ok(port) => port,
+            Err(e) => {
+                error!("{}", e);
+                return Err(Failed.into());
+            }
+        },
+        _ => None,
+    };
+
+    match args.get(1).map(|s| s.as_str()) {
+        None => with_tracer(|tracer| {
+            if tracer.taps.is_empty() {
+                info!("Not tracing any ports. Use `trace on [port]` to start");
+            }
+            for (port, tap) in &tracer.taps {
+                info!("Tracing `{}` (devices connect through `{}`)", port, tap.pty);
+            }
+            if let Some((path, _)) = &tracer.file {
+                info!("Frames are saved to `{}`", path);
+            }
+        }),
+        Some("on") => match target {
+            Some(port) => match start(&session.rtu, &port) {
+                Ok(_) => info!("Tracing `{}`", port),
+                Err(e) => {
+                    error!("{}", e);
+                    return Err(Failed.into());
+                }
+            },
+            None if !start_all(&session.rtu) => return Err(Failed.into()),
+            None => {}
+        },
+        Some("off") => match target {
+            Some(port) if stop(&port) => info!("Stopped tracing `{}`", port),
+            Some(port) => {
+                error!("`{}` isn't being traced", port);
+                return Err(Failed.into());
+            }
+            None => {
+                stop_all();
+                info!("Stopped tracing");
+            }
+        },
+        Some("show") => {
+            let count = match args.get(2).map(|n| n.parse::<usize>()) {
+                None => 20,
+                Some(Ok(n)) => n,
+                Some(Err(_)) => {
+                    error!("`{}` isn't a number of frames", args[2]);
+                    return Err(Failed.into());
+                }
+            };
+            let frames: Vec<Frame> = with_tracer(|tracer| {
+                tracer.recent.iter().skip(tracer.recent.len().saturating_sub(count)).cloned().collect()
+            });
+            outln!("{}", tables::trace::render(&frames));
+        }
+        Some(other) => {
+            error!("Unknown trace command `{}`. Use `trace`, `trace on [port]`, `trace off [port]` or `trace show [n]`", other);
+            return Err(Failed.into());
+        }
+    }
+    Ok(())
+}
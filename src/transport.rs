@@ -0,0 +1,258 @@
+//! Ports on the network, through serial gateways
+//!
+//! A device's `port` can be a serial port, or an RS-485 gateway on the network:
+//!
+//! ```yaml
+//! port: tcp://192.168.0.50:4001          # a gateway that passes bytes straight through
+//! port: modbus-tcp://192.168.0.51:502    # a gateway that speaks Modbus TCP
+//! ```
+//!
+//! The drivers only open serial ports, so each network port gets a bridge: a pseudo terminal that
+//! devices on the port are pointed at when they connect (see [`route`]), with a thread passing
+//! bytes between it and the gateway. For Modbus TCP, the thread turns each RTU frame into a Modbus
+//! TCP one and back. STR1 boards don't speak Modbus, so they need a `tcp://` gateway.
+//!
+//! The gateway is connected to when the first request goes out, and again after it drops.
+use std::collections::BTreeMap;
+use std::io::{ErrorKind, Read, Write};
+use std::net::{TcpStream, ToSocketAddrs};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::thread::JoinHandle;
+use std::time::Duration;
+
+use brewdrivers::controllers::Controller;
+use brewdrivers::model::{Device, ModelError, RTU};
+use log::warn;
+use serialport::{SerialPort, TTYPort};
+
+use crate::modbus;
+
+/// How long the bridge waits on either side before checking the other
+const POLL: Duration = Duration::from_millis(2);
+
+/// How long connecting to a gateway can take
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(1);
+
+/// What a gateway expects on the wire
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Kind {
+    /// Bytes straight through, like a serial port
+    Raw,
+    /// Modbus TCP, with an MBAP header in place of each frame's address and CRC
+    ModbusTcp,
+}
+
+/// What a network port is, and its `host:port`. `None` if it's a serial port.
+pub fn parse(port: &str) -> Option<(Kind, &str)> {
+    if let Some(address) = port.strip_prefix("tcp://") {
+        Some((Kind::Raw, address))
+    } else {
+        port.strip_prefix("modbus-tcp://").map(|address| (Kind::ModbusTcp, address))
+    }
+}
+
+/// Whether a port is on the network
+pub fn is_network(port: &str) -> bool {
+    parse(port).is_some()
+}
+
+/// Checks that every network port is one the devices on it can use
+pub fn validate(rtu: &RTU) -> Result<(), ModelError> {
+    for device in &rtu.devices {
+        let port = device.conn.port();
+        let msg = match parse(&port) {
+            Some((_, address)) if !address.contains(':') => "a gateway needs a port number, like `tcp://192.168.0.50:4001`",
+            Some((Kind::ModbusTcp, _)) if matches!(device.conn.controller(), Controller::STR1) => {
+                "STR1 boards don't speak Modbus, so they need a `tcp://` gateway"
+            }
+            _ => continue,
+        };
+        return Err(ModelError::validation_error(("port", &port), msg));
+    }
+    Ok(())
+}
+
+/// The thread passing bytes between a pseudo terminal and a gateway
+struct Bridge {
+    /// The pseudo terminal that devices on the port are pointed at
+    pty: String,
+    stop: Arc<AtomicBool>,
+    thread: JoinHandle<()>,
+    /// Held open so the pseudo terminal stays up between connections
+    _slave: TTYPort,
+}
+
+static BRIDGES: Mutex<BTreeMap<String, Bridge>> = Mutex::new(BTreeMap::new());
+
+fn bridges() -> MutexGuard<'static, BTreeMap<String, Bridge>> {
+    // A poisoned lock only means another thread panicked while holding it, the bridges still work
+    BRIDGES.lock().unwrap_or_else(|e| e.into_inner())
+}
+
+/// The path to open for a port. That's the port itself, or the bridge to it if it's on the network.
+/// The bridge is started the first time it's needed.
+pub fn local(port: &str) -> Result<String, String> {
+    let (kind, address) = match parse(port) {
+        Some(network) => network,
+        None => return Ok(port.to_string()),
+    };
+    let mut bridges = bridges();
+    if let Some(bridge) = bridges.get(port) {
+        return Ok(bridge.pty.clone());
+    }
+
+    let open = |e: serialport::Error| format!("Couldn't bridge to `{}`: {}", port, e);
+    let (mut master, slave) = TTYPort::pair().map_err(open)?;
+    master.set_timeout(POLL).map_err(open)?;
+    let pty = slave.name().ok_or_else(|| format!("Couldn't bridge to `{}`: the pseudo terminal has no name", port))?;
+
+    let stop = Arc::new(AtomicBool::new(false));
+    let (name, address, thread_stop) = (port.to_string(), address.to_string(), stop.clone());
+    let thread = std::thread::spawn(move || bridge(name, kind, address, master, thread_stop));
+
+    bridges.insert(port.to_string(), Bridge { pty: pty.clone(), stop, thread, _slave: slave });
+    Ok(pty)
+}
+
+/// The device to actually connect to. If its port is on the network, that's a copy of the device
+/// pointed at the bridge.
+pub fn route(device: &Device) -> Result<Device, String> {
+    let mut routed = device.clone();
+    routed.conn.port = local(&device.conn.port())?.into();
+    Ok(routed)
+}
+
+/// Stops every bridge
+pub fn stop_all() {
+    let stopped = std::mem::take(&mut *bridges());
+    for bridge in stopped.into_values() {
+        bridge.stop.store(true, Ordering::Relaxed);
+        let _ = bridge.thread.join();
+    }
+}
+
+/// Connects to a gateway
+fn connect(address: &str) -> std::io::Result<TcpStream> {
+    let mut last_error = std::io::Error::new(ErrorKind::NotFound, "the address didn't resolve");
+    for addr in address.to_socket_addrs()? {
+        match TcpStream::connect_timeout(&addr, CONNECT_TIMEOUT) {
+            Ok(stream) => {
+                stream.set_nodelay(true)?;
+                stream.set_read_timeout(Some(POLL))?;
+                return Ok(stream);
+            }
+            Err(e) => last_error = e,
+        }
+    }
+    Err(last_error)
+}
+
+/// Passes bytes between the pseudo terminal and the gateway until `stop` is set
+fn bridge(port: String, kind: Kind, address: String, mut pty: TTYPort, stop: Arc<AtomicBool>) {
+    let mut gateway: Option<TcpStream> = None;
+    // Only the first failure in a row is worth a warning, the drivers report the timeouts
+    let mut warned = false;
+    // Bytes from the drivers that haven't been sent, and from the gateway that haven't been passed on
+    let (mut request, mut response) = (Vec::new(), Vec::new());
+    let mut transaction: u16 = 0;
+    // The Modbus TCP transaction being waited on. Answers to anything else are late, and dropped.
+    let mut waiting: Option<u16> = None;
+    let mut buf = [0u8; 256];
+
+    while !stop.load(Ordering::Relaxed) {
+        let quiet = match pty.read(&mut buf) {
+            Ok(n) if n > 0 => {
+                request.extend_from_slice(&buf[..n]);
+                false
+            }
+            Err(e) if e.kind() != ErrorKind::TimedOut => {
+                std::thread::sleep(POLL);
+                true
+            }
+            _ => true,
+        };
+
+        let frame = match kind {
+            Kind::Raw if !request.is_empty() => Some(std::mem::take(&mut request)),
+            Kind::Raw => None,
+            Kind::ModbusTcp => match modbus::complete_frame(&request, true) {
+                Some(len) => {
+                    let rest = request.split_off(len);
+                    transaction = transaction.wrapping_add(1);
+                    waiting = Some(transaction);
+                    Some(modbus::rtu_to_tcp(transaction, &std::mem::replace(&mut request, rest)))
+                }
+                None => {
+                    if quiet && !request.is_empty() {
+                        warn!("`{}` only carries Modbus, so {} wasn't sent", port, modbus::hex(&request));
+                        request.clear();
+                    }
+                    None
+                }
+            },
+        };
+
+        if let Some(frame) = frame {
+            if gateway.is_none() {
+                match connect(&address) {
+                    Ok(stream) => {
+                        gateway = Some(stream);
+                        warned = false;
+                    }
+                    Err(e) if !warned => {
+                        warn!("Couldn't reach the gateway for `{}`: {}", port, e);
+                        warned = true;
+                    }
+                    Err(_) => {}
+                }
+            }
+            if let Some(stream) = &mut gateway {
+                if let Err(e) = stream.write_all(&frame) {
+                    warn!("Lost the gateway for `{}`: {}", port, e);
+                    gateway = None;
+                }
+            }
+        }
+
+        let stream = match &mut gateway {
+            Some(stream) => stream,
+            None => continue,
+        };
+        match stream.read(&mut buf) {
+            Ok(0) => {
+                // The gateway hung up. It's connected to again with the next request.
+                gateway = None;
+                response.clear();
+                continue;
+            }
+            Ok(n) => response.extend_from_slice(&buf[..n]),
+            Err(e) if matches!(e.kind(), ErrorKind::WouldBlock | ErrorKind::TimedOut) => continue,
+            Err(e) => {
+                warn!("Lost the gateway for `{}`: {}", port, e);
+                gateway = None;
+                response.clear();
+                continue;
+            }
+        }
+
+        match kind {
+            Kind::Raw => {
+                let _ = pty.write_all(&response);
+                response.clear();
+            }
+            Kind::ModbusTcp => {
+                while let Some(len) = modbus::complete_tcp_frame(&response) {
+                    let rest = response.split_off(len);
+                    if let Some((id, frame)) = modbus::tcp_to_rtu(&response) {
+                        if waiting == Some(id) {
+                            let _ = pty.write_all(&frame);
+                            waiting = None;
+                        }
+                    }
+                    response = rest;
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,562 @@
+//! Runs the CLI against recorded serial sessions, so it can be tested without any hardware.
+//!
+//! Each fixture in `tests/fixtures` is a trace made with `--trace-serial` (see `src/trace.rs`). The
+//! test runs the CLI with `--replay-serial`, which puts a fake device on every port in the trace
+//! that answers each request with the response that was recorded for it.
+//!
+//! The fixtures were recorded against boards emulated from the brewdrivers protocol code, on the
+//! devices in `tests/fixtures/rtu_conf.yaml`. To re-record one on real hardware, point that config
+//! at the real ports and run the same command with `--trace-serial`:
+//!
+//! ```text
+//! NBC_cli --config tests/fixtures/rtu_conf.yaml --trace-serial tests/fixtures/cn7500_read.jsonl exec mash_cn
+//! ```
+use std::io::Write;
+use std::net::TcpListener;
+use std::path::{Path, PathBuf};
+use std::process::{Child, Command, Output, Stdio};
+use std::thread::sleep;
+use std::time::{Duration, Instant};
+
+fn fixture(name: &str) -> String {
+    format!("{}/tests/fixtures/{}", env!("CARGO_MANIFEST_DIR"), name)
+}
+
+/// A fresh home directory for one test. `exec` mode caches the RTU in `~/.cache`, which would
+/// leak between tests.
+fn home(test: &str) -> PathBuf {
+    let home = home_path(test);
+    let _ = std::fs::remove_dir_all(&home);
+    std::fs::create_dir_all(&home).unwrap();
+    home
+}
+
+fn home_path(test: &str) -> PathBuf {
+    std::env::temp_dir().join(format!("nbc_cli_test_{}_{}", std::process::id(), test))
+}
+
+/// Starts a test with a fresh home directory, and the fixture config in it after `edit`. Returns
+/// the config file. The audit journal is kept in the home directory too, rather than in `/var/log`.
+fn test_config(test: &str, edit: impl FnOnce(String) -> String) -> PathBuf {
+    let home = home(test);
+    let config = std::fs::read_to_string(fixture("rtu_conf.yaml")).unwrap();
+    let config = config.replace("cli:\n", &format!("cli:\n  audit_file: {}\n", home.join("audit.jsonl").display()));
+    std::fs::write(home.join("rtu_conf.yaml"), edit(config)).unwrap();
+    home.join("rtu_conf.yaml")
+}
+
+/// `exec` with a test's home directory and `config`, replaying `trace` if there is one
+fn cli(test: &str, config: &Path, trace: Option<&str>, command: &[&str]) -> Command {
+    let mut cli = Command::new(env!("CARGO_BIN_EXE_NBC_cli"));
+    cli.env("HOME", home_path(test)).env("RUST_LOG", "info").args(["--config", config.to_str().unwrap()]);
+    if let Some(trace) = trace {
+        cli.args(["--replay-serial", trace]);
+    }
+    cli.arg("exec").args(command);
+    cli
+}
+
+/// Everything a command printed, on stdout and stderr
+fn printed(output: &Output) -> String {
+    format!("{}{}", String::from_utf8_lossy(&output.stdout), String::from_utf8_lossy(&output.stderr))
+}
+
+/// Runs a command against a trace with the fixture config, returning everything it printed
+fn run(test: &str, trace: &str, command: &[&str]) -> String {
+    printed(&cli(test, &test_config(test, |config| config), Some(&fixture(trace)), command).output().unwrap())
+}
+
+/// Runs a command against a trace, typing `input` into it
+fn run_with_input(test: &str, trace: &str, command: &[&str], input: &str) -> String {
+    let mut child = cli(test, &test_config(test, |config| config), Some(&fixture(trace)), command)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .unwrap();
+    child.stdin.take().unwrap().write_all(input.as_bytes()).unwrap();
+    printed(&child.wait_with_output().unwrap())
+}
+
+fn assert_printed(output: &str, expected: &str) {
+    assert!(output.contains(expected), "expected `{}` in the output:\n{}", expected, output);
+}
+
+#[test]
+fn cn7500_read() {
+    let output = run("cn7500_read", "cn7500_read.jsonl", &["mash_cn"]);
+    assert_printed(&output, "PV: 152.3, SV: 150, Running: true");
+}
+
+#[test]
+fn cn7500_set() {
+    let output = run("cn7500_set", "cn7500_set.jsonl", &["mash_cn", "set", "148.5"]);
+    assert_printed(&output, "Ok! Set to 148.5");
+}
+
+#[test]
+fn cn7500_by_role() {
+    // `mash` is the role for mash_cn in the fixture config
+    let output = run("cn7500_by_role", "cn7500_read.jsonl", &["mash"]);
+    assert_printed(&output, "PV: 152.3, SV: 150, Running: true");
+}
+
+#[test]
+fn cn7500_bad_argument() {
+    let output = run("cn7500_bad_argument", "cn7500_set.jsonl", &["mash_cn", "set", "hot"]);
+    assert_printed(&output, "expected a decimal number, got `hot`");
+    assert_printed(&output, "Usage: mash_cn set");
+}
+
+#[test]
+fn cn7500_probe() {
+    let output = run("cn7500_probe", "cn7500_read.jsonl", &["mash_cn", "probe"]);
+    assert_printed(&output, "`mash_cn` answers at 19200 baud with a 20ms timeout");
+    assert_printed(&output, "The config file's settings (19200 baud, 40ms) already work");
+}
+
+#[test]
+fn waveshare_on() {
+    let output = run("waveshare_on", "waveshare_on.jsonl", &["wsrelay0", "On"]);
+    assert_printed(&output, "Ok!");
+}
+
+#[test]
+fn waveshare_list_all() {
+    let output = run("waveshare_list_all", "waveshare_list_all.jsonl", &["wsrelay0", "list_all"]);
+    assert_printed(&output, "Relay 0: On");
+    assert_printed(&output, "Relay 7: Off");
+}
+
+#[test]
+fn waveshare_revision() {
+    let output = run("waveshare_revision", "waveshare_revision.jsonl", &["wsrelay0", "software_revision"]);
+    assert_printed(&output, "v1.00");
+}
+
+#[test]
+fn waveshare_unknown_command() {
+    let output = run("waveshare_unknown_command", "waveshare_on.jsonl", &["wsrelay0", "lsit_all"]);
+    assert_printed(&output, "Did you mean `list_all`?");
+}
+
+#[test]
+fn str1_read() {
+    let output = run("str1_read", "str1_read.jsonl", &["str1_heater"]);
+    assert_printed(&output, "Off");
+}
+
+#[test]
+fn str1_on() {
+    let output = run("str1_on", "str1_on.jsonl", &["str1_heater", "On"]);
+    assert_printed(&output, "Ok!");
+}
+
+#[test]
+fn unrecorded_request_times_out() {
+    // The trace only has reads, so the write gets no answer
+    let output = run("unrecorded_request_times_out", "cn7500_read.jsonl", &["mash_cn", "set", "148.5"]);
+    assert_printed(&output, "has no response recorded for");
+}
+
+#[test]
+fn raw_read_holding() {
+    let output = run("raw_read_holding", "raw_read.jsonl", &["raw", "mash_cn", "read_holding", "0x1000", "1"]);
+    assert_printed(&output, "Received: 16 03 02 05 F3 8F 52");
+    assert_printed(&output, "addr 22: registers [1523]");
+}
+
+#[test]
+fn raw_write_single_confirmed() {
+    let output = run_with_input("raw_write_single_confirmed", "raw_write.jsonl", &["raw", "mash_cn", "write_single", "0x1001", "1485"], "y\n");
+    assert_printed(&output, "Write 1485 to register 0x1001 on `mash_cn`");
+    assert_printed(&output, "addr 22: ok, register 0x1001 = 1485");
+
+    let journal = std::fs::read_to_string(home_path("raw_write_single_confirmed").join("audit.jsonl")).unwrap();
+    for expected in [r#""device":"mash_cn""#, r#""command":"raw write_single""#, r#""previous":"0x1001 = 1500""#, r#""new":"0x1001 = 1485""#, r#""result":"ok""#] {
+        assert_printed(&journal, expected);
+    }
+}
+
+#[test]
+fn raw_write_single_declined() {
+    let output = run_with_input("raw_write_single_declined", "raw_write.jsonl", &["raw", "mash_cn", "write_single", "0x1001", "1485"], "n\n");
+    assert_printed(&output, "Nothing was written");
+    assert!(!output.contains("Sent:"), "the write was sent anyway:\n{}", output);
+}
+
+#[test]
+fn set_all_asks_first() {
+    let output = run_with_input("set_all_asks_first", "waveshare_set_all.jsonl", &["wsrelay0", "set_all", "On"], "n\n");
+    assert_printed(&output, "On, Off, Off, Off, Off, Off, Off, Off → On. Are you sure?");
+    assert_printed(&output, "Cancelled");
+}
+
+#[test]
+fn set_all_with_yes() {
+    let output = run("set_all_with_yes", "waveshare_set_all.jsonl", &["--yes", "wsrelay0", "set_all", "On"]);
+    assert_printed(&output, "yes (--yes)");
+    assert_printed(&output, "Ok!");
+}
+
+#[test]
+fn dry_run_sends_nothing() {
+    // The trace is empty of writes, so anything sent would get no answer
+    let output = run("dry_run_sends_nothing", "cn7500_read.jsonl", &["--dry-run", "mash_cn", "set", "148.5"]);
+    assert_printed(&output, "Dry run: would send `set 148.5` to CN7500 controller 22 on `/dev/ttyUSB1`");
+    // The same bytes the driver sent in the `cn7500_set` trace
+    assert_printed(&output, "The frame would be `16 06 10 01 05 CD 1D 28`");
+    assert!(!output.contains("Ok!"), "the dry run changed the device:\n{}", output);
+}
+
+#[test]
+fn read_only_turns_away_writes() {
+    let output = run("read_only_turns_away_writes", "cn7500_set.jsonl", &["--read-only", "mash_cn", "set", "148.5"]);
+    assert_printed(&output, "`mash_cn set 148.5` would change a device, and this terminal is read-only");
+    assert!(!output.contains("Ok!"), "the write went through:\n{}", output);
+}
+
+#[test]
+fn read_only_allows_reads() {
+    let output = run("read_only_allows_reads", "cn7500_read.jsonl", &["--read-only", "mash_cn"]);
+    assert_printed(&output, "PV: 152.3, SV: 150, Running: true");
+}
+
+#[test]
+fn health_all_answer() {
+    let config = test_config("health_all_answer", |config| config);
+    let output = cli("health_all_answer", &config, Some(&fixture("health.jsonl")), &["health"]).output().unwrap();
+    let printed = printed(&output);
+    assert_printed(&printed, "v1.00");
+    assert_printed(&printed, "All 3 devices are healthy");
+    assert!(output.status.success(), "health failed:\n{}", printed);
+}
+
+#[test]
+fn health_fails_when_a_device_is_missing() {
+    // Only the CN7500's port is in this trace, so the relay boards' ports don't exist
+    let config = test_config("health_fails_when_a_device_is_missing", |config| config);
+    let output = cli("health_fails_when_a_device_is_missing", &config, Some(&fixture("cn7500_read.jsonl")), &["health"]).output().unwrap();
+    let printed = printed(&output);
+    assert_printed(&printed, "2 of 3 devices failed the health check");
+    assert_eq!(output.status.code(), Some(1), "health should have failed:\n{}", printed);
+}
+
+#[test]
+fn audit_records_changes() {
+    let output = run("audit_records_changes", "cn7500_set.jsonl", &["mash_cn", "set", "148.5"]);
+    assert_printed(&output, "Ok! Set to 148.5");
+
+    let journal = std::fs::read_to_string(home_path("audit_records_changes").join("audit.jsonl")).unwrap();
+    for expected in [r#""mode":"exec""#, r#""device":"mash_cn""#, r#""command":"set_sv""#, r#""previous":"150""#, r#""new":"148.5""#, r#""result":"ok""#] {
+        assert_printed(&journal, expected);
+    }
+}
+
+#[test]
+fn dashboard() {
+    // The dashboard redraws until Enter is pressed
+    let config = test_config("dashboard", |config| config);
+    let mut dashboard: Child = cli("dashboard", &config, Some(&fixture("dashboard.jsonl")), &["dashboard"])
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .unwrap();
+    sleep(Duration::from_millis(2500));
+    dashboard.stdin.take().unwrap().write_all(b"\n").unwrap();
+    let started = Instant::now();
+    while dashboard.try_wait().unwrap().is_none() {
+        if started.elapsed() > Duration::from_secs(5) {
+            dashboard.kill().unwrap();
+            panic!("the dashboard didn't quit on Enter");
+        }
+        sleep(Duration::from_millis(100));
+    }
+    let Output { status, stdout, .. } = dashboard.wait_with_output().unwrap();
+    assert!(status.success());
+    let output = String::from_utf8_lossy(&stdout);
+
+    for expected in ["Pump", "Mash Tun", "152.3", "150", "HLT Heater"] {
+        assert_printed(&output, expected);
+    }
+    assert!(!output.contains("(no response)"), "a device didn't answer:\n{}", output);
+}
+
+#[test]
+fn daemon_runs_commands_for_clients() {
+    // The daemon replays the trace, and the client runs without one, so the command has to go through the daemon
+    let test = "daemon_runs_commands_for_clients";
+    let home = home_path(test);
+    let socket = home.join("nbc_cli.sock");
+    let config = test_config(test, |config| config.replace("cli:\n", &format!("cli:\n  socket: {}\n", socket.display())));
+
+    let mut daemon = Command::new(env!("CARGO_BIN_EXE_NBC_cli"))
+        .env("HOME", &home)
+        .args(["--config", config.to_str().unwrap()])
+        .args(["--replay-serial", &fixture("waveshare_on.jsonl")])
+        .arg("daemon")
+        .stdout(Stdio::null())
+        .stderr(Stdio::null())
+        .spawn()
+        .unwrap();
+    for _ in 0..50 {
+        if socket.exists() {
+            break;
+        }
+        sleep(Duration::from_millis(100));
+    }
+
+    let client = |command: &[&str]| {
+        let output = cli(test, &config, None, command).output().unwrap();
+        (output.status.code(), printed(&output))
+    };
+    let (on, on_printed) = client(&["wsrelay0", "On"]);
+    let (missing, missing_printed) = client(&["nothing"]);
+    daemon.kill().unwrap();
+    daemon.wait().unwrap();
+
+    assert_printed(&on_printed, "Ok!");
+    assert_eq!(on, Some(0), "`wsrelay0 On` should have worked:\n{}", on_printed);
+    assert_printed(&missing_printed, "Command not found: nothing");
+    assert_eq!(missing, Some(1));
+    let journal = std::fs::read_to_string(home.join("audit.jsonl")).unwrap();
+    assert_printed(&journal, r#""device":"wsrelay0""#);
+}
+
+#[test]
+fn other_rtu_devices_are_namespaced() {
+    // The fixture config is used as another RTU too, so its devices are there twice
+    let test = "other_rtu_devices_are_namespaced";
+    let config = test_config(test, |config| config.replace("cli:\n", &format!("cli:\n  rtus:\n    cellar:\n      config: {}\n", fixture("rtu_conf.yaml"))));
+    let output = cli(test, &config, Some(&fixture("cn7500_read.jsonl")), &["cellar:mash_tun"]).output().unwrap();
+    assert_printed(&printed(&output), "PV: 152.3, SV: 150, Running: true");
+}
+
+/// Runs a command with the serial port `serial` moved to a free port on the network, with `scheme`,
+/// in the config and the trace
+fn run_on_network(test: &str, trace: &str, serial: &str, scheme: &str, command: &[&str]) -> String {
+    // The port is free once the listener is dropped, until the replay's gateway takes it
+    let port = TcpListener::bind("127.0.0.1:0").unwrap().local_addr().unwrap().port();
+    let address = format!("{}://127.0.0.1:{}", scheme, port);
+    let config = test_config(test, |config| config.replace(serial, &address));
+    let trace_file = home_path(test).join(trace);
+    std::fs::write(&trace_file, std::fs::read_to_string(fixture(trace)).unwrap().replace(serial, &address)).unwrap();
+    printed(&cli(test, &config, trace_file.to_str(), command).output().unwrap())
+}
+
+#[test]
+fn raw_tcp_gateway() {
+    let output = run_on_network("raw_tcp_gateway", "waveshare_on.jsonl", "/dev/ttyUSB0", "tcp", &["wsrelay0", "On"]);
+    assert_printed(&output, "Ok!");
+}
+
+#[test]
+fn modbus_tcp_gateway() {
+    let output = run_on_network("modbus_tcp_gateway", "cn7500_read.jsonl", "/dev/ttyUSB1", "modbus-tcp", &["mash_cn"]);
+    assert_printed(&output, "PV: 152.3, SV: 150, Running: true");
+}
+
+#[test]
+fn aliases_are_saved_and_run() {
+    let test = "aliases_are_saved_and_run";
+    let config = test_config(test, |config| config);
+    let cli = |command: &[&str]| printed(&cli(test, &config, Some(&fixture("waveshare_on.jsonl")), command).output().unwrap());
+
+    assert_printed(&cli(&["alias", "pump_on", "=", "wsrelay0;", "wsrelay0", "On"]), "Added `pump_on`");
+    let output = cli(&["pump_on"]);
+    assert_printed(&output, "Off");
+    assert_printed(&output, "Ok!");
+
+    // The whole chain as one quoted word, like `alias pump_check = "wsrelay0; wsrelay0 On"` in the shell
+    assert_printed(&cli(&["alias", "pump_check", "=", "wsrelay0; wsrelay0 On"]), "Added `pump_check`");
+    let output = cli(&["pump_check"]);
+    assert_printed(&output, "Off");
+    assert_printed(&output, "Ok!");
+}
+
+#[test]
+fn chains_stop_on_failure_and_fall_back() {
+    let config = test_config("chains_stop_on_failure", |config| config);
+    let stopped = cli("chains_stop_on_failure", &config, Some(&fixture("waveshare_on.jsonl")), &["bogus && wsrelay0 On"]).output().unwrap();
+    let out = printed(&stopped);
+    assert!(!out.contains("Ok!"), "`&&` should have stopped the chain:\n{}", out);
+    assert_eq!(stopped.status.code(), Some(1), "the chain should have failed:\n{}", out);
+
+    // A new home, so the first command's cache doesn't carry over
+    let config = test_config("chains_fall_back", |config| config);
+    let fell_back = cli("chains_fall_back", &config, Some(&fixture("waveshare_on.jsonl")), &["bogus || wsrelay0 On"]).output().unwrap();
+    let out = printed(&fell_back);
+    assert_printed(&out, "Ok!");
+    assert!(fell_back.status.success(), "the fallback should have succeeded:\n{}", out);
+}
+
+#[test]
+fn chains_go_by_results_not_logs() {
+    // A read that gets no answer is reported as the value, not logged as an error, but still fails
+    let config = test_config("chains_go_by_results", |config| config);
+    let stopped = cli("chains_go_by_results", &config, Some(&fixture("waveshare_on.jsonl")), &["str1_heater && wsrelay0 On"]).output().unwrap();
+    let out = printed(&stopped);
+    assert!(!out.contains("Ok!"), "`&&` should have stopped the chain:\n{}", out);
+    assert_eq!(stopped.status.code(), Some(1), "the read should have failed:\n{}", out);
+
+    let config = test_config("chains_go_by_results_back", |config| config);
+    let fell_back = cli("chains_go_by_results_back", &config, Some(&fixture("waveshare_on.jsonl")), &["str1_heater || wsrelay0 On"]).output().unwrap();
+    let out = printed(&fell_back);
+    assert_printed(&out, "Ok!");
+    assert!(fell_back.status.success(), "the fallback should have succeeded:\n{}", out);
+}
+
+/// The ID of a user or group, and its name, from `/etc/passwd` or `/etc/group`
+fn account(file: &str, id: u32) -> String {
+    std::fs::read_to_string(file)
+        .unwrap()
+        .lines()
+        .map(|line| line.split(':').collect::<Vec<_>>())
+        .find(|fields| fields.get(2) == Some(&id.to_string().as_str()))
+        .map(|fields| fields[0].to_string())
+        .unwrap_or_else(|| panic!("no ID {} in {}", id, file))
+}
+
+/// The real user and group IDs running the tests
+fn ids() -> (u32, u32) {
+    let status = std::fs::read_to_string("/proc/self/status").unwrap();
+    let id = |key: &str| status.lines().find(|line| line.starts_with(key)).unwrap().split_whitespace().nth(1).unwrap().parse().unwrap();
+    (id("Uid:"), id("Gid:"))
+}
+
+/// The CLI, with `permissions` as its permissions file, run as someone the file applies to: as
+/// `nobody` when the tests run as root, who can do anything, or as whoever runs them otherwise.
+/// Returns the command and that user's name and group.
+///
+/// `USER` and `SUDO_USER` are set to a maintainer's name, which the CLI shouldn't believe.
+fn as_ordinary_user(test: &str, trace: &str, permissions: &str, command: &[&str]) -> (Command, String, String) {
+    use std::os::unix::fs::PermissionsExt;
+    use std::os::unix::process::CommandExt;
+
+    let config = test_config(test, |config| config.replace("cli:\n", &format!("cli:\n  permissions_file: {}\n", home_path(test).join("permissions.yaml").display())));
+    let home = home_path(test);
+    let (uid, gid) = match ids() {
+        (0, _) => (65534, 65534),
+        ids => ids,
+    };
+    let user = account("/etc/passwd", uid);
+    let group = account("/etc/group", gid);
+    let permissions = permissions.replace("$USER", &user).replace("$GROUP", &group);
+
+    // `nobody` can't reach into the build directory, so it gets copies of everything
+    std::fs::set_permissions(&home, std::fs::Permissions::from_mode(0o777)).unwrap();
+    let cli = home.join("NBC_cli");
+    std::fs::copy(env!("CARGO_BIN_EXE_NBC_cli"), &cli).unwrap();
+    std::fs::copy(fixture(trace), home.join(trace)).unwrap();
+    std::fs::write(home.join("permissions.yaml"), permissions).unwrap();
+
+    let mut command_line = Command::new(&cli);
+    command_line
+        .env("HOME", &home)
+        .env("USER", "maintainer")
+        .env("LOGNAME", "maintainer")
+        .env("SUDO_USER", "maintainer")
+        .args(["--config", config.to_str().unwrap()])
+        .args(["--replay-serial", home.join(trace).to_str().unwrap()])
+        .arg("exec")
+        .args(command)
+        .uid(uid)
+        .gid(gid);
+    (command_line, user, group)
+}
+
+#[test]
+fn permissions_allow_by_user_and_group() {
+    let rules = "rules:\n  - users: [$USER]\n    devices: [wsrelay0]\n    categories: [relay]\n";
+    let (mut cli, ..) = as_ordinary_user("permissions_allow_by_user", "waveshare_on.jsonl", rules, &["wsrelay0", "On"]);
+    assert_printed(&printed(&cli.output().unwrap()), "Ok!");
+
+    // `kettle` is wsrelay0's role
+    let rules = "rules:\n  - groups: [$GROUP]\n    devices: [\"ws*\"]\n    categories: [\"*\"]\n";
+    let (mut cli, ..) = as_ordinary_user("permissions_allow_by_group", "waveshare_on.jsonl", rules, &["kettle", "On"]);
+    assert_printed(&printed(&cli.output().unwrap()), "Ok!");
+}
+
+#[test]
+fn permissions_deny_other_users_devices_and_categories() {
+    let denied = "isn't allowed to run relay commands on `wsrelay0`";
+    for (test, rules) in [
+        // Only the maintainer, who the environment claims to be
+        ("permissions_deny_user", "rules:\n  - users: [maintainer]\n    groups: [maintainers]\n    devices: [\"*\"]\n    categories: [\"*\"]\n"),
+        ("permissions_deny_device", "rules:\n  - users: [$USER]\n    devices: [\"mash*\", str1_heater]\n    categories: [relay]\n"),
+        ("permissions_deny_category", "rules:\n  - groups: [$GROUP]\n    devices: [\"*\"]\n    categories: [temperature, raw]\n"),
+    ] {
+        let (mut cli, user, _) = as_ordinary_user(test, "waveshare_on.jsonl", rules, &["wsrelay0", "On"]);
+        let output = cli.output().unwrap();
+        let printed = printed(&output);
+        assert_printed(&printed, &format!("`{}` {}", user, denied));
+        assert!(!printed.contains("Ok!"), "`{}` should have been denied:\n{}", test, printed);
+        assert_eq!(output.status.code(), Some(1), "`{}` should have failed:\n{}", test, printed);
+
+        let journal = std::fs::read_to_string(home_path(test).join("audit.jsonl")).unwrap();
+        for expected in [format!(r#""user":"{}""#, user), r#""device":"wsrelay0""#.to_string(), r#""new":"denied""#.to_string()] {
+            assert_printed(&journal, &expected);
+        }
+    }
+}
+
+#[test]
+fn permissions_dont_apply_to_root() {
+    if ids().0 != 0 {
+        eprintln!("Skipping, the tests aren't running as root");
+        return;
+    }
+    let test = "permissions_dont_apply_to_root";
+    let permissions = home_path(test).join("permissions.yaml");
+    let config = test_config(test, |config| config.replace("cli:\n", &format!("cli:\n  permissions_file: {}\n", permissions.display())));
+    std::fs::write(&permissions, "rules: []\n").unwrap();
+
+    let output = cli(test, &config, Some(&fixture("waveshare_on.jsonl")), &["wsrelay0", "On"]).output().unwrap();
+    assert_printed(&printed(&output), "Ok!");
+}
+
+/// Locks `port` the way another CLI would, as PID 4242 run by `brewer`. It's unlocked when the
+/// file is dropped.
+fn hold_lock(port: &str) -> std::fs::File {
+    let name = format!("nbc_cli{}.lock", port.replace('/', "_"));
+    let mut file = [PathBuf::from("/run/lock"), std::env::temp_dir()]
+        .iter()
+        .find_map(|dir| std::fs::OpenOptions::new().read(true).write(true).create(true).truncate(false).open(dir.join(&name)).ok())
+        .unwrap();
+    file.lock().unwrap();
+    file.set_len(0).unwrap();
+    writeln!(file, "4242 brewer").unwrap();
+    file
+}
+
+/// Runs `wsrelay0 On` while its port is locked, with `lock_timeout` and the flags in `args`.
+/// Returns the port, what happened, and how long it took.
+fn run_on_locked_port(test: &str, lock_timeout: u64, args: &[&str]) -> (String, Output, Duration) {
+    let port = format!("/dev/nbc_cli_test_{}_{}", std::process::id(), test);
+    let config = test_config(test, |config| config.replace("/dev/ttyUSB0", &port).replace("cli:\n", &format!("cli:\n  lock_timeout: {}\n", lock_timeout)));
+
+    let _lock = hold_lock(&port);
+    let start = Instant::now();
+    // Flags are picked out wherever they are, so they can follow the command
+    let output = cli(test, &config, None, &[&["wsrelay0", "On"], args].concat()).output().unwrap();
+    (port, output, start.elapsed())
+}
+
+#[test]
+fn locked_ports_fail_fast_with_no_wait() {
+    let (port, output, _) = run_on_locked_port("locked_ports_fail_fast", 5000, &["--no-wait"]);
+    let printed = printed(&output);
+    assert_printed(&printed, &format!("`{}` is in use by PID 4242 (brewer)", port));
+    assert!(!printed.contains("Gave up"), "`--no-wait` shouldn't have waited:\n{}", printed);
+    assert_eq!(output.status.code(), Some(1), "the command should have failed:\n{}", printed);
+}
+
+#[test]
+fn locked_ports_are_waited_on_until_the_timeout() {
+    let (port, output, elapsed) = run_on_locked_port("locked_ports_wait", 300, &[]);
+    let printed = printed(&output);
+    assert_printed(&printed, &format!("`{}` is in use by PID 4242 (brewer). Gave up after waiting 300ms", port));
+    assert!(elapsed >= Duration::from_millis(300), "gave up after only {:?}:\n{}", elapsed, printed);
+    assert_eq!(output.status.code(), Some(1), "the command should have failed:\n{}", printed);
+}